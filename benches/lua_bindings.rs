@@ -0,0 +1,144 @@
+//! Per-frame cost of Lua binding calls on the frame-buffered `LuaGameState`, as
+//! absolute times. Run with `cargo bench --bench lua_bindings --features scripting`.
+
+use std::time::{Duration, Instant};
+
+use mlua::Function;
+use revgame::scripting::{setup_lua_bindings, LuaGameState, LuaRuntime, ScriptLimits};
+
+/// Binding calls scripts make per frame
+const CALLS_PER_FRAME: usize = 10_000;
+
+/// Entities the calls are spread over
+const ENTITIES: u32 = 100;
+
+const WARMUP_FRAMES: u32 = 20;
+const FRAMES: u32 = 200;
+
+/// One frame of script work: reads and writes spread over every entity
+const MIXED_FRAME: &str = r#"
+return function(entities, calls)
+    local id = 1
+    for _ = 1, calls / 4 do
+        local x = get_position(id)
+        local current = get_health(id)
+        if is_key_pressed("KeyD") then
+            set_velocity(id, x + current, 0)
+        end
+        id = id % entities + 1
+    end
+end
+"#;
+
+/// One frame of calls to a single binding, `args(id)` giving its arguments
+const SINGLE_BINDING_FRAME: &str = r#"
+return function(entities, calls, binding, args)
+    local id = 1
+    for _ = 1, calls do
+        binding(args(id))
+        id = id % entities + 1
+    end
+end
+"#;
+
+/// Bindings timed on their own, with a Lua function building their arguments
+const BINDINGS: &[(&str, &str)] = &[
+    ("get_position", "function(id) return id end"),
+    ("get_health", "function(id) return id end"),
+    ("is_key_pressed", "function() return 'KeyD' end"),
+    ("set_velocity", "function(id) return id, 1, 0 end"),
+];
+
+fn main() {
+    let limits = ScriptLimits {
+        instructions_per_call: None,
+        memory_bytes: None,
+        debugger: false,
+    };
+    let runtime = LuaRuntime::with_limits(limits).expect("runtime");
+    {
+        let lua = runtime.lua();
+        setup_lua_bindings(&lua, LuaGameState::new()).expect("bindings");
+        lua.load(format!(
+            "for _ = 1, {} do spawn{{ sprite = {{ size = 10 }}, health = 100 }} end",
+            ENTITIES
+        ))
+        .exec()
+        .expect("spawn entities");
+    }
+    runtime.with_game_state(|state| {
+        state.take_commands();
+        state.set_key_pressed("KeyD", true);
+    });
+
+    let lua = runtime.lua();
+    // Like the plugin: positions change, a frame is published, scripts run and
+    // their commands are drained
+    let frame_time = |run: &dyn Fn()| {
+        measure(|tick| {
+            runtime.with_game_state(|state| {
+                for lua_id in 1..=ENTITIES {
+                    state.update_entity_position(lua_id, tick, 0.0);
+                }
+                state.publish_frame();
+            });
+            run();
+            runtime.with_game_state(LuaGameState::take_commands);
+        })
+    };
+
+    println!(
+        "{} binding calls per frame, {} frames",
+        CALLS_PER_FRAME, FRAMES
+    );
+
+    let mixed: Function = lua.load(MIXED_FRAME).eval().expect("mixed frame");
+    report(
+        "mixed",
+        frame_time(&|| {
+            mixed
+                .call::<()>((ENTITIES, CALLS_PER_FRAME))
+                .expect("frame")
+        }),
+    );
+
+    let single: Function = lua
+        .load(SINGLE_BINDING_FRAME)
+        .eval()
+        .expect("single binding frame");
+    for (name, args) in BINDINGS {
+        let binding: Function = lua.globals().get(*name).expect("binding");
+        let args: Function = lua.load(*args).eval().expect("arguments");
+        report(
+            name,
+            frame_time(&|| {
+                single
+                    .call::<()>((ENTITIES, CALLS_PER_FRAME, binding.clone(), args.clone()))
+                    .expect("frame")
+            }),
+        );
+    }
+}
+
+/// Total time of `FRAMES` frames after a warmup
+fn measure(mut frame: impl FnMut(f32)) -> Duration {
+    for tick in 0..WARMUP_FRAMES {
+        frame(tick as f32);
+    }
+    let start = Instant::now();
+    for tick in 0..FRAMES {
+        frame(tick as f32);
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, total: Duration) {
+    let per_frame = total / FRAMES;
+    let per_call = per_frame.as_nanos() as f64 / CALLS_PER_FRAME as f64;
+    println!(
+        "{:>16}: {:>8.3} ms/frame, {:>6.1} ns/call",
+        name,
+        per_frame.as_secs_f64() * 1000.0,
+        per_call
+    );
+}
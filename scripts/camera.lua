@@ -7,10 +7,12 @@ Camera = {
     deadzone = 0
 }
 
--- Updates camera to follow target
--- Called every frame when in game
-function update_camera(target_id)
-    local dt = get_delta_time()
+-- Moves the camera toward the player, after everything else has moved
+register_system("camera_follow", "camera", function(dt)
+    local target_id = get_player()
+    if not target_id then
+        return
+    end
 
     -- Get target position
     local tx, ty = get_position(target_id)
@@ -34,4 +36,4 @@ function update_camera(target_id)
     local new_y = cy + dy * lerp_factor
 
     set_camera_position(new_x, new_y)
-end
+end)
@@ -0,0 +1,41 @@
+-- Enemy behavior, attached to each enemy entity with spawn{ script = "enemy" }
+-- Every enemy gets its own `self`; hot-reloading this file updates all of them
+
+local Enemy = {
+    speed = 60,
+    wander_radius = 150,
+    turn_interval = 2,
+}
+
+function Enemy.on_spawn(self)
+    self.home_x, self.home_y = get_position(self.id)
+    self.timer = 0
+    self:pick_direction()
+end
+
+function Enemy.on_update(self, dt)
+    self.timer = self.timer - dt
+    local x, y = get_position(self.id)
+    local dx, dy = x - self.home_x, y - self.home_y
+    if dx * dx + dy * dy > self.wander_radius * self.wander_radius then
+        -- Head back home
+        local length = math.sqrt(dx * dx + dy * dy)
+        self.dir_x, self.dir_y = -dx / length, -dy / length
+        self.timer = self.turn_interval
+    elseif self.timer <= 0 then
+        self:pick_direction()
+    end
+    set_velocity(self.id, self.dir_x * self.speed, self.dir_y * self.speed)
+end
+
+function Enemy.on_despawn(self)
+    log("Enemy " .. tostring(self.id) .. " despawned")
+end
+
+function Enemy.pick_direction(self)
+    local angle = math.random() * 2 * math.pi
+    self.dir_x, self.dir_y = math.cos(angle), math.sin(angle)
+    self.timer = self.turn_interval
+end
+
+return Enemy
@@ -6,57 +6,108 @@ Healthbar = {
     height = 6,
     offset_y = 35,
     bg_color = { r = 0.2, g = 0.2, b = 0.2 },
-    fg_color = { r = 0.2, g = 0.8, b = 0.2 },
+    fg_color = { r = 0.2, g = 0.8, b = 0.2 }
+}
+
+-- Runtime state survives hot reload (see persist() in the scripting runtime)
+HealthbarState = persist("healthbar", {
     bg_id = nil,
     fg_id = nil,
+    player_id = nil,
+    ratio = 1,
     spawned = false
-}
+})
+
+-- Fraction of health left, clamped to 0..1
+local function health_ratio(current, max)
+    if max <= 0 then max = 1 end
+    local ratio = current / max
+    if ratio < 0 then ratio = 0 end
+    if ratio > 1 then ratio = 1 end
+    return ratio
+end
+
+-- Resize the foreground bar to the current ratio
+local function resize_foreground()
+    local fg_width = Healthbar.width * HealthbarState.ratio
+    set_sprite_size(HealthbarState.fg_id, fg_width, Healthbar.height)
+end
 
 -- Spawn the healthbar sprites (called once after player spawns)
 function spawn_healthbar(player_id)
     local px, py = get_position(player_id)
 
     -- Background bar (full width, dark gray)
-    Healthbar.bg_id = spawn_sprite(
+    HealthbarState.bg_id = spawn_sprite(
         Healthbar.width, Healthbar.height,
         Healthbar.bg_color.r, Healthbar.bg_color.g, Healthbar.bg_color.b,
         px, py + Healthbar.offset_y, 2
     )
 
     -- Foreground bar (resized based on health ratio)
-    Healthbar.fg_id = spawn_sprite(
+    HealthbarState.fg_id = spawn_sprite(
         Healthbar.width, Healthbar.height,
         Healthbar.fg_color.r, Healthbar.fg_color.g, Healthbar.fg_color.b,
         px, py + Healthbar.offset_y, 3
     )
 
-    Healthbar.spawned = true
+    HealthbarState.player_id = player_id
+    HealthbarState.ratio = health_ratio(get_health(player_id))
+    HealthbarState.spawned = true
+    resize_foreground()
     log("Healthbar spawned")
 end
 
--- Update healthbar position and width every frame
-function update_healthbar(player_id)
-    if not Healthbar.spawned then
-        spawn_healthbar(player_id)
+-- Respawn the bars after a reload so color changes show up
+on_after_reload(function()
+    if HealthbarState.spawned then
+        despawn(HealthbarState.bg_id)
+        despawn(HealthbarState.fg_id)
+        HealthbarState.spawned = false
+    end
+end)
+
+-- Resize the bar only when the player's health actually changes
+on("health_changed", function(event)
+    if not HealthbarState.spawned or event.id ~= HealthbarState.player_id then
         return
     end
+    HealthbarState.ratio = health_ratio(event.current, event.max)
+    resize_foreground()
+end)
 
-    local px, py = get_position(player_id)
+-- Keep the healthbar above the player, once the player has moved this frame
+register_system("healthbar_follow", "late", function()
+    local player_id = get_player()
+    if not player_id then
+        return
+    end
 
-    -- Get current health
-    local current, max = get_health(player_id)
-    if max <= 0 then max = 1 end
-    local ratio = current / max
-    if ratio < 0 then ratio = 0 end
-    if ratio > 1 then ratio = 1 end
+    if HealthbarState.spawned
+        and (HealthbarState.player_id ~= player_id or not is_alive(HealthbarState.fg_id)) then
+        -- The bars belong to an earlier player; start over
+        despawn(HealthbarState.bg_id)
+        despawn(HealthbarState.fg_id)
+        HealthbarState.spawned = false
+    end
+
+    if not HealthbarState.spawned then
+        -- Wait until the player's health has been synced from the game
+        local _, max = get_health(player_id)
+        if max > 0 then
+            spawn_healthbar(player_id)
+        end
+        return
+    end
+
+    local px, py = get_position(player_id)
 
     -- Position background bar above player
     local bar_y = py + Healthbar.offset_y
-    set_position(Healthbar.bg_id, px, bar_y)
+    set_position(HealthbarState.bg_id, px, bar_y)
 
-    -- Foreground bar: shrink width and shift left to keep left-aligned
-    local fg_width = Healthbar.width * ratio
+    -- Foreground bar: shift left by the missing width to keep it left-aligned
+    local fg_width = Healthbar.width * HealthbarState.ratio
     local offset_x = (Healthbar.width - fg_width) / 2
-    set_position(Healthbar.fg_id, px - offset_x, bar_y)
-    set_sprite_size(Healthbar.fg_id, fg_width, Healthbar.height)
-end
+    set_position(HealthbarState.fg_id, px - offset_x, bar_y)
+end)
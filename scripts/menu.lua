@@ -0,0 +1,15 @@
+-- Main menu: Escape leaves the game for the menu, Enter starts a new game.
+-- Script systems run in every state, so check get_game_state() first.
+
+function on_enter_main_menu()
+    log("Main menu: press Enter to start")
+end
+
+register_system("menu_keys", "input", function()
+    local state = get_game_state()
+    if state == "InGame" and is_key_just_pressed("Escape") then
+        set_game_state("MainMenu")
+    elseif state == "MainMenu" and is_key_just_pressed("Enter") then
+        set_game_state("InGame")
+    end
+end)
@@ -12,22 +12,23 @@ Player = {
 -- Returns the Lua entity ID
 function spawn_player()
     log("Spawning player...")
-    local id = spawn_sprite(
-        Player.size, Player.size,
-        Player.color.r, Player.color.g, Player.color.b,
-        0, 0, 0
-    )
-    mark_as_player(id)
-    mark_as_camera_target(id)
-    set_health(id, Player.max_health)
+    local id = spawn{
+        sprite = { size = Player.size, color = Player.color },
+        transform = { x = 0, y = 0, z = 0 },
+        tags = { "player", "camera_target" },
+        health = Player.max_health,
+    }
     log("Player spawned with ID: " .. tostring(id))
     return id
 end
 
--- Updates player based on input
--- Called every frame when in game
-function update_player(player_id)
-    local dt = get_delta_time()
+-- Moves the player based on input
+register_system("player_movement", "simulation", function(dt)
+    local player_id = get_player()
+    if not player_id then
+        return
+    end
+
     local vx, vy = 0, 0
 
     -- Read input
@@ -56,4 +57,4 @@ function update_player(player_id)
     local new_x = x + vx * Player.speed * dt
     local new_y = y + vy * Player.speed * dt
     set_position(player_id, new_x, new_y)
-end
+end)
@@ -0,0 +1,288 @@
+---@meta revgame
+-- Generated from LUA_BINDINGS in src/scripting/api.rs; do not edit.
+-- Regenerate with `revgame lua-api`.
+
+---`{r, g, b[, a]}`, `{r=, g=, b=[, a=]}` or a `"#rrggbb[aa]"` string; components are 0..1
+---@alias Color number[]|{r: number, g: number, b: number, a: number?}|string
+
+---Sprite part of a `spawn` table
+---@class SpriteSpec
+---@field size? number|number[]|{width: number, height: number}
+---@field color? Color
+---@field image? string
+---@field flip_x? boolean
+---@field flip_y? boolean
+
+---Placement part of a `spawn` table; `rotation` is in radians
+---@class TransformSpec
+---@field x? number
+---@field y? number
+---@field z? number
+---@field rotation? number
+---@field scale? number|number[]|{x: number, y: number}
+
+---Everything `spawn` accepts; unknown fields are an error
+---@class SpawnSpec
+---@field sprite? SpriteSpec
+---@field transform? TransformSpec
+---@field tags? ("player"|"camera_target"|"world_element")[]
+---@field health? number|{current: number?, max: number}
+---@field velocity? number[]|{x: number, y: number}
+---@field script? string
+
+---The `self` table of a script attached with `spawn{ script = ... }`; missing fields fall back to the table the script returned
+---@class ScriptInstance
+---@field id integer
+
+---Payload of the `command_failed` event, sent the frame after a queued command (`set_health`, `set_position`, ...) could not be applied
+---@class CommandFailure
+---@field command string
+---@field id? integer
+---@field message string
+
+---Loads a module from the scripts directory (`ai.enemy` is `ai/enemy.lua`) and returns what it returned
+---@param name string
+---@return any module
+function require(name) end
+
+---Returns the state table stored under `key`, created from `defaults` the first time; survives hot reload
+---@param key string
+---@param defaults? table
+---@return table state
+function persist(key, defaults) end
+
+---Runs `func(module)` before the loading script is hot-reloaded; if it fails, the old version stays loaded
+---@param func fun(module: string)
+function on_before_reload(func) end
+
+---Runs `func(module)` after the loading script was hot-reloaded
+---@param func fun(module: string)
+function on_after_reload(func) end
+
+---Subscribes `func` to an event; the engine sends `health_changed` and `command_failed` (a `CommandFailure`)
+---@param event string
+---@param func fun(payload: any)
+function on(event, func) end
+
+---Unsubscribes `func`; returns whether it was subscribed
+---@param event string
+---@param func function
+---@return boolean removed
+function off(event, func) end
+
+---Queues an event for handlers and Rust; delivered once per frame
+---@param event string
+---@param payload? any
+function emit(event, payload) end
+
+---Runs `func(dt)` every frame in a stage (`input`, `simulation`, `late` or `camera`); lower `order` runs first
+---@param name string
+---@param stage "input"|"simulation"|"late"|"camera"
+---@param func fun(dt: number)
+---@param order? integer
+function register_system(name, stage, func, order) end
+
+---Removes a system; returns whether it existed
+---@param name string
+---@return boolean removed
+function unregister_system(name) end
+
+---Runs `func` as a coroutine starting next frame; cancelled when `owner` despawns
+---@param func function
+---@param owner? integer
+---@return integer task_id
+function spawn_task(func, owner) end
+
+---Stops a task; returns whether it was still running
+---@param task_id integer
+---@return boolean cancelled
+function cancel_task(task_id) end
+
+---Inside a task: pauses for `seconds` of game time
+---@param seconds? number
+function wait(seconds) end
+
+---Inside a task: pauses for `frames` frames (default 1)
+---@param frames? integer
+function wait_frames(frames) end
+
+---Inside a task: pauses until `predicate()` returns true, checked once per frame
+---@param predicate fun(): boolean
+function wait_until(predicate) end
+
+---Spawns an entity described by a table; errors name the invalid field
+---@param spec SpawnSpec
+---@return integer entity_id
+function spawn(spec) end
+
+---Spawns a colored rectangle (prefer `spawn`)
+---@param width number
+---@param height number
+---@param r number
+---@param g number
+---@param b number
+---@param x number
+---@param y number
+---@param z number
+---@return integer entity_id
+function spawn_sprite(width, height, r, g, b, x, y, z) end
+
+---Despawns an entity and its children; returns false if it was not alive
+---@param entity_id integer
+---@return boolean despawned
+function despawn(entity_id) end
+
+---Whether an entity has been spawned and not despawned
+---@param entity_id integer
+---@return boolean alive
+function is_alive(entity_id) end
+
+---The `self` table of the script attached to an entity
+---@param entity_id integer
+---@return ScriptInstance? instance
+function get_instance(entity_id) end
+
+---Position at the start of the frame (0, 0 if unknown)
+---@param entity_id integer
+---@return number x
+---@return number y
+function get_position(entity_id) end
+
+---Moves an entity
+---@param entity_id integer
+---@param x number
+---@param y number
+function set_position(entity_id, x, y) end
+
+---Sets an entity's velocity in pixels per second
+---@param entity_id integer
+---@param vx number
+---@param vy number
+function set_velocity(entity_id, vx, vy) end
+
+---Current and max health at the start of the frame (0, 0 if the entity has none)
+---@param entity_id integer
+---@return number current
+---@return number max
+function get_health(entity_id) end
+
+---Sets current health, clamped to 0..max
+---@param entity_id integer
+---@param health number
+function set_health(entity_id, health) end
+
+---Resizes an entity's sprite
+---@param entity_id integer
+---@param width number
+---@param height number
+function set_sprite_size(entity_id, width, height) end
+
+---Makes an entity the player (prefer the `player` spawn tag)
+---@param entity_id integer
+function mark_as_player(entity_id) end
+
+---The player entity, while it is alive
+---@return integer? entity_id
+function get_player() end
+
+---Tags an entity for the camera to follow (prefer the `camera_target` spawn tag)
+---@param entity_id integer
+function mark_as_camera_target(entity_id) end
+
+---Tags an entity as part of the world (prefer the `world_element` spawn tag)
+---@param entity_id integer
+function mark_as_world_element(entity_id) end
+
+---Camera position at the start of the frame
+---@return number x
+---@return number y
+function get_camera_position() end
+
+---Moves the camera
+---@param x number
+---@param y number
+function set_camera_position(x, y) end
+
+---Seconds since the last frame
+---@return number dt
+function get_delta_time() end
+
+---The game state (`MainMenu`, `InGame`, ...); entering a state calls the global `on_enter_<state>()` if defined (e.g. `on_enter_main_menu`), leaving it `on_exit_<state>()`
+---@return string? state
+function get_game_state() end
+
+---Switches to another game state at the end of the frame; entering `InGame` again restarts every script with fresh `persist` state
+---@param state string
+function set_game_state(state) end
+
+---Whether a key is held; accepts `KeyW` or `W`, `ArrowUp` or `Up`, `ShiftLeft` or `Shift`
+---@param key string
+---@return boolean pressed
+function is_key_pressed(key) end
+
+---Whether a key went down this frame
+---@param key string
+---@return boolean pressed
+function is_key_just_pressed(key) end
+
+---Whether a key went up this frame
+---@param key string
+---@return boolean released
+function is_key_just_released(key) end
+
+---Whether a mouse button (`Left`, `Right`, `Middle`) is held
+---@param button string
+---@return boolean pressed
+function is_mouse_pressed(button) end
+
+---Whether a mouse button went down this frame
+---@param button string
+---@return boolean pressed
+function is_mouse_just_pressed(button) end
+
+---Whether a mouse button went up this frame
+---@param button string
+---@return boolean released
+function is_mouse_just_released(button) end
+
+---Cursor position in world coordinates; nil outside the window
+---@return number? x
+---@return number? y
+function get_cursor_position() end
+
+---Cursor position in window coordinates (origin top-left); nil outside the window
+---@return number? x
+---@return number? y
+function get_cursor_screen_position() end
+
+---Number of connected gamepads
+---@return integer count
+function get_gamepad_count() end
+
+---Whether a gamepad button (`South` or `A`, `DPadUp`, ...) is held; gamepads are indexed from 1
+---@param gamepad integer
+---@param button string
+---@return boolean pressed
+function is_gamepad_pressed(gamepad, button) end
+
+---Whether a gamepad button went down this frame
+---@param gamepad integer
+---@param button string
+---@return boolean pressed
+function is_gamepad_just_pressed(gamepad, button) end
+
+---Whether a gamepad button went up this frame
+---@param gamepad integer
+---@param button string
+---@return boolean released
+function is_gamepad_just_released(gamepad, button) end
+
+---Axis value (`LeftStickX`, `RightStickY`, ...); 0 for unknown gamepads
+---@param gamepad integer
+---@param axis string
+---@return number value
+function get_gamepad_axis(gamepad, axis) end
+
+---Writes a message to the game log
+---@param message string
+function log(message) end
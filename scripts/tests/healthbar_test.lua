@@ -0,0 +1,42 @@
+-- Healthbar behaviour, run with `revgame test-scripts`
+
+local function spawned_bars()
+    step(2)
+    return HealthbarState.bg_id, HealthbarState.fg_id
+end
+
+test("healthbar spawns above the player", function()
+    local id = spawn_player()
+    local bg, fg = spawned_bars()
+    expect_eq(HealthbarState.player_id, id, "bar owner")
+    expect_near(entity(bg).y, Healthbar.offset_y, 0.01, "background y")
+    expect_eq(entity(fg).width, Healthbar.width, "full health bar width")
+end)
+
+test("healthbar follows the player", function()
+    local id = spawn_player()
+    local bg = spawned_bars()
+    press("KeyD")
+    step(30)
+    -- The bar catches up with the player's move on the next frame
+    release("KeyD")
+    step()
+    expect_near(entity(bg).x, entity(id).x, 0.01, "background x")
+end)
+
+test("set_health shrinks the foreground bar", function()
+    local id = spawn_player()
+    local _, fg = spawned_bars()
+    set_health(id, Player.max_health / 2)
+    step(2)
+    expect_eq(entity(id).health, Player.max_health / 2, "player health")
+    expect_near(entity(fg).width, Healthbar.width / 2, 0.01, "bar width")
+end)
+
+test("health is clamped to the player's max", function()
+    local id = spawn_player()
+    step()
+    set_health(id, Player.max_health * 2)
+    step()
+    expect_eq(entity(id).health, Player.max_health, "health")
+end)
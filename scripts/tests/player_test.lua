@@ -0,0 +1,41 @@
+-- Player movement, run with `revgame test-scripts`
+
+test("player moves right while D is held", function()
+    local id = spawn_player()
+    step()
+    press("KeyD")
+    step(30)
+    local player = entity(id)
+    expect_near(player.x, Player.speed * 30 / 60, 0.01, "x after half a second")
+    expect_near(player.y, 0, 0.01, "y")
+end)
+
+test("player stops when the key is released", function()
+    local id = spawn_player()
+    press("KeyW")
+    step(10)
+    release("KeyW")
+    step()
+    local y = entity(id).y
+    step(10)
+    expect_near(entity(id).y, y, 0.01, "y after releasing W")
+end)
+
+test("diagonal movement is normalized", function()
+    local id = spawn_player()
+    press("KeyD")
+    press("KeyW")
+    step(60)
+    local player = entity(id)
+    local distance = math.sqrt(player.x * player.x + player.y * player.y)
+    expect_near(distance, Player.speed, 1, "distance after one second")
+end)
+
+test("spawn_player tags the player with full health", function()
+    local id = spawn_player()
+    step()
+    expect_eq(get_player(), id, "get_player()")
+    local player = entity(id)
+    expect_eq(player.health, Player.max_health, "health")
+    expect_eq(player.max_health, Player.max_health, "max health")
+end)
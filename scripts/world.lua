@@ -6,25 +6,27 @@ World = {
     ground_color = { r = 0.176, g = 0.353, b = 0.153 },  -- #2d5a27 dark green
     grid_spacing = 200,
     grid_size = 20,
-    grid_color = { r = 0.333, g = 0.333, b = 0.333 }  -- #555555 gray
+    grid_color = { r = 0.333, g = 0.333, b = 0.333 },  -- #555555 gray
+    enemy_count = 5,
+    enemy_size = 30,
+    enemy_color = "#c0392b"
 }
 
--- Store spawned world element IDs for cleanup
-world_elements = {}
+-- Spawned world element IDs survive hot reload (see persist())
+WorldState = persist("world", { elements = {} })
 
 -- Spawns the world (ground and grid markers)
 function spawn_world()
     log("Spawning world...")
-    world_elements = {}
+    WorldState.elements = {}
 
     -- Spawn ground
-    local ground_id = spawn_sprite(
-        World.ground_size, World.ground_size,
-        World.ground_color.r, World.ground_color.g, World.ground_color.b,
-        0, 0, -1
-    )
-    mark_as_world_element(ground_id)
-    table.insert(world_elements, ground_id)
+    local ground_id = spawn{
+        sprite = { size = World.ground_size, color = World.ground_color },
+        transform = { z = -1 },
+        tags = { "world_element" },
+    }
+    table.insert(WorldState.elements, ground_id)
 
     -- Spawn grid markers
     local half = World.ground_size / 2
@@ -33,22 +35,43 @@ function spawn_world()
         for y = -half, half, World.grid_spacing do
             -- Skip center (where player spawns)
             if not (x == 0 and y == 0) then
-                local marker_id = spawn_sprite(
-                    World.grid_size, World.grid_size,
-                    World.grid_color.r, World.grid_color.g, World.grid_color.b,
-                    x, y, -0.5
-                )
-                mark_as_world_element(marker_id)
-                table.insert(world_elements, marker_id)
+                local marker_id = spawn{
+                    sprite = { size = World.grid_size, color = World.grid_color },
+                    transform = { x = x, y = y, z = -0.5 },
+                    tags = { "world_element" },
+                }
+                table.insert(WorldState.elements, marker_id)
                 count = count + 1
             end
         end
     end
 
-    log("World spawned with " .. tostring(count) .. " grid markers")
+    -- Spawn enemies in a ring around the center; each runs its own enemy.lua instance
+    for i = 1, World.enemy_count do
+        local angle = (i / World.enemy_count) * 2 * math.pi
+        local enemy_id = spawn{
+            sprite = { size = World.enemy_size, color = World.enemy_color },
+            transform = { x = math.cos(angle) * 400, y = math.sin(angle) * 400, z = 0.5 },
+            script = "enemy",
+        }
+        table.insert(WorldState.elements, enemy_id)
+    end
+
+    log("World spawned with " .. tostring(count) .. " grid markers and "
+        .. tostring(World.enemy_count) .. " enemies")
 end
 
 -- Returns the list of world element IDs (for cleanup)
 function get_world_elements()
-    return world_elements
+    return WorldState.elements
 end
+
+-- Rebuild an already spawned world so layout changes show up
+on_after_reload(function()
+    if #WorldState.elements > 0 then
+        for _, id in ipairs(WorldState.elements) do
+            despawn(id)
+        end
+        spawn_world()
+    end
+end)
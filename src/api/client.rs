@@ -0,0 +1,242 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+use super::error::ApiError;
+use super::types::*;
+use crate::config::{BackendConfig, GameConfig};
+
+/// Blocking HTTP client for the RevGame backend
+pub struct ApiClient {
+    base_url: String,
+    agent: ureq::Agent,
+    token: Option<String>,
+}
+
+impl ApiClient {
+    /// Creates a client for the given base URL with the default timeout
+    pub fn new(base_url: &str) -> Result<Self, ApiError> {
+        Self::with_config(&BackendConfig {
+            url: base_url.to_string(),
+            ..BackendConfig::default()
+        })
+    }
+
+    /// Creates a client from explicit connection settings
+    pub fn with_config(config: &BackendConfig) -> Result<Self, ApiError> {
+        let base_url = config.url.trim_end_matches('/').to_string();
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            return Err(ApiError::Config(format!(
+                "backend url must start with http:// or https://, got {:?}",
+                config.url
+            )));
+        }
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build();
+
+        Ok(Self {
+            base_url,
+            agent,
+            token: None,
+        })
+    }
+
+    /// Creates a client from the `backend` section of config.json,
+    /// including `REVGAME_BACKEND_*` environment overrides
+    pub fn from_config() -> Result<Self, ApiError> {
+        let config = GameConfig::load().map_err(|e| ApiError::Config(e.to_string()))?;
+        Self::with_config(&config.backend)
+    }
+
+    /// Creates a client from the `backend` section of the given config file
+    pub fn from_config_file(path: &Path) -> Result<Self, ApiError> {
+        let config = GameConfig::from_file(path).map_err(|e| ApiError::Config(e.to_string()))?;
+        Self::with_config(&config.backend)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Sets the bearer token sent with every request
+    pub fn set_token(&mut self, token: Option<String>) {
+        self.token = token;
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Starts a new session; the returned token is used for later requests
+    pub fn create_session(&mut self, request: &CreateSessionRequest) -> Result<Session, ApiError> {
+        let session: Session = self.send("POST", "/sessions", request)?;
+        self.token = Some(session.token.clone());
+        Ok(session)
+    }
+
+    /// Fetches a session by id
+    pub fn get_session(&self, session_id: &str) -> Result<Session, ApiError> {
+        self.get(&format!("/sessions/{}", path_segment(session_id)))
+    }
+
+    /// Ends a session and forgets its token
+    pub fn end_session(&mut self, session_id: &str) -> Result<(), ApiError> {
+        self.request("DELETE", &format!("/sessions/{}", path_segment(session_id)))
+            .call()?;
+        self.token = None;
+        Ok(())
+    }
+
+    /// Fetches a player profile
+    pub fn get_profile(&self, profile_id: &str) -> Result<Profile, ApiError> {
+        self.get(&format!("/profiles/{}", path_segment(profile_id)))
+    }
+
+    /// Updates a player profile and returns the stored version
+    pub fn update_profile(
+        &self,
+        profile_id: &str,
+        request: &UpdateProfileRequest,
+    ) -> Result<Profile, ApiError> {
+        let path = format!("/profiles/{}", path_segment(profile_id));
+        self.send("PATCH", &path, request)
+    }
+
+    /// Submits the result of a finished game
+    pub fn submit_result(&self, request: &SubmitResultRequest) -> Result<GameResult, ApiError> {
+        self.send("POST", "/results", request)
+    }
+
+    /// Lists recorded results for a profile, newest first
+    pub fn list_results(&self, profile_id: &str) -> Result<Vec<GameResult>, ApiError> {
+        self.get(&format!("/profiles/{}/results", path_segment(profile_id)))
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}{}", self.base_url, path))
+            .set("Accept", "application/json");
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ApiError> {
+        decode(self.request("GET", path).call()?)
+    }
+
+    fn send<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ApiError> {
+        decode(self.request(method, path).send_json(body)?)
+    }
+}
+
+/// Percent-encodes an id for use as one path segment, so ids containing `/`,
+/// `?` or `#` cannot address another endpoint
+fn path_segment(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn decode<T: DeserializeOwned>(response: ureq::Response) -> Result<T, ApiError> {
+    let body = response
+        .into_string()
+        .map_err(|e| ApiError::Transport(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| ApiError::Decode(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Local stand-in for the backend: answers one request with `status` and
+    /// `body`, and returns the request line it got
+    fn serve_once(status: &str, body: &str) -> (ApiClient, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim_end().is_empty() {
+                    break;
+                }
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            request_line.trim_end().to_string()
+        });
+        (ApiClient::new(&url).unwrap(), server)
+    }
+
+    #[test]
+    fn success_response_is_decoded() {
+        let (client, server) = serve_once(
+            "200 OK",
+            r#"{"id": "p1", "display_name": "Rev", "level": 3}"#,
+        );
+        let profile = client.get_profile("p1").unwrap();
+        assert_eq!(profile.display_name, "Rev");
+        assert_eq!(profile.level, 3);
+        assert_eq!(server.join().unwrap(), "GET /profiles/p1 HTTP/1.1");
+    }
+
+    #[test]
+    fn error_status_maps_to_status_error() {
+        let (client, server) = serve_once("404 Not Found", r#"{"error": "no such profile"}"#);
+        match client.get_profile("missing") {
+            Err(ApiError::Status { code, message }) => {
+                assert_eq!(code, 404);
+                assert_eq!(message, "no such profile");
+            }
+            other => panic!("expected a status error, got {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn bad_body_maps_to_decode_error() {
+        let (client, server) = serve_once("200 OK", "not json");
+        assert!(matches!(client.get_profile("p1"), Err(ApiError::Decode(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn ids_are_percent_encoded_into_the_path() {
+        let (client, server) = serve_once("404 Not Found", "{}");
+        let _ = client.list_results("a/b?c#d e");
+        assert_eq!(
+            server.join().unwrap(),
+            "GET /profiles/a%2Fb%3Fc%23d%20e/results HTTP/1.1"
+        );
+    }
+}
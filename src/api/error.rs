@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Errors returned by the backend API client
+#[derive(Debug)]
+pub enum ApiError {
+    /// The client configuration could not be read or is invalid
+    Config(String),
+    /// The backend answered with a non-success status code
+    Status { code: u16, message: String },
+    /// The request never got a response (connection refused, timeout, DNS, ...)
+    Transport(String),
+    /// The response body could not be decoded into the expected type
+    Decode(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Config(msg) => write!(f, "invalid API configuration: {}", msg),
+            ApiError::Status { code, message } => {
+                write!(f, "backend returned HTTP {}: {}", code, message)
+            }
+            ApiError::Transport(msg) => write!(f, "request failed: {}", msg),
+            ApiError::Decode(msg) => write!(f, "failed to decode response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<ureq::Error> for ApiError {
+    fn from(err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, response) => {
+                // Prefer the backend's own error message when it sends one
+                let body = response.into_string().unwrap_or_default();
+                let message = serde_json::from_str::<ErrorBody>(&body)
+                    .map(|b| b.error)
+                    .unwrap_or(body);
+                ApiError::Status { code, message }
+            }
+            ureq::Error::Transport(transport) => ApiError::Transport(transport.to_string()),
+        }
+    }
+}
+
+/// Error payload shape used by the backend (`{"error": "..."}`)
+#[derive(serde::Deserialize)]
+struct ErrorBody {
+    error: String,
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// Request body for starting a new game session
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreateSessionRequest {
+    pub profile_id: String,
+    pub client_version: String,
+}
+
+impl CreateSessionRequest {
+    /// Creates a session request tagged with this crate's version
+    pub fn new(profile_id: impl Into<String>) -> Self {
+        Self {
+            profile_id: profile_id.into(),
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// An active game session issued by the backend
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub profile_id: String,
+    /// Bearer token used to authenticate follow-up requests
+    pub token: String,
+    /// RFC 3339 timestamp
+    pub started_at: String,
+    /// RFC 3339 timestamp, if the session expires
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+/// A player profile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Profile {
+    pub id: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub level: u32,
+    #[serde(default)]
+    pub experience: u64,
+    #[serde(default)]
+    pub games_played: u32,
+}
+
+/// Request body for updating a profile; unset fields are left unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UpdateProfileRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+/// How a game ended
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOutcome {
+    Win,
+    Loss,
+    Draw,
+    Abandoned,
+}
+
+/// Request body for submitting the result of a finished game
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SubmitResultRequest {
+    pub session_id: String,
+    pub outcome: GameOutcome,
+    pub score: i64,
+    pub duration_secs: f32,
+}
+
+/// A game result as stored by the backend
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GameResult {
+    pub id: String,
+    pub session_id: String,
+    pub profile_id: String,
+    pub outcome: GameOutcome,
+    pub score: i64,
+    pub duration_secs: f32,
+    /// RFC 3339 timestamp
+    pub recorded_at: String,
+}
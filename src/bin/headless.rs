@@ -0,0 +1,232 @@
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use revgame::game::{self, Health, OrbiterAgent, Player, Stamina, Velocity, WorldElement};
+use revgame::{GameConfig, GameState};
+
+const USAGE: &str = "usage: revgame-headless [--ticks N] [--dt SECONDS] [--mode lua|native] \
+[--inputs FILE] [--output FILE] [--config FILE] [--set KEY=VALUE]...";
+
+/// Which gameplay implementation to simulate
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Lua,
+    Native,
+}
+
+/// A scripted input change applied right before the given tick runs
+#[derive(Debug, Deserialize)]
+struct InputEvent {
+    tick: u64,
+    #[serde(default)]
+    press: Vec<KeyCode>,
+    #[serde(default)]
+    release: Vec<KeyCode>,
+}
+
+struct Options {
+    ticks: u64,
+    dt: f32,
+    mode: Mode,
+    inputs: Vec<InputEvent>,
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let (mut config, args) = match GameConfig::load_with_args(std::env::args().skip(1)) {
+        Ok(loaded) => loaded,
+        Err(e) => fail(&e.to_string()),
+    };
+    let options = parse_options(args).unwrap_or_else(|e| fail(&e));
+
+    // Nothing to hot reload during a fixed-length run
+    config.scripting.hot_reload = false;
+
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            options.dt,
+        )))
+        .insert_resource(config)
+        .init_resource::<ButtonInput<KeyCode>>()
+        .init_state::<GameState>()
+        .add_systems(OnEnter(GameState::Loading), setup);
+
+    match options.mode {
+        Mode::Lua => app.add_plugins(game::ScriptedGameplayPlugin),
+        Mode::Native => app.add_plugins(game::NativeGameplayPlugin),
+    };
+
+    app.finish();
+    app.cleanup();
+
+    // Run until the Loading -> InGame transition has happened
+    let mut warmup = 0;
+    while *app.world().resource::<State<GameState>>().get() != GameState::InGame {
+        warmup += 1;
+        if warmup > 10 {
+            fail("game never reached the InGame state");
+        }
+        app.update();
+    }
+
+    for tick in 0..options.ticks {
+        {
+            let mut keyboard = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+            keyboard.clear();
+            for event in options.inputs.iter().filter(|e| e.tick == tick) {
+                for key in &event.release {
+                    keyboard.release(*key);
+                }
+                for key in &event.press {
+                    keyboard.press(*key);
+                }
+            }
+        }
+        app.update();
+    }
+    if options.mode == Mode::Lua {
+        game::write_lua_profile_trace(app.world());
+    }
+
+    let state = dump_world(app.world_mut(), &options);
+    let text = serde_json::to_string_pretty(&state).expect("world state is valid JSON");
+    match &options.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, text) {
+                fail(&format!("cannot write {}: {}", path.display(), e));
+            }
+        }
+        None => println!("{}", text),
+    }
+}
+
+fn setup(mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
+    commands.spawn(Camera2d);
+    next_state.set(GameState::InGame);
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        ticks: 600,
+        dt: 1.0 / 60.0,
+        mode: Mode::Lua,
+        inputs: Vec::new(),
+        output: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for `{}`", arg))
+        };
+        match arg.as_str() {
+            "--ticks" => {
+                options.ticks = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid --ticks: {}", e))?
+            }
+            "--dt" => {
+                options.dt = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid --dt: {}", e))?;
+                if !(options.dt.is_finite() && options.dt > 0.0) {
+                    return Err("--dt must be greater than 0".to_string());
+                }
+            }
+            "--mode" => {
+                options.mode = match value()?.as_str() {
+                    "lua" => Mode::Lua,
+                    "native" => Mode::Native,
+                    other => {
+                        return Err(format!("unknown mode `{}` (expected lua or native)", other))
+                    }
+                }
+            }
+            "--inputs" => {
+                let path = value()?;
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("cannot read {}: {}", path, e))?;
+                options.inputs = serde_json::from_str(&content)
+                    .map_err(|e| format!("invalid inputs in {}: {}", path, e))?;
+            }
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("unexpected argument `{}`\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Collects the gameplay-relevant parts of the world as JSON
+fn dump_world(world: &mut World, options: &Options) -> serde_json::Value {
+    let elapsed = world.resource::<Time>().elapsed_secs();
+
+    let mut players = world.query_filtered::<(
+        &Transform,
+        Option<&Velocity>,
+        Option<&Stamina>,
+        Option<&Health>,
+    ), With<Player>>();
+    let players: Vec<_> = players
+        .iter(world)
+        .map(|(transform, velocity, stamina, health)| {
+            json!({
+                "x": transform.translation.x,
+                "y": transform.translation.y,
+                "velocity": velocity.map(|v| json!({ "x": v.x, "y": v.y })),
+                "stamina": stamina.map(|s| json!({ "current": s.current, "max": s.max })),
+                "health": health.map(|h| json!({ "current": h.current, "max": h.max })),
+            })
+        })
+        .collect();
+
+    let mut agents = world.query::<(&Transform, &OrbiterAgent)>();
+    let agents: Vec<_> = agents
+        .iter(world)
+        .map(|(transform, agent)| {
+            json!({
+                "x": transform.translation.x,
+                "y": transform.translation.y,
+                "state": format!("{:?}", agent.state),
+                "angle": agent.angle,
+            })
+        })
+        .collect();
+
+    let mut cameras = world.query_filtered::<&Transform, With<Camera2d>>();
+    let camera = cameras
+        .iter(world)
+        .next()
+        .map(|t| json!({ "x": t.translation.x, "y": t.translation.y }));
+
+    let mut world_elements = world.query_filtered::<(), With<WorldElement>>();
+    let world_elements = world_elements.iter(world).count();
+
+    json!({
+        "mode": format!("{:?}", options.mode).to_lowercase(),
+        "ticks": options.ticks,
+        "dt": options.dt,
+        "elapsed_secs": elapsed,
+        "state": format!("{:?}", world.resource::<State<GameState>>().get()),
+        "players": players,
+        "agents": agents,
+        "camera": camera,
+        "world_elements": world_elements,
+    })
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("revgame-headless: {}", message);
+    std::process::exit(2);
+}
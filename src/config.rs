@@ -0,0 +1,519 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Default location of the game configuration file
+pub const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+/// Environment variable that overrides the config file location
+pub const CONFIG_PATH_ENV: &str = "REVGAME_CONFIG";
+
+/// Keys that can be overridden from the command line (`--set key=value`)
+/// or the environment (`REVGAME_<SECTION>_<FIELD>`, e.g. `REVGAME_WINDOW_WIDTH`)
+pub const OVERRIDE_KEYS: &[&str] = &[
+    "window.title",
+    "window.width",
+    "window.height",
+    "scripting.scripts_dir",
+    "scripting.hot_reload",
+    "scripting.disable_after_errors",
+    "scripting.instruction_limit",
+    "scripting.memory_limit_mb",
+    "scripting.profile",
+    "scripting.profile_sampling",
+    "scripting.profile_trace",
+    "scripting.debug_port",
+    "gameplay.player_speed",
+    "gameplay.camera_follow_speed",
+    "backend.url",
+    "backend.timeout_secs",
+];
+
+/// Top-level game configuration, loaded from config.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Resource))]
+#[serde(default)]
+pub struct GameConfig {
+    pub window: WindowConfig,
+    pub scripting: ScriptingConfig,
+    pub gameplay: GameplayConfig,
+    pub backend: BackendConfig,
+}
+
+/// Primary window settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "RevGame".to_string(),
+            width: 1280.0,
+            height: 720.0,
+        }
+    }
+}
+
+/// Lua scripting settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ScriptingConfig {
+    /// Directory scripts are loaded from and watched for changes
+    pub scripts_dir: PathBuf,
+    /// Watch the scripts directory and reload changed files
+    pub hot_reload: bool,
+    /// Disable a Lua callback after this many consecutive errors until its
+    /// script is reloaded (0 = never disable)
+    pub disable_after_errors: u32,
+    /// Maximum Lua VM instructions per call from the game (0 = unlimited)
+    pub instruction_limit: u64,
+    /// Maximum Lua heap size in megabytes (0 = unlimited)
+    pub memory_limit_mb: u64,
+    /// Time every Lua callback into the `ScriptProfile` resource
+    pub profile: bool,
+    /// Also sample the running Lua function every 1000 instructions
+    pub profile_sampling: bool,
+    /// Write a Chrome trace of the profiled calls here when the game exits;
+    /// turns on `profile`
+    pub profile_trace: Option<PathBuf>,
+    /// Local TCP port of the Lua debug adapter (DAP) server (0 = off). Turning
+    /// it on weakens the sandbox: the Lua VM is created in mlua's unsafe mode,
+    /// which no longer rejects precompiled bytecode in script files.
+    pub debug_port: u16,
+}
+
+impl Default for ScriptingConfig {
+    fn default() -> Self {
+        Self {
+            scripts_dir: PathBuf::from("scripts"),
+            hot_reload: true,
+            disable_after_errors: 0,
+            instruction_limit: 10_000_000,
+            memory_limit_mb: 64,
+            profile: false,
+            profile_sampling: false,
+            profile_trace: None,
+            debug_port: 0,
+        }
+    }
+}
+
+/// Gameplay tuning values
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GameplayConfig {
+    /// Player movement speed in pixels per second
+    pub player_speed: f32,
+    /// Camera follow speed (higher = faster, 1.0 / delta = instant)
+    pub camera_follow_speed: f32,
+}
+
+impl Default for GameplayConfig {
+    fn default() -> Self {
+        Self {
+            player_speed: 200.0,
+            camera_follow_speed: 5.0,
+        }
+    }
+}
+
+/// Backend connection settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BackendConfig {
+    /// Base URL of the backend, e.g. `http://localhost:8080`
+    pub url: String,
+    /// Per-request timeout in seconds
+    pub timeout_secs: u64,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8080".to_string(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Errors produced while loading or validating the configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io { path: PathBuf, message: String },
+    /// The config file is not valid JSON or has wrongly typed fields
+    Parse { path: PathBuf, message: String },
+    /// An override names a key that does not exist
+    UnknownKey(String),
+    /// A command-line flag is missing its value
+    MissingValue(String),
+    /// A value failed to parse or failed validation
+    InvalidValue { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, message } => {
+                write!(f, "cannot read {}: {}", path.display(), message)
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "invalid config in {}: {}", path.display(), message)
+            }
+            ConfigError::UnknownKey(key) => write!(
+                f,
+                "unknown config key `{}` (expected one of: {})",
+                key,
+                OVERRIDE_KEYS.join(", ")
+            ),
+            ConfigError::MissingValue(flag) => write!(f, "missing value for `{}`", flag),
+            ConfigError::InvalidValue { key, message } => {
+                write!(f, "invalid value for `{}`: {}", key, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl GameConfig {
+    /// Reads a config file; sections and fields that are absent use their defaults
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        serde_json::from_str(&content).map_err(|e| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Loads config.json (or `$REVGAME_CONFIG`) with environment overrides applied.
+    /// A missing default config file falls back to built-in defaults.
+    pub fn load() -> Result<Self, ConfigError> {
+        let (config, _) = Self::load_with_args(Vec::new())?;
+        Ok(config)
+    }
+
+    /// Loads the configuration for a binary: picks the file from `--config`,
+    /// `$REVGAME_CONFIG` or config.json, then applies environment overrides,
+    /// then command-line overrides, and validates the result.
+    ///
+    /// Arguments that are not config flags are returned for the caller to parse.
+    pub fn load_with_args<I>(args: I) -> Result<(Self, Vec<String>), ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        Self::load_with_args_and_env(args, |name| std::env::var(name).ok())
+    }
+
+    /// Like `load_with_args`, reading environment variables through `env`
+    pub fn load_with_args_and_env<I>(
+        args: I,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Self, Vec<String>), ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config_path: Option<PathBuf> = None;
+        let mut overrides = Vec::new();
+        let mut rest = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value_for = |flag: &str| {
+                args.next()
+                    .ok_or_else(|| ConfigError::MissingValue(flag.to_string()))
+            };
+            match arg.as_str() {
+                "--config" => config_path = Some(PathBuf::from(value_for("--config")?)),
+                "--set" => {
+                    let assignment = value_for("--set")?;
+                    let (key, value) =
+                        assignment
+                            .split_once('=')
+                            .ok_or_else(|| ConfigError::InvalidValue {
+                                key: assignment.clone(),
+                                message: "expected KEY=VALUE".to_string(),
+                            })?;
+                    overrides.push((key.to_string(), value.to_string()));
+                }
+                "--width" => overrides.push(("window.width".to_string(), value_for("--width")?)),
+                "--height" => overrides.push(("window.height".to_string(), value_for("--height")?)),
+                "--scripts-dir" => overrides.push((
+                    "scripting.scripts_dir".to_string(),
+                    value_for("--scripts-dir")?,
+                )),
+                "--backend-url" => {
+                    overrides.push(("backend.url".to_string(), value_for("--backend-url")?))
+                }
+                _ => rest.push(arg),
+            }
+        }
+
+        let mut config = match config_path.or_else(|| env(CONFIG_PATH_ENV).map(PathBuf::from)) {
+            Some(path) => Self::from_file(&path)?,
+            None => {
+                let path = Path::new(DEFAULT_CONFIG_PATH);
+                if path.exists() {
+                    Self::from_file(path)?
+                } else {
+                    Self::default()
+                }
+            }
+        };
+
+        config.apply_env(env)?;
+        for (key, value) in &overrides {
+            config.set(key, value)?;
+        }
+        config.validate()?;
+
+        Ok((config, rest))
+    }
+
+    /// Applies `REVGAME_<SECTION>_<FIELD>` environment overrides, looking each
+    /// variable up with `env`
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        for key in OVERRIDE_KEYS {
+            if let Some(value) = env(&env_var_name(key)) {
+                self.set(key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a single value by its dotted key, e.g. `set("window.width", "1920")`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "window.title" => self.window.title = value.to_string(),
+            "window.width" => self.window.width = parse_value(key, value)?,
+            "window.height" => self.window.height = parse_value(key, value)?,
+            "scripting.scripts_dir" => self.scripting.scripts_dir = PathBuf::from(value),
+            "scripting.hot_reload" => self.scripting.hot_reload = parse_value(key, value)?,
+            "scripting.disable_after_errors" => {
+                self.scripting.disable_after_errors = parse_value(key, value)?
+            }
+            "scripting.instruction_limit" => {
+                self.scripting.instruction_limit = parse_value(key, value)?
+            }
+            "scripting.memory_limit_mb" => {
+                self.scripting.memory_limit_mb = parse_value(key, value)?
+            }
+            "scripting.profile" => self.scripting.profile = parse_value(key, value)?,
+            "scripting.profile_sampling" => {
+                self.scripting.profile_sampling = parse_value(key, value)?
+            }
+            // An empty value turns the trace off
+            "scripting.profile_trace" => {
+                self.scripting.profile_trace = (!value.is_empty()).then(|| PathBuf::from(value))
+            }
+            "scripting.debug_port" => self.scripting.debug_port = parse_value(key, value)?,
+            "gameplay.player_speed" => self.gameplay.player_speed = parse_value(key, value)?,
+            "gameplay.camera_follow_speed" => {
+                self.gameplay.camera_follow_speed = parse_value(key, value)?
+            }
+            "backend.url" => self.backend.url = value.to_string(),
+            "backend.timeout_secs" => self.backend.timeout_secs = parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Checks that all values are usable
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_positive("window.width", self.window.width)?;
+        check_positive("window.height", self.window.height)?;
+        if self.scripting.scripts_dir.as_os_str().is_empty() {
+            return Err(invalid("scripting.scripts_dir", "must not be empty"));
+        }
+        if !self.gameplay.player_speed.is_finite() || self.gameplay.player_speed < 0.0 {
+            return Err(invalid(
+                "gameplay.player_speed",
+                "must be a non-negative number",
+            ));
+        }
+        check_positive(
+            "gameplay.camera_follow_speed",
+            self.gameplay.camera_follow_speed,
+        )?;
+        if !(self.backend.url.starts_with("http://") || self.backend.url.starts_with("https://")) {
+            return Err(invalid(
+                "backend.url",
+                &format!(
+                    "must start with http:// or https://, got {:?}",
+                    self.backend.url
+                ),
+            ));
+        }
+        if self.backend.timeout_secs == 0 {
+            return Err(invalid("backend.timeout_secs", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Environment variable name for an override key (`window.width` -> `REVGAME_WINDOW_WIDTH`)
+pub fn env_var_name(key: &str) -> String {
+    format!("REVGAME_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e: T::Err| invalid(key, &format!("{:?}: {}", value, e)))
+}
+
+fn check_positive(key: &str, value: f32) -> Result<(), ConfigError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(
+            key,
+            &format!("must be greater than 0, got {}", value),
+        ))
+    }
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Writes a config file for one test, so tests don't depend on config.json.
+    /// The file is removed when the returned handle is dropped.
+    fn config_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    /// `--config <file> args...`
+    fn args(file: &tempfile::NamedTempFile, extra: &[&str]) -> Vec<String> {
+        let mut args = vec!["--config".to_string(), file.path().display().to_string()];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    /// Loads an empty config file with `extra` args and no environment variables set
+    fn load(extra: &[&str]) -> Result<(GameConfig, Vec<String>), ConfigError> {
+        let file = config_file("{}");
+        GameConfig::load_with_args_and_env(args(&file, extra), |_| None)
+    }
+
+    fn invalid_key(err: ConfigError) -> String {
+        match err {
+            ConfigError::InvalidValue { key, .. } => key,
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_overrides_a_dotted_key_and_keeps_other_args() {
+        let (config, rest) = load(&[
+            "--set",
+            "window.width=1920",
+            "--fullscreen",
+            "--height",
+            "900",
+        ])
+        .unwrap();
+        assert_eq!(config.window.width, 1920.0);
+        assert_eq!(config.window.height, 900.0);
+        assert_eq!(rest, vec!["--fullscreen".to_string()]);
+    }
+
+    #[test]
+    fn flag_without_value_is_an_error() {
+        let err = load(&["--width"]).unwrap_err();
+        assert!(matches!(err, ConfigError::MissingValue(flag) if flag == "--width"));
+    }
+
+    #[test]
+    fn set_without_equals_sign_is_an_error() {
+        let err = load(&["--set", "window.width"]).unwrap_err();
+        assert_eq!(invalid_key(err), "window.width");
+    }
+
+    #[test]
+    fn unknown_key_is_an_error() {
+        let err = load(&["--set", "window.depth=3"]).unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownKey(key) if key == "window.depth"));
+    }
+
+    #[test]
+    fn unparsable_value_is_an_error() {
+        let err = load(&["--set", "window.width=wide"]).unwrap_err();
+        assert_eq!(invalid_key(err), "window.width");
+    }
+
+    #[test]
+    fn environment_overrides_the_file_and_the_command_line_overrides_both() {
+        let file = config_file(r#"{"window": {"title": "file"}}"#);
+        let env = |name: &str| (name == env_var_name("window.title")).then(|| "env".to_string());
+
+        let (from_file, _) =
+            GameConfig::load_with_args_and_env(args(&file, &[]), |_| None).unwrap();
+        let (from_env, _) = GameConfig::load_with_args_and_env(args(&file, &[]), env).unwrap();
+        let (from_cli, _) =
+            GameConfig::load_with_args_and_env(args(&file, &["--set", "window.title=cli"]), env)
+                .unwrap();
+
+        assert_eq!(from_file.window.title, "file");
+        assert_eq!(from_env.window.title, "env");
+        assert_eq!(from_cli.window.title, "cli");
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        GameConfig::default().validate().unwrap();
+    }
+
+    /// Makes one value of a valid config unusable
+    type Breakage = fn(&mut GameConfig);
+
+    #[test]
+    fn validate_rejects_unusable_values() {
+        let cases: &[(&str, Breakage)] = &[
+            ("window.width", |c| c.window.width = 0.0),
+            ("window.height", |c| c.window.height = -1.0),
+            ("window.width", |c| c.window.width = f32::NAN),
+            ("scripting.scripts_dir", |c| {
+                c.scripting.scripts_dir = PathBuf::new()
+            }),
+            ("gameplay.player_speed", |c| c.gameplay.player_speed = -1.0),
+            ("gameplay.player_speed", |c| {
+                c.gameplay.player_speed = f32::INFINITY
+            }),
+            ("gameplay.camera_follow_speed", |c| {
+                c.gameplay.camera_follow_speed = 0.0
+            }),
+            ("backend.url", |c| {
+                c.backend.url = "ftp://example.com".to_string()
+            }),
+            ("backend.timeout_secs", |c| c.backend.timeout_secs = 0),
+        ];
+        for (key, break_config) in cases {
+            let mut config = GameConfig::default();
+            break_config(&mut config);
+            assert_eq!(invalid_key(config.validate().unwrap_err()), *key);
+        }
+    }
+}
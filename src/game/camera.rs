@@ -1,10 +1,12 @@
 use bevy::prelude::*;
 
 use super::components::CameraTarget;
+use crate::config::GameConfig;
 
 /// Smoothly moves the camera to follow the target entity
 pub fn camera_follow(
     time: Res<Time>,
+    config: Res<GameConfig>,
     target_query: Query<&Transform, (With<CameraTarget>, Without<Camera2d>)>,
     mut camera_query: Query<&mut Transform, With<Camera2d>>,
 ) {
@@ -23,7 +25,7 @@ pub fn camera_follow(
 
     // Smooth follow using lerp
     // Higher values = faster follow (1.0 = instant, 0.1 = slow)
-    let follow_speed = 5.0;
+    let follow_speed = config.gameplay.camera_follow_speed;
     let lerp_factor = (follow_speed * time.delta_secs()).min(1.0);
 
     // Only lerp X and Y, keep camera Z unchanged
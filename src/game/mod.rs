@@ -2,6 +2,7 @@ pub mod agent;
 pub mod camera;
 pub mod components;
 pub mod player;
+pub mod plugin;
 pub mod state;
 pub mod systems;
 pub mod world;
@@ -13,6 +14,7 @@ pub use agent::*;
 pub use camera::*;
 pub use components::*;
 pub use player::*;
+pub use plugin::*;
 pub use state::*;
 pub use systems::*;
 pub use world::*;
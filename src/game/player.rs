@@ -1,9 +1,10 @@
 use bevy::prelude::*;
 
 use super::components::{CameraTarget, MoveSpeed, Player, Stamina, Velocity};
+use crate::config::GameConfig;
 
 /// Spawns the player entity
-pub fn spawn_player(mut commands: Commands) {
+pub fn spawn_player(mut commands: Commands, config: Res<GameConfig>) {
     info!("Spawning player...");
 
     let player_color = Color::srgb(0.204, 0.596, 0.859); // Blue #3498db
@@ -18,7 +19,7 @@ pub fn spawn_player(mut commands: Commands) {
         Transform::from_xyz(0.0, 0.0, 0.0),
         Player,
         Velocity::default(),
-        MoveSpeed::default(),
+        MoveSpeed(config.gameplay.player_speed),
         Stamina::default(),
         CameraTarget,
     ));
@@ -93,7 +94,12 @@ pub fn player_movement(time: Res<Time>, mut query: Query<(&Velocity, &mut Transf
     let delta = time.delta_secs();
 
     for (velocity, mut transform) in query.iter_mut() {
-        transform.translation.x += velocity.x * delta;
-        transform.translation.y += velocity.y * delta;
+        apply_velocity(velocity, &mut transform, delta);
     }
 }
+
+/// Moves a transform by velocity over `delta` seconds
+pub fn apply_velocity(velocity: &Velocity, transform: &mut Transform, delta: f32) {
+    transform.translation.x += velocity.x * delta;
+    transform.translation.y += velocity.y * delta;
+}
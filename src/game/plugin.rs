@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+
+use super::*;
+
+/// Native (Rust) gameplay: player input and movement, stamina, the orbiter agent
+/// and camera follow
+pub struct NativeGameplayPlugin;
+
+impl Plugin for NativeGameplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::InGame),
+            (spawn_world, spawn_player, spawn_agent),
+        )
+        .add_systems(
+            Update,
+            (
+                player_input,
+                stamina_system,
+                player_movement,
+                agent_behavior,
+                camera_follow,
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(
+            OnExit(GameState::InGame),
+            (despawn_world, despawn_player, despawn_agents),
+        );
+    }
+}
+
+/// Lua-driven gameplay with hot reload of the scripts directory
+#[cfg(feature = "scripting")]
+pub struct ScriptedGameplayPlugin;
+
+#[cfg(feature = "scripting")]
+impl Plugin for ScriptedGameplayPlugin {
+    fn build(&self, app: &mut App) {
+        use crate::scripting::{
+            check_script_changes, lua_dispatch_events, LuaCommandFailure, LuaEmittedEvent,
+            LuaEntityMap, LuaEventAppExt, LuaEventSet, ScriptEvent, ScriptStage,
+        };
+
+        app.init_resource::<LuaEntityMap>()
+            .add_event::<ScriptEvent>()
+            .add_event::<LuaEmittedEvent>()
+            .add_event_to_lua::<HealthChanged>("health_changed")
+            .add_event_to_lua::<LuaCommandFailure>("command_failed")
+            // Health changes reach scripts the frame they happen; command failures
+            // are sent after dispatch and arrive the next frame
+            .configure_sets(Update, LuaEventSet::ToLua.after(lua_send_health_changes))
+            .add_systems(Startup, init_lua_scripting)
+            .add_systems(
+                OnEnter(GameState::InGame),
+                (
+                    lua_restart_scripts,
+                    lua_enter_state(GameState::InGame),
+                    lua_spawn_world,
+                    lua_spawn_player,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    lua_poll_debugger,
+                    check_script_changes,
+                    lua_update_time,
+                    lua_update_input,
+                    lua_sync_positions,
+                    lua_send_health_changes,
+                    lua_begin_frame,
+                    lua_dispatch_events,
+                    lua_run_stage(ScriptStage::Input),
+                    lua_run_stage(ScriptStage::Simulation),
+                    lua_update_scripts,
+                    lua_update_tasks,
+                    lua_run_stage(ScriptStage::Late),
+                    lua_run_stage(ScriptStage::Camera),
+                    lua_apply_commands,
+                    lua_attach_scripts,
+                    lua_apply_velocity,
+                    lua_cleanup_despawned,
+                    lua_apply_game_state,
+                )
+                    .chain()
+                    // Scripts keep running outside InGame so they can drive menus;
+                    // `get_game_state()` tells them where they are
+                    .run_if(not(in_state(GameState::Loading))),
+            )
+            .add_systems(
+                OnExit(GameState::InGame),
+                (lua_exit_state(GameState::InGame), lua_despawn_entities).chain(),
+            )
+            // Also picks up the state hooks and spawns run outside `Update`
+            .add_systems(
+                Last,
+                (lua_collect_profile, lua_write_profile_trace_on_exit).chain(),
+            );
+
+        for state in [GameState::Loading, GameState::MainMenu] {
+            app.add_systems(OnEnter(state), lua_enter_state(state))
+                .add_systems(OnExit(state), lua_exit_state(state));
+        }
+    }
+}
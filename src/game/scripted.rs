@@ -1,17 +1,33 @@
+use bevy::input::gamepad::GamepadInput;
 use bevy::prelude::*;
-use std::path::PathBuf;
-
-use crate::game::{CameraTarget, Health, MoveSpeed, Player, Velocity, WorldElement};
-use crate::scripting::{init_script_watcher, setup_lua_bindings, LuaGameState, LuaRuntime};
-
-/// Resource to track the player entity spawned by Lua
-#[derive(Resource, Default)]
-pub struct LuaPlayerEntity(pub Option<(u32, Entity)>);
+use bevy::window::PrimaryWindow;
+use serde::Serialize;
+
+use crate::config::GameConfig;
+use crate::game::{
+    apply_velocity, CameraTarget, GameState, Health, MoveSpeed, Player, Velocity, WorldElement,
+};
+use crate::scripting::{
+    api_mismatches, init_script_watcher, script_module_name, setup_lua_bindings, ButtonState,
+    GamepadState, InputState, LuaCommand, LuaEntity, LuaEntityMap, LuaGameState, LuaRuntime,
+    PendingSpawn, ScriptComponent, ScriptErrors, ScriptInstance, ScriptLimits, ScriptProfile,
+    ScriptStage, SpawnTag, UNKNOWN_SCRIPT,
+};
+
+/// Sent to scripts as `health_changed` whenever a Lua entity's health changes
+#[derive(Event, Debug, Clone, Serialize)]
+pub struct HealthChanged {
+    /// Lua id of the entity
+    pub id: u32,
+    pub current: f32,
+    pub max: f32,
+}
 
 /// Initialize the Lua scripting system
-pub fn init_lua_scripting(mut commands: Commands) {
-    // Create Lua runtime
-    let mut runtime = match LuaRuntime::new() {
+pub fn init_lua_scripting(mut commands: Commands, config: Res<GameConfig>) {
+    // Create the sandboxed Lua runtime
+    let limits = ScriptLimits::from_config(&config.scripting);
+    let mut runtime = match LuaRuntime::with_limits(limits) {
         Ok(r) => r,
         Err(e) => {
             error!("Failed to create Lua runtime: {}", e);
@@ -19,283 +35,744 @@ pub fn init_lua_scripting(mut commands: Commands) {
         }
     };
 
-    // Create game state
-    let game_state = LuaGameState::new();
+    // Create game state; the bindings own it from here on
+    let mut game_state = LuaGameState::new();
+    game_state.set_game_states(GameState::ALL.map(GameState::name));
 
     // Setup bindings
     {
         let lua = runtime.lua();
-        if let Err(e) = setup_lua_bindings(&lua, game_state.clone()) {
+        if let Err(e) = setup_lua_bindings(&lua, game_state) {
             error!("Failed to setup Lua bindings: {}", e);
             return;
         }
+        // Catch bindings added without updating the generated API reference
+        if cfg!(debug_assertions) {
+            for mismatch in api_mismatches(&lua).unwrap_or_default() {
+                warn!("Lua API reference: {} (see src/scripting/api.rs)", mismatch);
+            }
+        }
     }
 
-    // Load initial scripts
-    let scripts_dir = PathBuf::from("scripts");
-    for script in &["world", "player", "camera", "healthbar"] {
-        let path = scripts_dir.join(format!("{}.lua", script));
-        if path.exists() {
-            if let Err(e) = runtime.load_script(script, &path) {
-                error!("Failed to load {}.lua: {}", script, e);
+    let disable_after = config.scripting.disable_after_errors;
+    let mut errors = ScriptErrors::new((disable_after > 0).then_some(disable_after));
+
+    // Load every script in the scripts directory; `require` pulls in dependencies first
+    let scripts_dir = config.scripting.scripts_dir.clone();
+    runtime.set_scripts_dir(scripts_dir.clone());
+    match LuaRuntime::discover_scripts(&scripts_dir) {
+        Ok(scripts) => {
+            for script in &scripts {
+                if let Err(e) = runtime.require(script) {
+                    errors.report_load(script, &e);
+                }
             }
-        } else {
-            warn!("Script not found: {:?}", path);
         }
+        Err(e) => error!("Failed to scan scripts directory {:?}: {}", scripts_dir, e),
     }
 
     // Initialize file watcher for hot reload
-    if let Some(watcher) = init_script_watcher(scripts_dir) {
-        commands.insert_resource(watcher);
+    if config.scripting.hot_reload {
+        if let Some(watcher) = init_script_watcher(scripts_dir) {
+            commands.insert_resource(watcher);
+        }
+    }
+
+    let profile = &config.scripting;
+    if profile.profile || profile.profile_trace.is_some() {
+        runtime.set_profiling(true);
+        runtime.set_sampling(profile.profile_sampling);
+        commands.insert_resource(ScriptProfile::new());
     }
 
+    let debug_port = config.scripting.debug_port;
+    if debug_port > 0 {
+        if let Err(e) = runtime.start_debugger(debug_port) {
+            warn!("Failed to start the Lua debugger on port {}: {}", debug_port, e);
+        }
+    }
+
+    commands.insert_resource(errors);
     commands.insert_resource(runtime);
-    commands.insert_resource(game_state);
-    commands.insert_resource(LuaPlayerEntity::default());
 
     info!("Lua scripting initialized");
 }
 
-/// Spawn world using Lua
-pub fn lua_spawn_world(runtime: Option<Res<LuaRuntime>>) {
+/// Runs a Lua callback, reporting a failure to `ScriptErrors` instead of dropping it.
+/// Callbacks that `ScriptErrors` has disabled are skipped. Without a `script`, the
+/// failing callback is attributed to the script defining the global of that name.
+fn run_lua_callback<T>(
+    runtime: &LuaRuntime,
+    errors: Option<&mut ScriptErrors>,
+    callback: &str,
+    script: Option<&str>,
+    call: impl FnOnce(&LuaRuntime) -> mlua::Result<T>,
+) -> Option<T> {
+    let Some(errors) = errors else {
+        return call(runtime)
+            .map_err(|e| error!("Lua callback {} failed: {}", callback, e))
+            .ok();
+    };
+    if errors.is_disabled(callback) {
+        return None;
+    }
+
+    match call(runtime) {
+        Ok(value) => {
+            errors.record_success(callback);
+            Some(value)
+        }
+        Err(e) => {
+            let script = script
+                .map(str::to_string)
+                .or_else(|| runtime.function_script(callback))
+                .unwrap_or_else(|| UNKNOWN_SCRIPT.to_string());
+            errors.report(&script, callback, &e);
+            None
+        }
+    }
+}
+
+/// Records `state` as the game state scripts see and calls the optional
+/// `on_enter_<state>()` Lua hook
+pub fn lua_enter_state(
+    state: GameState,
+) -> impl FnMut(Option<Res<LuaRuntime>>, Option<ResMut<ScriptErrors>>) {
+    move |runtime, mut errors| {
+        let Some(runtime) = runtime else { return };
+        runtime.with_game_state(|game_state| game_state.set_game_state_read(state.name()));
+        let hook = format!("on_enter_{}", state.hook_suffix());
+        run_state_hook(&runtime, errors.as_deref_mut(), &hook);
+    }
+}
+
+/// Calls the optional `on_exit_<state>()` Lua hook
+pub fn lua_exit_state(
+    state: GameState,
+) -> impl FnMut(Option<Res<LuaRuntime>>, Option<ResMut<ScriptErrors>>) {
+    move |runtime, mut errors| {
+        let Some(runtime) = runtime else { return };
+        let hook = format!("on_exit_{}", state.hook_suffix());
+        run_state_hook(&runtime, errors.as_deref_mut(), &hook);
+    }
+}
+
+fn run_state_hook(runtime: &LuaRuntime, errors: Option<&mut ScriptErrors>, hook: &str) {
+    if runtime.has_function(hook) {
+        run_lua_callback(runtime, errors, hook, None, |runtime| runtime.call_function(hook));
+    }
+}
+
+/// Switches to the game state scripts last asked for with `set_game_state`.
+/// Asking for the current state does nothing.
+pub fn lua_apply_game_state(
+    runtime: Option<Res<LuaRuntime>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
     let Some(runtime) = runtime else { return };
+    let Some(requested) = runtime
+        .with_game_state(LuaGameState::take_requested_game_state)
+        .flatten()
+    else {
+        return;
+    };
+    match GameState::from_name(&requested) {
+        Some(requested) if requested != *state.get() => {
+            info!("Lua requested game state {:?}", requested);
+            next_state.set(requested);
+        }
+        Some(_) => {}
+        None => warn!("Lua requested unknown game state '{}'", requested),
+    }
+}
 
-    if let Err(e) = (*runtime).call_function("spawn_world") {
-        error!("Failed to call spawn_world: {}", e);
+/// Starts every script over when InGame is entered again, so state such as
+/// `HealthbarState.spawned` does not carry over from the previous game
+pub fn lua_restart_scripts(
+    mut entered_before: Local<bool>,
+    mut runtime: Option<ResMut<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+) {
+    if !std::mem::replace(&mut *entered_before, true) {
+        return;
+    }
+    let Some(ref mut runtime) = runtime else { return };
+
+    let result = runtime.restart_scripts(|script, e| match errors {
+        Some(ref mut errors) => errors.report(script, "restart", &e),
+        None => error!("Failed to restart Lua script {}: {}", script, e),
+    });
+    if let Err(e) = result {
+        error!("Failed to restart Lua scripts: {}", e);
     }
 }
 
+/// Spawn world using Lua
+pub fn lua_spawn_world(runtime: Option<Res<LuaRuntime>>, mut errors: Option<ResMut<ScriptErrors>>) {
+    let Some(runtime) = runtime else { return };
+
+    // Named explicitly so the error is filed under the script even when the
+    // global is missing, and editing that script re-enables the callback
+    run_lua_callback(
+        &runtime,
+        errors.as_deref_mut(),
+        "spawn_world",
+        Some("world"),
+        |runtime| runtime.call_function("spawn_world"),
+    );
+}
+
 /// Spawn player using Lua
 pub fn lua_spawn_player(
     runtime: Option<Res<LuaRuntime>>,
-    mut player_entity: Option<ResMut<LuaPlayerEntity>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
 ) {
     let Some(runtime) = runtime else { return };
-    let Some(ref mut player_entity) = player_entity else {
-        return;
-    };
 
-    match (*runtime).call_spawn_function("spawn_player") {
-        Ok(lua_id) => {
-            info!("Lua spawn_player returned ID: {}", lua_id);
-            // Entity will be created in process_lua_commands
-            player_entity.0 = Some((lua_id, Entity::PLACEHOLDER));
-        }
-        Err(e) => error!("Failed to call spawn_player: {}", e),
+    let lua_id = run_lua_callback(
+        &runtime,
+        errors.as_deref_mut(),
+        "spawn_player",
+        Some("player"),
+        |runtime| runtime.call_spawn_function("spawn_player"),
+    );
+    if let Some(lua_id) = lua_id {
+        // Entity will be created in lua_apply_commands
+        info!("Lua spawn_player returned ID: {}", lua_id);
     }
 }
 
-/// Update keyboard state for Lua
-pub fn lua_update_input(keyboard: Res<ButtonInput<KeyCode>>, game_state: Option<Res<LuaGameState>>) {
-    let Some(game_state) = game_state else { return };
+/// Mirrors keyboard, mouse and gamepad input into the Lua game state.
+/// Mouse input and the cursor are optional so headless runs work without them.
+pub fn lua_update_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Option<Res<ButtonInput<MouseButton>>>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    runtime: Option<Res<LuaRuntime>>,
+) {
+    let Some(runtime) = runtime else { return };
 
-    // Clear and update key states
-    game_state.clear_keys();
+    let mut input = InputState::default();
+    mirror_buttons(&mut input.keys, &keyboard);
+    if let Some(mouse) = mouse {
+        mirror_buttons(&mut input.mouse, &mouse);
+    }
 
-    let key_mappings = [
-        (KeyCode::KeyW, "W"),
-        (KeyCode::KeyA, "A"),
-        (KeyCode::KeyS, "S"),
-        (KeyCode::KeyD, "D"),
-        (KeyCode::ArrowUp, "UP"),
-        (KeyCode::ArrowDown, "DOWN"),
-        (KeyCode::ArrowLeft, "LEFT"),
-        (KeyCode::ArrowRight, "RIGHT"),
-    ];
+    if let Ok(window) = windows.get_single() {
+        if let Some(cursor) = window.cursor_position() {
+            input.cursor_screen = Some((cursor.x, cursor.y));
+            if let Ok((camera, camera_transform)) = cameras.get_single() {
+                if let Ok(world) = camera.viewport_to_world_2d(camera_transform, cursor) {
+                    input.cursor_world = Some((world.x, world.y));
+                }
+            }
+        }
+    }
 
-    for (code, name) in key_mappings {
-        if keyboard.pressed(code) {
-            game_state.set_key_pressed(name, true);
+    // Entities are allocated in connection order, which keeps Lua indices stable
+    let mut pads: Vec<_> = gamepads.iter().collect();
+    pads.sort_by_key(|(entity, _)| *entity);
+    for (_, gamepad) in pads {
+        let mut pad = GamepadState::default();
+        mirror_buttons(&mut pad.buttons, gamepad.digital());
+        for input in gamepad.get_analog_axes() {
+            let name = match input {
+                GamepadInput::Axis(axis) => format!("{:?}", axis),
+                GamepadInput::Button(button) => format!("{:?}", button),
+            };
+            if let Some(value) = gamepad.get(*input) {
+                pad.axes.insert(name.to_uppercase(), value);
+            }
         }
+        input.gamepads.push(pad);
+    }
+
+    runtime.with_game_state(|game_state| game_state.set_input(input));
+}
+
+/// Copies a Bevy `ButtonInput` into a Lua `ButtonState`, named by the button's `Debug` form
+fn mirror_buttons<T>(state: &mut ButtonState, buttons: &ButtonInput<T>)
+where
+    T: Copy + Eq + std::hash::Hash + std::fmt::Debug + Send + Sync + 'static,
+{
+    for button in buttons.get_pressed() {
+        state.set(&format!("{:?}", button), true, false, false);
+    }
+    for button in buttons.get_just_pressed() {
+        state.set(&format!("{:?}", button), false, true, false);
+    }
+    for button in buttons.get_just_released() {
+        state.set(&format!("{:?}", button), false, false, true);
     }
 }
 
 /// Update delta time for Lua
-pub fn lua_update_time(time: Res<Time>, game_state: Option<Res<LuaGameState>>) {
-    let Some(game_state) = game_state else { return };
-    game_state.set_delta_time(time.delta_secs());
+pub fn lua_update_time(time: Res<Time>, runtime: Option<Res<LuaRuntime>>) {
+    let Some(runtime) = runtime else { return };
+    runtime.with_game_state(|game_state| game_state.set_delta_time(time.delta_secs()));
 }
 
-/// Sync entity positions from Bevy to Lua (for reading)
+/// Sync entity positions and health from Bevy to Lua (for reading).
+/// Only entities whose components changed since the last run are copied,
+/// so static world elements cost nothing after their first frame.
 pub fn lua_sync_positions(
-    game_state: Option<Res<LuaGameState>>,
-    player_entity: Option<Res<LuaPlayerEntity>>,
-    transforms: Query<&Transform>,
-    health_query: Query<&Health>,
-    camera_query: Query<&Transform, With<Camera2d>>,
+    runtime: Option<Res<LuaRuntime>>,
+    transforms: Query<(&LuaEntity, &Transform), Changed<Transform>>,
+    health_query: Query<(&LuaEntity, &Health), Changed<Health>>,
+    camera_query: Query<&Transform, (With<Camera2d>, Changed<Transform>)>,
 ) {
-    let Some(game_state) = game_state else { return };
-    let Some(player_entity) = player_entity else {
-        return;
-    };
+    let Some(runtime) = runtime else { return };
 
-    // Sync player position and health
-    if let Some((lua_id, entity)) = player_entity.0 {
-        if entity != Entity::PLACEHOLDER {
-            if let Ok(transform) = transforms.get(entity) {
-                game_state.update_entity_position(lua_id, transform.translation.x, transform.translation.y);
-            }
-            if let Ok(health) = health_query.get(entity) {
-                game_state.update_entity_health(lua_id, health.current, health.max);
-            }
+    runtime.with_game_state(|game_state| {
+        for (lua_entity, transform) in transforms.iter() {
+            game_state.update_entity_position(
+                lua_entity.0,
+                transform.translation.x,
+                transform.translation.y,
+            );
+        }
+
+        for (lua_entity, health) in health_query.iter() {
+            game_state.update_entity_health(lua_entity.0, health.current, health.max);
         }
+
+        // Sync camera position
+        if let Ok(camera_transform) = camera_query.get_single() {
+            game_state.set_camera_position_read(
+                camera_transform.translation.x,
+                camera_transform.translation.y,
+            );
+        }
+    });
+}
+
+/// Publishes what was synced this frame, so every script reads the same snapshot
+pub fn lua_begin_frame(runtime: Option<Res<LuaRuntime>>) {
+    let Some(runtime) = runtime else { return };
+    runtime.with_game_state(LuaGameState::publish_frame);
+}
+
+/// Handles requests from an attached Lua debugger
+pub fn lua_poll_debugger(runtime: Option<Res<LuaRuntime>>) {
+    let Some(runtime) = runtime else { return };
+    runtime.poll_debugger();
+}
+
+/// Reports health changes of Lua entities as `HealthChanged` events
+pub fn lua_send_health_changes(
+    query: Query<(&LuaEntity, &Health), Changed<Health>>,
+    mut events: EventWriter<HealthChanged>,
+) {
+    for (lua_entity, health) in query.iter() {
+        events.send(HealthChanged {
+            id: lua_entity.0,
+            current: health.current,
+            max: health.max,
+        });
     }
+}
 
-    // Sync camera position
-    if let Ok(camera_transform) = camera_query.get_single() {
-        game_state.set_camera_position_read(
-            camera_transform.translation.x,
-            camera_transform.translation.y,
-        );
+/// Runs the Lua systems registered for `stage` (see `register_system`), in order
+pub fn lua_run_stage(
+    stage: ScriptStage,
+) -> impl FnMut(Res<Time>, Option<Res<LuaRuntime>>, Option<ResMut<ScriptErrors>>) {
+    move |time, runtime, mut errors| {
+        let Some(runtime) = runtime else { return };
+
+        let systems = match runtime.systems(stage) {
+            Ok(systems) => systems,
+            Err(e) => {
+                error!("Failed to list Lua {} systems: {}", stage, e);
+                return;
+            }
+        };
+        let delta = time.delta_secs();
+        for system in systems {
+            run_lua_callback(
+                &runtime,
+                errors.as_deref_mut(),
+                &system.name,
+                system.module.as_deref(),
+                |runtime| runtime.run_system(&system.name, delta),
+            );
+        }
     }
 }
 
-/// Call Lua update functions
-pub fn lua_update_player(
+/// Resumes `spawn_task` coroutines whose wait is over
+pub fn lua_update_tasks(
+    time: Res<Time>,
     runtime: Option<Res<LuaRuntime>>,
-    player_entity: Option<Res<LuaPlayerEntity>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
 ) {
     let Some(runtime) = runtime else { return };
-    let Some(player_entity) = player_entity else {
-        return;
-    };
 
-    if let Some((lua_id, entity)) = player_entity.0 {
-        if entity != Entity::PLACEHOLDER {
-            let _ = (*runtime).call_update_function("update_player", lua_id, 0.0);
+    let result = runtime.update_tasks(time.delta_secs(), |id, module, e| match errors {
+        Some(ref mut errors) => {
+            errors.report(module.unwrap_or("<unknown>"), &format!("task {}", id), &e)
         }
+        None => error!("Lua task {} failed: {}", id, e),
+    });
+    if let Err(e) = result {
+        error!("Failed to update Lua tasks: {}", e);
     }
 }
 
-/// Call Lua camera update
-pub fn lua_update_camera(
+/// Creates the script instances of entities that gained a `ScriptComponent` and calls
+/// their `on_spawn(self)`. Entities spawned from Rust are given a Lua id first.
+pub fn lua_attach_scripts(
+    mut commands: Commands,
+    query: Query<(Entity, &ScriptComponent, Option<&LuaEntity>), Added<ScriptComponent>>,
     runtime: Option<Res<LuaRuntime>>,
-    player_entity: Option<Res<LuaPlayerEntity>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+    mut entity_map: ResMut<LuaEntityMap>,
 ) {
     let Some(runtime) = runtime else { return };
-    let Some(player_entity) = player_entity else {
-        return;
-    };
 
-    if let Some((lua_id, entity)) = player_entity.0 {
-        if entity != Entity::PLACEHOLDER {
-            let _ = (*runtime).call_update_function("update_camera", lua_id, 0.0);
-        }
+    for (entity, component, lua_entity) in query.iter() {
+        let lua_id = match lua_entity {
+            Some(lua_entity) => lua_entity.0,
+            None => {
+                let Some(lua_id) = runtime.with_game_state(LuaGameState::allocate_entity) else {
+                    return;
+                };
+                commands.entity(entity).insert(LuaEntity(lua_id));
+                entity_map.insert(lua_id, entity);
+                lua_id
+            }
+        };
+
+        let script = script_module_name(&component.script);
+        run_lua_callback(
+            &runtime,
+            errors.as_deref_mut(),
+            &format!("{}.on_spawn", script),
+            Some(&script),
+            |runtime| runtime.spawn_instance(lua_id, &script),
+        );
     }
 }
 
-/// Process commands from Lua (spawn entities, update positions, etc.)
-pub fn lua_process_commands(
-    mut commands: Commands,
-    game_state: Option<Res<LuaGameState>>,
-    mut player_entity: Option<ResMut<LuaPlayerEntity>>,
-    mut transforms: Query<&mut Transform, Without<Camera2d>>,
-    mut camera_query: Query<&mut Transform, (With<Camera2d>, Without<Player>)>,
-    mut health_query: Query<&mut Health>,
-    mut sprites: Query<&mut Sprite>,
+/// Calls `on_update(self, dt)` of every entity script, in spawn order, as one batch
+pub fn lua_update_scripts(
+    time: Res<Time>,
+    runtime: Option<Res<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
 ) {
-    let Some(game_state) = game_state else { return };
-
-    // Process pending spawns
-    for spawn in game_state.take_pending_spawns() {
-        let entity = commands
-            .spawn((
-                Sprite {
-                    color: spawn.color,
-                    custom_size: Some(Vec2::new(spawn.width, spawn.height)),
-                    ..default()
-                },
-                Transform::from_xyz(spawn.x, spawn.y, spawn.z),
-            ))
-            .id();
-
-        // Register entity mapping
-        game_state.register_entity(spawn.lua_id, entity);
-
-        // Update player entity if this was the player spawn
-        if let Some(ref mut player_entity) = player_entity {
-            if let Some((lua_id, _)) = player_entity.0 {
-                if lua_id == spawn.lua_id {
-                    player_entity.0 = Some((lua_id, entity));
-                }
-            }
+    let Some(runtime) = runtime else { return };
+
+    let instances = match runtime.instances() {
+        Ok(instances) => instances,
+        Err(e) => {
+            error!("Failed to list Lua script instances: {}", e);
+            return;
         }
-    }
+    };
+    // Skip callbacks that failed too often
+    let callback = |instance: &ScriptInstance| format!("{}.on_update", instance.script);
+    let instances: Vec<_> = match errors {
+        Some(ref errors) => instances
+            .into_iter()
+            .filter(|instance| !errors.is_disabled(&callback(instance)))
+            .collect(),
+        None => instances,
+    };
 
-    // Process marker components
-    for lua_id in game_state.take_mark_player() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            commands.entity(entity).insert((
-                Player,
-                Velocity::default(),
-                MoveSpeed::default(),
-                Health::default(),
-            ));
+    runtime.update(time.delta_secs(), &instances, |instance, result| {
+        match (result, errors.as_deref_mut()) {
+            (Ok(()), Some(errors)) => errors.record_success(&callback(instance)),
+            (Ok(()), None) => {}
+            (Err(e), Some(errors)) => errors.report(&instance.script, &callback(instance), &e),
+            (Err(e), None) => error!("Lua callback {} failed: {}", callback(instance), e),
+        }
+    });
+}
+
+/// Drops the script instance of a despawned entity after calling its `on_despawn(self)`
+fn despawn_script_instance(runtime: &LuaRuntime, errors: Option<&mut ScriptErrors>, lua_id: u32) {
+    let Some(script) = runtime.instance_script(lua_id) else {
+        return;
+    };
+    if let Err(e) = runtime.despawn_instance(lua_id) {
+        let callback = format!("{}.on_despawn", script);
+        match errors {
+            Some(errors) => errors.report(&script, &callback, &e),
+            None => error!("Lua callback {} failed: {}", callback, e),
         }
     }
+}
 
-    for lua_id in game_state.take_mark_camera_target() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            commands.entity(entity).insert(CameraTarget);
+/// Applies the commands scripts queued this frame, in the order they were issued.
+/// Each command sees the effects of the ones before it: an entity spawned earlier
+/// in the frame can be moved, tagged or hurt right away. Commands that cannot be
+/// applied are logged and sent to scripts as `command_failed` events.
+pub fn lua_apply_commands(world: &mut World) {
+    let Some(commands) = world
+        .get_resource::<LuaRuntime>()
+        .and_then(|runtime| runtime.with_game_state(LuaGameState::take_commands))
+    else {
+        return;
+    };
+
+    for command in commands {
+        if let Err(message) = apply_lua_command(world, &command) {
+            let failure = command.failed(message);
+            warn!("Lua {} failed: {}", failure.command, failure.message);
+            world.send_event(failure);
         }
     }
+}
 
-    for lua_id in game_state.take_mark_world_element() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            commands.entity(entity).insert(WorldElement);
+fn apply_lua_command(world: &mut World, command: &LuaCommand) -> Result<(), String> {
+    match command {
+        LuaCommand::Spawn(spawn) => spawn_lua_entity(world, spawn),
+        LuaCommand::Tag { lua_id, tag } => {
+            let entity = mapped_entity(world, *lua_id)?;
+            match tag {
+                SpawnTag::Player => {
+                    let speed = world.resource::<GameConfig>().gameplay.player_speed;
+                    world.entity_mut(entity).insert((
+                        Player,
+                        Velocity::default(),
+                        MoveSpeed(speed),
+                        Health::default(),
+                    ));
+                }
+                SpawnTag::CameraTarget => {
+                    world.entity_mut(entity).insert(CameraTarget);
+                }
+                SpawnTag::WorldElement => {
+                    world.entity_mut(entity).insert(WorldElement);
+                }
+            }
+        }
+        LuaCommand::SetPosition { lua_id, x, y } => {
+            let entity = mapped_entity(world, *lua_id)?;
+            let mut entity = world.entity_mut(entity);
+            let Some(mut transform) = entity.get_mut::<Transform>() else {
+                return Err(format!("entity {} has no transform", lua_id));
+            };
+            transform.translation.x = *x;
+            transform.translation.y = *y;
+        }
+        // Entities without a Velocity get one
+        LuaCommand::SetVelocity { lua_id, x, y } => {
+            let entity = mapped_entity(world, *lua_id)?;
+            world.entity_mut(entity).insert(Velocity { x: *x, y: *y });
+        }
+        LuaCommand::SetHealth { lua_id, health } => {
+            let entity = mapped_entity(world, *lua_id)?;
+            let mut entity = world.entity_mut(entity);
+            let Some(mut current) = entity.get_mut::<Health>() else {
+                return Err(format!("entity {} has no health", lua_id));
+            };
+            current.current = health.clamp(0.0, current.max);
+        }
+        LuaCommand::SetSpriteSize {
+            lua_id,
+            width,
+            height,
+        } => {
+            let entity = mapped_entity(world, *lua_id)?;
+            let mut entity = world.entity_mut(entity);
+            let Some(mut sprite) = entity.get_mut::<Sprite>() else {
+                return Err(format!("entity {} has no sprite", lua_id));
+            };
+            sprite.custom_size = Some(Vec2::new(*width, *height));
+        }
+        LuaCommand::SetCameraPosition { x, y } => {
+            let mut cameras =
+                world.query_filtered::<&mut Transform, (With<Camera2d>, Without<Player>)>();
+            let Ok(mut camera_transform) = cameras.get_single_mut(world) else {
+                return Err("there is no camera".to_string());
+            };
+            camera_transform.translation.x = *x;
+            camera_transform.translation.y = *y;
+        }
+        // Children go with their parent; the id is cleaned up by `lua_cleanup_despawned`
+        LuaCommand::Despawn(lua_id) => {
+            let entity = mapped_entity(world, *lua_id)?;
+            world.entity_mut(entity).despawn_recursive();
         }
     }
+    Ok(())
+}
 
-    // Process position updates
-    for (lua_id, x, y) in game_state.take_position_updates() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            if let Ok(mut transform) = transforms.get_mut(entity) {
-                transform.translation.x = x;
-                transform.translation.y = y;
+/// The live Bevy entity a Lua id was spawned as
+fn mapped_entity(world: &World, lua_id: u32) -> Result<Entity, String> {
+    world
+        .resource::<LuaEntityMap>()
+        .get(lua_id)
+        .filter(|entity| world.entities().contains(*entity))
+        .ok_or_else(|| format!("entity {} does not exist", lua_id))
+}
+
+/// Spawns the entity for a `spawn{...}` / `spawn_sprite` command and maps its Lua id
+fn spawn_lua_entity(world: &mut World, spawn: &PendingSpawn) {
+    let transform = Transform::from_xyz(spawn.x, spawn.y, spawn.z)
+        .with_rotation(Quat::from_rotation_z(spawn.rotation))
+        .with_scale(Vec3::new(spawn.scale[0], spawn.scale[1], 1.0));
+    let player_speed = world.resource::<GameConfig>().gameplay.player_speed;
+    let asset_server = world.get_resource::<AssetServer>().cloned();
+    let mut entity = world.spawn((transform, LuaEntity(spawn.lua_id)));
+
+    if let Some(spec) = &spawn.sprite {
+        let [r, g, b, a] = spec.color;
+        let mut sprite = Sprite {
+            color: Color::srgba(r, g, b, a),
+            custom_size: spec.size.map(|[w, h]| Vec2::new(w, h)),
+            flip_x: spec.flip_x,
+            flip_y: spec.flip_y,
+            ..default()
+        };
+        if let Some(path) = &spec.image {
+            match &asset_server {
+                Some(asset_server) => sprite.image = asset_server.load(path.clone()),
+                None => warn!(
+                    "No asset server; spawning Lua entity {} without image '{}'",
+                    spawn.lua_id, path
+                ),
             }
         }
+        entity.insert(sprite);
     }
 
-    // Process camera position
-    if let Some((x, y)) = game_state.take_camera_position() {
-        if let Ok(mut camera_transform) = camera_query.get_single_mut() {
-            camera_transform.translation.x = x;
-            camera_transform.translation.y = y;
+    for tag in &spawn.tags {
+        match tag {
+            SpawnTag::Player => {
+                entity.insert((
+                    Player,
+                    Velocity::default(),
+                    MoveSpeed(player_speed),
+                    Health::default(),
+                ));
+            }
+            SpawnTag::CameraTarget => {
+                entity.insert(CameraTarget);
+            }
+            SpawnTag::WorldElement => {
+                entity.insert(WorldElement);
+            }
         }
     }
+    if let Some([current, max]) = spawn.health {
+        entity.insert(Health { current, max });
+    }
+    if let Some([x, y]) = spawn.velocity {
+        entity.insert(Velocity { x, y });
+    }
+    if let Some(script) = &spawn.script {
+        entity.insert(ScriptComponent::new(script.clone()));
+    }
 
-    // Process health updates
-    for (lua_id, new_health) in game_state.take_health_updates() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            if let Ok(mut health) = health_query.get_mut(entity) {
-                health.current = new_health.clamp(0.0, health.max);
+    let entity = entity.id();
+    world
+        .resource_mut::<LuaEntityMap>()
+        .insert(spawn.lua_id, entity);
+}
+
+/// Forgets Lua ids whose entities were despawned, whether by a script or by Rust,
+/// and cancels the tasks they owned
+pub fn lua_cleanup_despawned(
+    mut removed: RemovedComponents<LuaEntity>,
+    runtime: Option<Res<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+    mut entity_map: ResMut<LuaEntityMap>,
+) {
+    for entity in removed.read() {
+        let Some(lua_id) = entity_map.remove_entity(entity) else {
+            continue;
+        };
+        if let Some(ref runtime) = runtime {
+            despawn_script_instance(runtime, errors.as_deref_mut(), lua_id);
+            runtime.with_game_state(|game_state| game_state.forget_entity(lua_id));
+            if let Err(e) = runtime.cancel_entity_tasks(lua_id) {
+                error!("Failed to cancel tasks of entity {}: {}", lua_id, e);
             }
         }
     }
+}
 
-    // Process sprite size updates
-    for (lua_id, w, h) in game_state.take_size_updates() {
-        if let Some(entity) = game_state.get_entity(lua_id) {
-            if let Ok(mut sprite) = sprites.get_mut(entity) {
-                sprite.custom_size = Some(Vec2::new(w, h));
+/// Despawns every Lua-spawned entity (for cleanup when leaving InGame state)
+pub fn lua_despawn_entities(
+    mut commands: Commands,
+    runtime: Option<Res<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+    mut entity_map: ResMut<LuaEntityMap>,
+) {
+    for (lua_id, entity) in entity_map.iter() {
+        if let Some(entity_commands) = commands.get_entity(entity) {
+            entity_commands.despawn_recursive();
+        }
+        if let Some(ref runtime) = runtime {
+            despawn_script_instance(runtime, errors.as_deref_mut(), lua_id);
+            if let Err(e) = runtime.cancel_entity_tasks(lua_id) {
+                error!("Failed to cancel tasks of entity {}: {}", lua_id, e);
             }
         }
     }
+    entity_map.clear();
+
+    if let Some(runtime) = runtime {
+        runtime.with_game_state(LuaGameState::clear_entities);
+    }
+    info!("Lua entities despawned");
 }
 
-/// Call Lua healthbar update
-pub fn lua_update_healthbar(
+/// Moves this frame's Lua timings into the `ScriptProfile` resource
+pub fn lua_collect_profile(
     runtime: Option<Res<LuaRuntime>>,
-    player_entity: Option<Res<LuaPlayerEntity>>,
+    profile: Option<ResMut<ScriptProfile>>,
 ) {
-    let Some(runtime) = runtime else { return };
-    let Some(player_entity) = player_entity else {
+    let (Some(runtime), Some(mut profile)) = (runtime, profile) else {
         return;
     };
+    runtime.collect_profile(&mut profile);
+}
 
-    if let Some((lua_id, entity)) = player_entity.0 {
-        if entity != Entity::PLACEHOLDER {
-            let _ = (*runtime).call_update_function("update_healthbar", lua_id, 0.0);
-        }
+/// Writes the Chrome trace to `scripting.profile_trace` when the app exits
+pub fn lua_write_profile_trace_on_exit(
+    mut exits: EventReader<AppExit>,
+    config: Res<GameConfig>,
+    profile: Option<Res<ScriptProfile>>,
+) {
+    if exits.read().next().is_none() {
+        return;
+    }
+    if let Some(profile) = profile {
+        write_profile_trace(&config, &profile);
+    }
+}
+
+/// Writes the profiled Lua calls to `scripting.profile_trace`, if set. For runs
+/// that end without an `AppExit` event, such as the headless simulator.
+pub fn write_lua_profile_trace(world: &World) {
+    if let Some(profile) = world.get_resource::<ScriptProfile>() {
+        write_profile_trace(world.resource::<GameConfig>(), profile);
+    }
+}
+
+fn write_profile_trace(config: &GameConfig, profile: &ScriptProfile) {
+    let Some(path) = &config.scripting.profile_trace else {
+        return;
+    };
+    match profile.write_chrome_trace(path) {
+        Ok(()) => info!("Wrote Lua profile trace to {}", path.display()),
+        Err(e) => error!("Failed to write Lua profile trace to {}: {}", path.display(), e),
+    }
+}
+
+/// Integrates velocity for every Lua entity, the same way `player_movement` does
+pub fn lua_apply_velocity(
+    time: Res<Time>,
+    mut query: Query<(&Velocity, &mut Transform), With<LuaEntity>>,
+) {
+    let delta = time.delta_secs();
+
+    for (velocity, mut transform) in query.iter_mut() {
+        apply_velocity(velocity, &mut transform, delta);
     }
 }
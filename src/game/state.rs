@@ -11,3 +11,29 @@ pub enum GameState {
     /// In game
     InGame,
 }
+
+impl GameState {
+    pub const ALL: [GameState; 3] = [GameState::Loading, GameState::MainMenu, GameState::InGame];
+
+    /// Name scripts use for the state (`set_game_state("MainMenu")`)
+    pub fn name(self) -> &'static str {
+        match self {
+            GameState::Loading => "Loading",
+            GameState::MainMenu => "MainMenu",
+            GameState::InGame => "InGame",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|state| state.name() == name)
+    }
+
+    /// Suffix of the state's Lua hooks, e.g. `main_menu` for `on_enter_main_menu`
+    pub fn hook_suffix(self) -> &'static str {
+        match self {
+            GameState::Loading => "loading",
+            GameState::MainMenu => "main_menu",
+            GameState::InGame => "in_game",
+        }
+    }
+}
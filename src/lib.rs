@@ -1,9 +1,13 @@
 //! RevGame - Bevy game client
 
-#[cfg(feature = "graphics")]
+pub mod api;
+pub mod config;
+#[cfg(feature = "engine")]
 pub mod game;
 #[cfg(feature = "scripting")]
 pub mod scripting;
 
-#[cfg(feature = "graphics")]
+#[cfg(feature = "engine")]
 pub use game::GameState;
+
+pub use config::GameConfig;
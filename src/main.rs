@@ -3,20 +3,51 @@ use bevy::render::{
     settings::{Backends, RenderCreation, WgpuSettings},
     RenderPlugin,
 };
-use revgame::{game, GameState};
-
-#[cfg(feature = "scripting")]
-use revgame::scripting::check_script_changes;
+use revgame::{game, GameConfig, GameState};
 
 fn main() {
+    let (config, args) = match GameConfig::load_with_args(std::env::args().skip(1)) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("revgame: {}", e);
+            std::process::exit(2);
+        }
+    };
+    match args.first().map(String::as_str) {
+        None => {}
+        #[cfg(feature = "scripting")]
+        Some("lua-api") => {
+            if let Err(e) = lua_api(&config, &args[1..]) {
+                eprintln!("revgame lua-api: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(feature = "scripting")]
+        Some("test-scripts") => {
+            match test_scripts(&config) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("revgame test-scripts: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(arg) => {
+            eprintln!("revgame: unexpected argument `{}`", arg);
+            std::process::exit(2);
+        }
+    }
+
     let mut app = App::new();
 
     app.add_plugins(
         DefaultPlugins
             .set(WindowPlugin {
                 primary_window: Some(Window {
-                    title: "RevGame".to_string(),
-                    resolution: (1280.0, 720.0).into(),
+                    title: config.window.title.clone(),
+                    resolution: (config.window.width, config.window.height).into(),
                     ..default()
                 }),
                 ..default()
@@ -29,6 +60,7 @@ fn main() {
                 ..default()
             }),
     )
+    .insert_resource(config)
     // Initialize game state
     .init_state::<GameState>()
     // Setup systems
@@ -36,60 +68,88 @@ fn main() {
 
     // Use Lua scripting if enabled, otherwise use Rust systems
     #[cfg(feature = "scripting")]
-    {
-        app.add_systems(Startup, game::init_lua_scripting)
-            .add_systems(
-                OnEnter(GameState::InGame),
-                (game::lua_spawn_world, game::lua_spawn_player),
-            )
-            .add_systems(
-                Update,
-                (
-                    check_script_changes,
-                    game::lua_update_time,
-                    game::lua_update_input,
-                    game::lua_sync_positions,
-                    game::lua_update_player,
-                    game::lua_update_healthbar,
-                    game::lua_update_camera,
-                    game::lua_process_commands,
-                )
-                    .chain()
-                    .run_if(in_state(GameState::InGame)),
-            )
-            .add_systems(
-                OnExit(GameState::InGame),
-                (game::despawn_world, game::despawn_player),
-            );
-    }
+    app.add_plugins(game::ScriptedGameplayPlugin);
 
     #[cfg(not(feature = "scripting"))]
-    {
-        app.add_systems(
-            OnEnter(GameState::InGame),
-            (game::spawn_world, game::spawn_player, game::spawn_agent),
-        )
-        .add_systems(
-            Update,
-            (
-                game::player_input,
-                game::stamina_system,
-                game::player_movement,
-                game::agent_behavior,
-                game::camera_follow,
-            )
-                .chain()
-                .run_if(in_state(GameState::InGame)),
-        )
-        .add_systems(
-            OnExit(GameState::InGame),
-            (game::despawn_world, game::despawn_player, game::despawn_agents),
-        );
-    }
+    app.add_plugins(game::NativeGameplayPlugin);
 
     app.run();
 }
 
+/// `revgame lua-api [--stubs FILE] [--docs FILE] [--check]`: writes the LuaLS stubs
+/// and Markdown reference for the Lua bindings, or with `--check` fails if the
+/// files on disk are out of date
+#[cfg(feature = "scripting")]
+fn lua_api(config: &GameConfig, args: &[String]) -> Result<(), String> {
+    use revgame::scripting::{
+        api_mismatches, lua_api_markdown, lua_stubs, setup_lua_bindings, LuaGameState,
+        LuaRuntime, LUA_STUBS_FILE,
+    };
+    use std::path::PathBuf;
+
+    let mut stubs = config.scripting.scripts_dir.join(LUA_STUBS_FILE);
+    let mut docs = PathBuf::from("docs/lua-api.md");
+    let mut check = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stubs" => stubs = args.next().ok_or("missing value for `--stubs`")?.into(),
+            "--docs" => docs = args.next().ok_or("missing value for `--docs`")?.into(),
+            "--check" => check = true,
+            other => return Err(format!("unexpected argument `{}`", other)),
+        }
+    }
+
+    // The reference must describe exactly what the runtime registers
+    let runtime = LuaRuntime::new().map_err(|e| e.to_string())?;
+    setup_lua_bindings(&runtime.lua(), LuaGameState::new()).map_err(|e| e.to_string())?;
+    let mismatches = api_mismatches(&runtime.lua()).map_err(|e| e.to_string())?;
+    if !mismatches.is_empty() {
+        return Err(format!(
+            "LUA_BINDINGS is out of sync with the runtime:\n  {}",
+            mismatches.join("\n  ")
+        ));
+    }
+
+    for (path, content) in [(stubs, lua_stubs()), (docs, lua_api_markdown())] {
+        if check {
+            let current = std::fs::read_to_string(&path).unwrap_or_default();
+            if current != content {
+                return Err(format!("{} is out of date; run `revgame lua-api`", path.display()));
+            }
+            continue;
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&path, content)
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        println!("wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// `revgame test-scripts`: runs the Lua tests in `<scripts_dir>/tests` headlessly.
+/// Returns whether they all passed.
+#[cfg(feature = "scripting")]
+fn test_scripts(config: &GameConfig) -> std::io::Result<bool> {
+    let results = revgame::scripting::run_lua_tests(&config.scripting.scripts_dir)?;
+    let mut failed = 0;
+    for result in &results {
+        let file = result.file.file_name().unwrap_or_default().to_string_lossy();
+        match &result.failure {
+            None => println!("ok      {} > {}", file, result.name),
+            Some(failure) => {
+                failed += 1;
+                println!("FAILED  {} > {}\n        {}", file, result.name, failure);
+            }
+        }
+    }
+    println!("\n{} passed, {} failed", results.len() - failed, failed);
+    Ok(failed == 0)
+}
+
 fn setup(mut commands: Commands, mut next_state: ResMut<NextState<GameState>>) {
     // Spawn a 2D camera
     commands.spawn(Camera2d);
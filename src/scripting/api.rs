@@ -0,0 +1,639 @@
+use mlua::{Function, Lua, Result as LuaResult, Value};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+use super::sandbox::{new_sandboxed_lua, ScriptLimits};
+
+/// File name of the generated LuaLS annotations
+pub const LUA_STUBS_FILE: &str = "revgame.d.lua";
+
+/// A parameter or return value of a Lua binding. Types use LuaLS syntax; a
+/// trailing `?` marks an optional parameter or a value that may be nil.
+#[derive(Debug, Clone, Copy)]
+pub struct LuaParam {
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+/// A global function the engine provides to scripts
+#[derive(Debug, Clone, Copy)]
+pub struct LuaBinding {
+    pub name: &'static str,
+    /// Section of the API reference the binding is listed under
+    pub category: &'static str,
+    pub doc: &'static str,
+    pub params: &'static [LuaParam],
+    pub returns: &'static [LuaParam],
+}
+
+/// A table shape bindings accept or return, documented as a LuaLS class
+#[derive(Debug, Clone, Copy)]
+pub struct LuaClass {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub fields: &'static [LuaParam],
+}
+
+/// A named union type, documented as a LuaLS alias
+#[derive(Debug, Clone, Copy)]
+pub struct LuaAlias {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub ty: &'static str,
+}
+
+const fn p(name: &'static str, ty: &'static str) -> LuaParam {
+    LuaParam { name, ty }
+}
+
+const ENTITY: LuaParam = p("entity_id", "integer");
+
+/// Named types used by the bindings
+pub const LUA_ALIASES: &[LuaAlias] = &[LuaAlias {
+    name: "Color",
+    doc:
+        "`{r, g, b[, a]}`, `{r=, g=, b=[, a=]}` or a `\"#rrggbb[aa]\"` string; components are 0..1",
+    ty: "number[]|{r: number, g: number, b: number, a: number?}|string",
+}];
+
+/// Every table shape used by the bindings
+pub const LUA_CLASSES: &[LuaClass] = &[
+    LuaClass {
+        name: "SpriteSpec",
+        doc: "Sprite part of a `spawn` table",
+        fields: &[
+            p("size", "number|number[]|{width: number, height: number}?"),
+            p("color", "Color?"),
+            p("image", "string?"),
+            p("flip_x", "boolean?"),
+            p("flip_y", "boolean?"),
+        ],
+    },
+    LuaClass {
+        name: "TransformSpec",
+        doc: "Placement part of a `spawn` table; `rotation` is in radians",
+        fields: &[
+            p("x", "number?"),
+            p("y", "number?"),
+            p("z", "number?"),
+            p("rotation", "number?"),
+            p("scale", "number|number[]|{x: number, y: number}?"),
+        ],
+    },
+    LuaClass {
+        name: "SpawnSpec",
+        doc: "Everything `spawn` accepts; unknown fields are an error",
+        fields: &[
+            p("sprite", "SpriteSpec?"),
+            p("transform", "TransformSpec?"),
+            p(
+                "tags",
+                "(\"player\"|\"camera_target\"|\"world_element\")[]?",
+            ),
+            p("health", "number|{current: number?, max: number}?"),
+            p("velocity", "number[]|{x: number, y: number}?"),
+            p("script", "string?"),
+        ],
+    },
+    LuaClass {
+        name: "ScriptInstance",
+        doc: "The `self` table of a script attached with `spawn{ script = ... }`; \
+              missing fields fall back to the table the script returned",
+        fields: &[p("id", "integer")],
+    },
+    LuaClass {
+        name: "CommandFailure",
+        doc: "Payload of the `command_failed` event, sent the frame after a queued command \
+              (`set_health`, `set_position`, ...) could not be applied",
+        fields: &[
+            p("command", "string"),
+            p("id", "integer?"),
+            p("message", "string"),
+        ],
+    },
+];
+
+/// Every global the engine registers, in reference order
+pub const LUA_BINDINGS: &[LuaBinding] = &[
+    // Modules and hot reload
+    LuaBinding {
+        name: "require",
+        category: "Modules and hot reload",
+        doc: "Loads a module from the scripts directory (`ai.enemy` is `ai/enemy.lua`) and returns what it returned",
+        params: &[p("name", "string")],
+        returns: &[p("module", "any")],
+    },
+    LuaBinding {
+        name: "persist",
+        category: "Modules and hot reload",
+        doc: "Returns the state table stored under `key`, created from `defaults` the first time; survives hot reload",
+        params: &[p("key", "string"), p("defaults", "table?")],
+        returns: &[p("state", "table")],
+    },
+    LuaBinding {
+        name: "on_before_reload",
+        category: "Modules and hot reload",
+        doc: "Runs `func(module)` before the loading script is hot-reloaded; if it fails, the old version stays loaded",
+        params: &[p("func", "fun(module: string)")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "on_after_reload",
+        category: "Modules and hot reload",
+        doc: "Runs `func(module)` after the loading script was hot-reloaded",
+        params: &[p("func", "fun(module: string)")],
+        returns: &[],
+    },
+    // Events
+    LuaBinding {
+        name: "on",
+        category: "Events",
+        doc: "Subscribes `func` to an event; the engine sends `health_changed` and \
+              `command_failed` (a `CommandFailure`)",
+        params: &[p("event", "string"), p("func", "fun(payload: any)")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "off",
+        category: "Events",
+        doc: "Unsubscribes `func`; returns whether it was subscribed",
+        params: &[p("event", "string"), p("func", "function")],
+        returns: &[p("removed", "boolean")],
+    },
+    LuaBinding {
+        name: "emit",
+        category: "Events",
+        doc: "Queues an event for handlers and Rust; delivered once per frame",
+        params: &[p("event", "string"), p("payload", "any?")],
+        returns: &[],
+    },
+    // Systems and tasks
+    LuaBinding {
+        name: "register_system",
+        category: "Systems and tasks",
+        doc: "Runs `func(dt)` every frame in a stage (`input`, `simulation`, `late` or `camera`); lower `order` runs first",
+        params: &[
+            p("name", "string"),
+            p("stage", "\"input\"|\"simulation\"|\"late\"|\"camera\""),
+            p("func", "fun(dt: number)"),
+            p("order", "integer?"),
+        ],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "unregister_system",
+        category: "Systems and tasks",
+        doc: "Removes a system; returns whether it existed",
+        params: &[p("name", "string")],
+        returns: &[p("removed", "boolean")],
+    },
+    LuaBinding {
+        name: "spawn_task",
+        category: "Systems and tasks",
+        doc: "Runs `func` as a coroutine starting next frame; cancelled when `owner` despawns",
+        params: &[p("func", "function"), p("owner", "integer?")],
+        returns: &[p("task_id", "integer")],
+    },
+    LuaBinding {
+        name: "cancel_task",
+        category: "Systems and tasks",
+        doc: "Stops a task; returns whether it was still running",
+        params: &[p("task_id", "integer")],
+        returns: &[p("cancelled", "boolean")],
+    },
+    LuaBinding {
+        name: "wait",
+        category: "Systems and tasks",
+        doc: "Inside a task: pauses for `seconds` of game time",
+        params: &[p("seconds", "number?")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "wait_frames",
+        category: "Systems and tasks",
+        doc: "Inside a task: pauses for `frames` frames (default 1)",
+        params: &[p("frames", "integer?")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "wait_until",
+        category: "Systems and tasks",
+        doc: "Inside a task: pauses until `predicate()` returns true, checked once per frame",
+        params: &[p("predicate", "fun(): boolean")],
+        returns: &[],
+    },
+    // Entities
+    LuaBinding {
+        name: "spawn",
+        category: "Entities",
+        doc: "Spawns an entity described by a table; errors name the invalid field",
+        params: &[p("spec", "SpawnSpec")],
+        returns: &[ENTITY],
+    },
+    LuaBinding {
+        name: "spawn_sprite",
+        category: "Entities",
+        doc: "Spawns a colored rectangle (prefer `spawn`)",
+        params: &[
+            p("width", "number"),
+            p("height", "number"),
+            p("r", "number"),
+            p("g", "number"),
+            p("b", "number"),
+            p("x", "number"),
+            p("y", "number"),
+            p("z", "number"),
+        ],
+        returns: &[ENTITY],
+    },
+    LuaBinding {
+        name: "despawn",
+        category: "Entities",
+        doc: "Despawns an entity and its children; returns false if it was not alive",
+        params: &[ENTITY],
+        returns: &[p("despawned", "boolean")],
+    },
+    LuaBinding {
+        name: "is_alive",
+        category: "Entities",
+        doc: "Whether an entity has been spawned and not despawned",
+        params: &[ENTITY],
+        returns: &[p("alive", "boolean")],
+    },
+    LuaBinding {
+        name: "get_instance",
+        category: "Entities",
+        doc: "The `self` table of the script attached to an entity",
+        params: &[ENTITY],
+        returns: &[p("instance", "ScriptInstance?")],
+    },
+    LuaBinding {
+        name: "get_position",
+        category: "Entities",
+        doc: "Position at the start of the frame (0, 0 if unknown)",
+        params: &[ENTITY],
+        returns: &[p("x", "number"), p("y", "number")],
+    },
+    LuaBinding {
+        name: "set_position",
+        category: "Entities",
+        doc: "Moves an entity",
+        params: &[ENTITY, p("x", "number"), p("y", "number")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "set_velocity",
+        category: "Entities",
+        doc: "Sets an entity's velocity in pixels per second",
+        params: &[ENTITY, p("vx", "number"), p("vy", "number")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "get_health",
+        category: "Entities",
+        doc: "Current and max health at the start of the frame (0, 0 if the entity has none)",
+        params: &[ENTITY],
+        returns: &[p("current", "number"), p("max", "number")],
+    },
+    LuaBinding {
+        name: "set_health",
+        category: "Entities",
+        doc: "Sets current health, clamped to 0..max",
+        params: &[ENTITY, p("health", "number")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "set_sprite_size",
+        category: "Entities",
+        doc: "Resizes an entity's sprite",
+        params: &[ENTITY, p("width", "number"), p("height", "number")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "mark_as_player",
+        category: "Entities",
+        doc: "Makes an entity the player (prefer the `player` spawn tag)",
+        params: &[ENTITY],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "get_player",
+        category: "Entities",
+        doc: "The player entity, while it is alive",
+        params: &[],
+        returns: &[p("entity_id", "integer?")],
+    },
+    LuaBinding {
+        name: "mark_as_camera_target",
+        category: "Entities",
+        doc: "Tags an entity for the camera to follow (prefer the `camera_target` spawn tag)",
+        params: &[ENTITY],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "mark_as_world_element",
+        category: "Entities",
+        doc: "Tags an entity as part of the world (prefer the `world_element` spawn tag)",
+        params: &[ENTITY],
+        returns: &[],
+    },
+    // Camera and time
+    LuaBinding {
+        name: "get_camera_position",
+        category: "Camera and time",
+        doc: "Camera position at the start of the frame",
+        params: &[],
+        returns: &[p("x", "number"), p("y", "number")],
+    },
+    LuaBinding {
+        name: "set_camera_position",
+        category: "Camera and time",
+        doc: "Moves the camera",
+        params: &[p("x", "number"), p("y", "number")],
+        returns: &[],
+    },
+    LuaBinding {
+        name: "get_delta_time",
+        category: "Camera and time",
+        doc: "Seconds since the last frame",
+        params: &[],
+        returns: &[p("dt", "number")],
+    },
+    // Game state
+    LuaBinding {
+        name: "get_game_state",
+        category: "Game state",
+        doc: "The game state (`MainMenu`, `InGame`, ...); entering a state calls the global \
+              `on_enter_<state>()` if defined (e.g. `on_enter_main_menu`), leaving it `on_exit_<state>()`",
+        params: &[],
+        returns: &[p("state", "string?")],
+    },
+    LuaBinding {
+        name: "set_game_state",
+        category: "Game state",
+        doc: "Switches to another game state at the end of the frame; entering `InGame` again \
+              restarts every script with fresh `persist` state",
+        params: &[p("state", "string")],
+        returns: &[],
+    },
+    // Input
+    LuaBinding {
+        name: "is_key_pressed",
+        category: "Input",
+        doc: "Whether a key is held; accepts `KeyW` or `W`, `ArrowUp` or `Up`, `ShiftLeft` or `Shift`",
+        params: &[p("key", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_key_just_pressed",
+        category: "Input",
+        doc: "Whether a key went down this frame",
+        params: &[p("key", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_key_just_released",
+        category: "Input",
+        doc: "Whether a key went up this frame",
+        params: &[p("key", "string")],
+        returns: &[p("released", "boolean")],
+    },
+    LuaBinding {
+        name: "is_mouse_pressed",
+        category: "Input",
+        doc: "Whether a mouse button (`Left`, `Right`, `Middle`) is held",
+        params: &[p("button", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_mouse_just_pressed",
+        category: "Input",
+        doc: "Whether a mouse button went down this frame",
+        params: &[p("button", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_mouse_just_released",
+        category: "Input",
+        doc: "Whether a mouse button went up this frame",
+        params: &[p("button", "string")],
+        returns: &[p("released", "boolean")],
+    },
+    LuaBinding {
+        name: "get_cursor_position",
+        category: "Input",
+        doc: "Cursor position in world coordinates; nil outside the window",
+        params: &[],
+        returns: &[p("x", "number?"), p("y", "number?")],
+    },
+    LuaBinding {
+        name: "get_cursor_screen_position",
+        category: "Input",
+        doc: "Cursor position in window coordinates (origin top-left); nil outside the window",
+        params: &[],
+        returns: &[p("x", "number?"), p("y", "number?")],
+    },
+    LuaBinding {
+        name: "get_gamepad_count",
+        category: "Input",
+        doc: "Number of connected gamepads",
+        params: &[],
+        returns: &[p("count", "integer")],
+    },
+    LuaBinding {
+        name: "is_gamepad_pressed",
+        category: "Input",
+        doc: "Whether a gamepad button (`South` or `A`, `DPadUp`, ...) is held; gamepads are indexed from 1",
+        params: &[p("gamepad", "integer"), p("button", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_gamepad_just_pressed",
+        category: "Input",
+        doc: "Whether a gamepad button went down this frame",
+        params: &[p("gamepad", "integer"), p("button", "string")],
+        returns: &[p("pressed", "boolean")],
+    },
+    LuaBinding {
+        name: "is_gamepad_just_released",
+        category: "Input",
+        doc: "Whether a gamepad button went up this frame",
+        params: &[p("gamepad", "integer"), p("button", "string")],
+        returns: &[p("released", "boolean")],
+    },
+    LuaBinding {
+        name: "get_gamepad_axis",
+        category: "Input",
+        doc: "Axis value (`LeftStickX`, `RightStickY`, ...); 0 for unknown gamepads",
+        params: &[p("gamepad", "integer"), p("axis", "string")],
+        returns: &[p("value", "number")],
+    },
+    // Debugging
+    LuaBinding {
+        name: "log",
+        category: "Debugging",
+        doc: "Writes a message to the game log",
+        params: &[p("message", "string")],
+        returns: &[],
+    },
+];
+
+/// Looks up a binding by name
+pub fn lua_binding(name: &str) -> Option<&'static LuaBinding> {
+    LUA_BINDINGS.iter().find(|binding| binding.name == name)
+}
+
+/// Sets a global binding. Fails if `LUA_BINDINGS` has no entry for it, so a new
+/// binding cannot be left out of the generated reference.
+pub(crate) fn register_binding(lua: &Lua, name: &str, func: Function) -> LuaResult<()> {
+    if lua_binding(name).is_none() {
+        return Err(mlua::Error::RuntimeError(format!(
+            "binding `{}` has no entry in LUA_BINDINGS (src/scripting/api.rs)",
+            name
+        )));
+    }
+    lua.globals().set(name, func)
+}
+
+/// Differences between the functions a set-up runtime defines and `LUA_BINDINGS`:
+/// engine globals with no entry, and entries with no global. Lua standard library
+/// functions are ignored. Run it before loading game scripts, whose own globals
+/// would otherwise be reported.
+pub fn api_mismatches(lua: &Lua) -> LuaResult<Vec<String>> {
+    let baseline = new_sandboxed_lua(ScriptLimits::default())?;
+    let builtins = function_globals(&baseline)?;
+    let defined = function_globals(lua)?;
+
+    let mut mismatches = Vec::new();
+    for name in defined.difference(&builtins) {
+        if lua_binding(name).is_none() {
+            mismatches.push(format!("`{}` is registered but not documented", name));
+        }
+    }
+    for binding in LUA_BINDINGS {
+        if !defined.contains(binding.name) {
+            mismatches.push(format!(
+                "`{}` is documented but not registered",
+                binding.name
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+fn function_globals(lua: &Lua) -> LuaResult<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for pair in lua.globals().pairs::<Value, Value>() {
+        if let (Value::String(name), Value::Function(_)) = pair? {
+            names.insert(name.to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// LuaLS annotations (`revgame.d.lua`) for every binding and class
+pub fn lua_stubs() -> String {
+    let mut out = String::new();
+    out.push_str("---@meta revgame\n");
+    out.push_str("-- Generated from LUA_BINDINGS in src/scripting/api.rs; do not edit.\n");
+    out.push_str("-- Regenerate with `revgame lua-api`.\n");
+
+    for alias in LUA_ALIASES {
+        out.push('\n');
+        let _ = writeln!(out, "---{}", alias.doc);
+        let _ = writeln!(out, "---@alias {} {}", alias.name, alias.ty);
+    }
+
+    for class in LUA_CLASSES {
+        out.push('\n');
+        let _ = writeln!(out, "---{}", class.doc);
+        let _ = writeln!(out, "---@class {}", class.name);
+        for field in class.fields {
+            let _ = writeln!(out, "---@field {}", annotated(field));
+        }
+    }
+
+    for binding in LUA_BINDINGS {
+        out.push('\n');
+        let _ = writeln!(out, "---{}", binding.doc);
+        for param in binding.params {
+            let _ = writeln!(out, "---@param {}", annotated(param));
+        }
+        for ret in binding.returns {
+            let _ = writeln!(out, "---@return {} {}", ret.ty, ret.name);
+        }
+        let params: Vec<_> = binding.params.iter().map(|param| param.name).collect();
+        let _ = writeln!(out, "function {}({}) end", binding.name, params.join(", "));
+    }
+    out
+}
+
+/// `name type`, or `name? type` for optional values: LuaLS would read a trailing `?`
+/// on a union as applying to its last member only
+fn annotated(param: &LuaParam) -> String {
+    match param.ty.strip_suffix('?') {
+        Some(ty) => format!("{}? {}", param.name, ty),
+        None => format!("{} {}", param.name, param.ty),
+    }
+}
+
+/// Markdown API reference for every binding and class, grouped by category
+pub fn lua_api_markdown() -> String {
+    let mut out = String::new();
+    out.push_str("# Lua API reference\n\n");
+    out.push_str(
+        "Generated from `LUA_BINDINGS` in `src/scripting/api.rs` by `revgame lua-api`; do not edit.\n",
+    );
+
+    let mut categories: Vec<&str> = Vec::new();
+    for binding in LUA_BINDINGS {
+        if !categories.contains(&binding.category) {
+            categories.push(binding.category);
+        }
+    }
+
+    for category in categories {
+        let _ = write!(out, "\n## {}\n", category);
+        for binding in LUA_BINDINGS.iter().filter(|b| b.category == category) {
+            let params: Vec<_> = binding
+                .params
+                .iter()
+                .map(|param| format!("{}: {}", param.name, param.ty))
+                .collect();
+            let _ = write!(out, "\n### `{}({})`", binding.name, params.join(", "));
+            if !binding.returns.is_empty() {
+                let returns: Vec<_> = binding
+                    .returns
+                    .iter()
+                    .map(|ret| format!("{}: {}", ret.name, ret.ty))
+                    .collect();
+                let _ = write!(out, " → `{}`", returns.join(", "));
+            }
+            let _ = write!(out, "\n\n{}.\n", binding.doc);
+        }
+    }
+
+    out.push_str("\n## Types\n");
+    for alias in LUA_ALIASES {
+        let _ = write!(
+            out,
+            "\n### `{}`\n\n{}.\n\n`{}`\n",
+            alias.name, alias.doc, alias.ty
+        );
+    }
+    for class in LUA_CLASSES {
+        let _ = write!(out, "\n### `{}`\n\n{}.\n", class.name, class.doc);
+        if !class.fields.is_empty() {
+            out.push_str("\n| Field | Type |\n| --- | --- |\n");
+            for field in class.fields {
+                let _ = writeln!(
+                    out,
+                    "| `{}` | `{}` |",
+                    field.name,
+                    field.ty.replace('|', "\\|")
+                );
+            }
+        }
+    }
+    out
+}
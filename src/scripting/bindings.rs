@@ -1,162 +1,241 @@
-use bevy::prelude::*;
-use mlua::{Lua, Result as LuaResult};
-use std::sync::{Arc, RwLock};
+use mlua::{AppDataRef, AppDataRefMut, FromLuaMulti, IntoLuaMulti, Lua, LuaSerdeExt};
+use mlua::{Result as LuaResult, Table};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+use tracing::info;
+
+use super::api::register_binding;
+use super::{GamepadState, InputState, LuaCommand, PendingSpawn, SpawnTag};
+
+/// Hasher for Lua ids: small sequential integers that need no DoS protection
+#[derive(Default)]
+struct LuaIdHasher(u64);
+
+impl Hasher for LuaIdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
 
-/// Shared game state accessible from Lua
-#[derive(Clone, Resource)]
-pub struct LuaGameState {
-    inner: Arc<RwLock<LuaGameStateInner>>,
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(u64::from(*byte));
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0 ^ n).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
 }
 
-struct LuaGameStateInner {
-    /// Pending sprite spawns (width, height, r, g, b, x, y, z)
-    pending_spawns: Vec<PendingSpawn>,
-    /// Entity positions to set
-    position_updates: Vec<(u32, f32, f32)>,
-    /// Entity velocities to set
-    velocity_updates: Vec<(u32, f32, f32)>,
-    /// Camera position to set
-    camera_position: Option<(f32, f32)>,
-    /// Entities to mark as player
-    mark_player: Vec<u32>,
-    /// Entities to mark as camera target
-    mark_camera_target: Vec<u32>,
-    /// Entities to mark as world element
-    mark_world_element: Vec<u32>,
-    /// Entity ID counter
-    next_entity_id: u32,
-    /// Spawned entity map (Lua ID -> Bevy Entity)
-    entity_map: std::collections::HashMap<u32, Entity>,
-    /// Current delta time
+type LuaIdMap<V> = HashMap<u32, V, BuildHasherDefault<LuaIdHasher>>;
+type LuaIdSet = HashSet<u32, BuildHasherDefault<LuaIdHasher>>;
+
+/// What scripts read: the game as it was when the frame was published
+#[derive(Default)]
+struct FrameSnapshot {
     delta_time: f32,
-    /// Current keyboard state
-    keys_pressed: std::collections::HashSet<String>,
-    /// Entity positions (for reading)
-    entity_positions: std::collections::HashMap<u32, (f32, f32)>,
-    /// Camera position (for reading)
-    current_camera_pos: (f32, f32),
-    /// Health updates from Lua (entity_id, new_current_health)
-    health_updates: Vec<(u32, f32)>,
-    /// Entity health values synced from Bevy (lua_id -> (current, max))
-    entity_health: std::collections::HashMap<u32, (f32, f32)>,
-    /// Sprite size updates from Lua (entity_id, width, height)
-    size_updates: Vec<(u32, f32, f32)>,
+    /// Keyboard, mouse and gamepad state for the frame
+    input: InputState,
+    camera_position: (f32, f32),
+    positions: LuaIdMap<(f32, f32)>,
+    /// lua_id -> (current, max)
+    health: LuaIdMap<(f32, f32)>,
 }
 
-#[derive(Clone)]
-pub struct PendingSpawn {
-    pub lua_id: u32,
-    pub width: f32,
-    pub height: f32,
-    pub color: Color,
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+/// What Rust reported since the last `publish_frame`
+#[derive(Default)]
+struct FrameUpdate {
+    delta_time: Option<f32>,
+    input: Option<InputState>,
+    camera_position: Option<(f32, f32)>,
+    positions: Vec<(u32, f32, f32)>,
+    health: Vec<(u32, f32, f32)>,
+}
+
+/// Game state the bindings work on. `setup_lua_bindings` moves it into the Lua
+/// VM; Rust reaches it through `LuaRuntime::with_game_state`.
+///
+/// Scripts read a snapshot that only changes in `publish_frame`, so every script
+/// sees the same frame no matter when it runs. What they ask for is queued as
+/// commands for Rust to drain with `take_commands`. Nothing is locked: the state
+/// is only touched from whichever thread is running Lua.
+pub struct LuaGameState {
+    frame: FrameSnapshot,
+    pending: FrameUpdate,
+    /// Commands scripts queued since the last `take_commands`, in call order
+    commands: Vec<LuaCommand>,
+    /// The entity most recently marked as player, while it is alive
+    player_id: Option<u32>,
+    /// Entity ID counter
+    next_entity_id: u32,
+    /// Lua ids that have been spawned and not yet despawned
+    alive: LuaIdSet,
+    /// Events scripts emitted with `emit(name, payload)`, in order
+    emitted_events: Vec<(String, serde_json::Value)>,
+    /// Game state the game is in (for reading)
+    game_state: Option<String>,
+    /// Names `set_game_state` accepts; any name if empty
+    game_states: Vec<String>,
+    /// Game state scripts asked to switch to
+    requested_game_state: Option<String>,
 }
 
 impl LuaGameState {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(RwLock::new(LuaGameStateInner {
-                pending_spawns: Vec::new(),
-                position_updates: Vec::new(),
-                velocity_updates: Vec::new(),
-                camera_position: None,
-                mark_player: Vec::new(),
-                mark_camera_target: Vec::new(),
-                mark_world_element: Vec::new(),
-                next_entity_id: 1,
-                entity_map: std::collections::HashMap::new(),
-                delta_time: 0.0,
-                keys_pressed: std::collections::HashSet::new(),
-                entity_positions: std::collections::HashMap::new(),
-                current_camera_pos: (0.0, 0.0),
-                health_updates: Vec::new(),
-                entity_health: std::collections::HashMap::new(),
-                size_updates: Vec::new(),
-            })),
+            frame: FrameSnapshot::default(),
+            pending: FrameUpdate::default(),
+            commands: Vec::new(),
+            player_id: None,
+            next_entity_id: 1,
+            alive: LuaIdSet::default(),
+            emitted_events: Vec::new(),
+            game_state: None,
+            game_states: Vec::new(),
+            requested_game_state: None,
+        }
+    }
+
+    /// Makes everything reported since the last call visible to scripts
+    pub fn publish_frame(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(delta_time) = pending.delta_time {
+            self.frame.delta_time = delta_time;
+        }
+        if let Some(input) = pending.input {
+            self.frame.input = input;
+        }
+        if let Some(camera_position) = pending.camera_position {
+            self.frame.camera_position = camera_position;
+        }
+        // Skip entities despawned since they were reported
+        for (lua_id, x, y) in pending.positions {
+            if self.alive.contains(&lua_id) {
+                self.frame.positions.insert(lua_id, (x, y));
+            }
+        }
+        for (lua_id, current, max) in pending.health {
+            if self.alive.contains(&lua_id) {
+                self.frame.health.insert(lua_id, (current, max));
+            }
         }
     }
 
-    pub fn set_delta_time(&self, dt: f32) {
-        self.inner.write().unwrap().delta_time = dt;
+    pub fn set_delta_time(&mut self, dt: f32) {
+        self.pending.delta_time = Some(dt);
     }
 
-    pub fn set_key_pressed(&self, key: &str, pressed: bool) {
-        let mut inner = self.inner.write().unwrap();
+    /// Replaces the input scripts see from the next published frame on
+    pub fn set_input(&mut self, input: InputState) {
+        self.pending.input = Some(input);
+    }
+
+    pub fn set_key_pressed(&mut self, key: &str, pressed: bool) {
+        let input = self.pending_input();
         if pressed {
-            inner.keys_pressed.insert(key.to_uppercase());
+            input.keys.set(key, true, false, false);
         } else {
-            inner.keys_pressed.remove(&key.to_uppercase());
+            input.keys.unset(key);
         }
     }
 
-    pub fn clear_keys(&self) {
-        self.inner.write().unwrap().keys_pressed.clear();
+    pub fn clear_keys(&mut self) {
+        self.pending_input().keys.clear();
     }
 
-    pub fn update_entity_position(&self, lua_id: u32, x: f32, y: f32) {
-        self.inner
-            .write()
-            .unwrap()
-            .entity_positions
-            .insert(lua_id, (x, y));
+    fn pending_input(&mut self) -> &mut InputState {
+        let current = &self.frame.input;
+        self.pending.input.get_or_insert_with(|| current.clone())
     }
 
-    pub fn set_camera_position_read(&self, x: f32, y: f32) {
-        self.inner.write().unwrap().current_camera_pos = (x, y);
+    pub fn update_entity_position(&mut self, lua_id: u32, x: f32, y: f32) {
+        self.pending.positions.push((lua_id, x, y));
     }
 
-    pub fn register_entity(&self, lua_id: u32, entity: Entity) {
-        self.inner.write().unwrap().entity_map.insert(lua_id, entity);
+    pub fn set_camera_position_read(&mut self, x: f32, y: f32) {
+        self.pending.camera_position = Some((x, y));
     }
 
-    pub fn get_entity(&self, lua_id: u32) -> Option<Entity> {
-        self.inner.read().unwrap().entity_map.get(&lua_id).copied()
+    pub fn update_entity_health(&mut self, lua_id: u32, current: f32, max: f32) {
+        self.pending.health.push((lua_id, current, max));
     }
 
-    pub fn take_pending_spawns(&self) -> Vec<PendingSpawn> {
-        std::mem::take(&mut self.inner.write().unwrap().pending_spawns)
+    /// Commands queued since the last call, in the order scripts issued them
+    pub fn take_commands(&mut self) -> Vec<LuaCommand> {
+        std::mem::take(&mut self.commands)
     }
 
-    pub fn take_position_updates(&self) -> Vec<(u32, f32, f32)> {
-        std::mem::take(&mut self.inner.write().unwrap().position_updates)
+    pub fn take_emitted_events(&mut self) -> Vec<(String, serde_json::Value)> {
+        std::mem::take(&mut self.emitted_events)
     }
 
-    pub fn take_velocity_updates(&self) -> Vec<(u32, f32, f32)> {
-        std::mem::take(&mut self.inner.write().unwrap().velocity_updates)
+    /// Sets the states `set_game_state` accepts
+    pub fn set_game_states(&mut self, names: impl IntoIterator<Item = impl Into<String>>) {
+        self.game_states = names.into_iter().map(Into::into).collect();
     }
 
-    pub fn take_camera_position(&self) -> Option<(f32, f32)> {
-        self.inner.write().unwrap().camera_position.take()
+    /// Sets the state `get_game_state` returns. Takes effect at once, so enter
+    /// hooks already see the new state.
+    pub fn set_game_state_read(&mut self, name: &str) {
+        self.game_state = Some(name.to_string());
     }
 
-    pub fn take_mark_player(&self) -> Vec<u32> {
-        std::mem::take(&mut self.inner.write().unwrap().mark_player)
+    /// The last state scripts asked for with `set_game_state`, if any
+    pub fn take_requested_game_state(&mut self) -> Option<String> {
+        self.requested_game_state.take()
     }
 
-    pub fn take_mark_camera_target(&self) -> Vec<u32> {
-        std::mem::take(&mut self.inner.write().unwrap().mark_camera_target)
+    /// Hands out a Lua id for an entity spawned from Rust (e.g. with a `ScriptComponent`)
+    pub fn allocate_entity(&mut self) -> u32 {
+        let lua_id = self.next_entity_id;
+        self.next_entity_id += 1;
+        self.alive.insert(lua_id);
+        lua_id
     }
 
-    pub fn take_mark_world_element(&self) -> Vec<u32> {
-        std::mem::take(&mut self.inner.write().unwrap().mark_world_element)
+    pub fn is_alive(&self, lua_id: u32) -> bool {
+        self.alive.contains(&lua_id)
     }
 
-    pub fn update_entity_health(&self, lua_id: u32, current: f32, max: f32) {
-        self.inner
-            .write()
-            .unwrap()
-            .entity_health
-            .insert(lua_id, (current, max));
+    /// Queues a spawn. The entity reads as alive, at its spawn position, right away.
+    fn spawn(&mut self, spawn: PendingSpawn) -> u32 {
+        let lua_id = spawn.lua_id;
+        self.next_entity_id = self.next_entity_id.max(lua_id + 1);
+        self.alive.insert(lua_id);
+        if spawn.tags.contains(&SpawnTag::Player) {
+            self.player_id = Some(lua_id);
+        }
+        if let Some([current, max]) = spawn.health {
+            self.frame.health.insert(lua_id, (current, max));
+        }
+        self.frame.positions.insert(lua_id, (spawn.x, spawn.y));
+        self.commands.push(LuaCommand::Spawn(spawn));
+        lua_id
     }
 
-    pub fn take_health_updates(&self) -> Vec<(u32, f32)> {
-        std::mem::take(&mut self.inner.write().unwrap().health_updates)
+    /// Drops everything known about an entity that no longer exists
+    pub fn forget_entity(&mut self, lua_id: u32) {
+        self.alive.remove(&lua_id);
+        self.frame.positions.remove(&lua_id);
+        self.frame.health.remove(&lua_id);
+        if self.player_id == Some(lua_id) {
+            self.player_id = None;
+        }
     }
 
-    pub fn take_size_updates(&self) -> Vec<(u32, f32, f32)> {
-        std::mem::take(&mut self.inner.write().unwrap().size_updates)
+    /// Forgets all entities, e.g. when the game world is torn down
+    pub fn clear_entities(&mut self) {
+        self.alive.clear();
+        self.frame.positions.clear();
+        self.frame.health.clear();
+        self.pending.positions.clear();
+        self.pending.health.clear();
+        self.player_id = None;
+        self.commands.clear();
     }
 }
 
@@ -166,163 +245,227 @@ impl Default for LuaGameState {
     }
 }
 
-/// Setup Lua bindings for the game state
+/// Setup Lua bindings for the game state, which moves into the Lua VM
 pub fn setup_lua_bindings(lua: &Lua, game_state: LuaGameState) -> LuaResult<()> {
-    let globals = lua.globals();
+    lua.set_app_data(game_state);
 
-    // Clone game_state for each closure
-    let gs = game_state.clone();
-    globals.set(
+    writer(
+        lua,
         "spawn_sprite",
-        lua.create_function(move |_, (w, h, r, g, b, x, y, z): (f32, f32, f32, f32, f32, f32, f32, f32)| {
-            let mut inner = gs.inner.write().unwrap();
-            let lua_id = inner.next_entity_id;
-            inner.next_entity_id += 1;
-            inner.pending_spawns.push(PendingSpawn {
-                lua_id,
-                width: w,
-                height: h,
-                color: Color::srgb(r, g, b),
-                x,
-                y,
-                z,
-            });
-            Ok(lua_id)
-        })?,
-    )?;
-
-    let gs = game_state.clone();
-    globals.set(
-        "get_position",
-        lua.create_function(move |_, entity_id: u32| {
-            let inner = gs.inner.read().unwrap();
-            if let Some((x, y)) = inner.entity_positions.get(&entity_id) {
-                Ok((*x, *y))
-            } else {
-                Ok((0.0, 0.0))
-            }
-        })?,
+        |state, (w, h, r, g, b, x, y, z): (f32, f32, f32, f32, f32, f32, f32, f32)| {
+            let lua_id = state.next_entity_id;
+            let spawn = PendingSpawn::sprite(lua_id, [w, h], [r, g, b, 1.0], [x, y, z]);
+            Ok(state.spawn(spawn))
+        },
     )?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "set_position",
-        lua.create_function(move |_, (entity_id, x, y): (u32, f32, f32)| {
-            gs.inner.write().unwrap().position_updates.push((entity_id, x, y));
-            Ok(())
+    // Reads the spec before borrowing the state, as that may run Lua code
+    register_binding(
+        lua,
+        "spawn",
+        lua.create_function(|lua, spec: Table| {
+            let lua_id = game_state(lua)?.next_entity_id;
+            let spawn = PendingSpawn::from_table(lua_id, &spec)
+                .map_err(|e| mlua::Error::RuntimeError(format!("spawn: {}", e)))?;
+            Ok(game_state_mut(lua)?.spawn(spawn))
         })?,
     )?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "set_velocity",
-        lua.create_function(move |_, (entity_id, vx, vy): (u32, f32, f32)| {
-            gs.inner.write().unwrap().velocity_updates.push((entity_id, vx, vy));
+    reader(lua, "get_position", |state, entity_id: u32| {
+        state.frame.positions.get(&entity_id).copied().unwrap_or((0.0, 0.0))
+    })?;
+    writer(lua, "set_position", |state, (entity_id, x, y): (u32, f32, f32)| {
+        state.commands.push(LuaCommand::SetPosition { lua_id: entity_id, x, y });
+        Ok(())
+    })?;
+    writer(lua, "set_velocity", |state, (entity_id, vx, vy): (u32, f32, f32)| {
+        state.commands.push(LuaCommand::SetVelocity { lua_id: entity_id, x: vx, y: vy });
+        Ok(())
+    })?;
+
+    reader(lua, "is_key_pressed", |state, key: String| {
+        state.frame.input.keys.pressed(&key)
+    })?;
+    reader(lua, "is_key_just_pressed", |state, key: String| {
+        state.frame.input.keys.just_pressed(&key)
+    })?;
+    reader(lua, "is_key_just_released", |state, key: String| {
+        state.frame.input.keys.just_released(&key)
+    })?;
+    reader(lua, "is_mouse_pressed", |state, button: String| {
+        state.frame.input.mouse.pressed(&button)
+    })?;
+    reader(lua, "is_mouse_just_pressed", |state, button: String| {
+        state.frame.input.mouse.just_pressed(&button)
+    })?;
+    reader(lua, "is_mouse_just_released", |state, button: String| {
+        state.frame.input.mouse.just_released(&button)
+    })?;
+
+    // Returns nil when the cursor is outside the window
+    reader(lua, "get_cursor_position", |state, ()| {
+        state.frame.input.cursor_world
+    })?;
+    reader(lua, "get_cursor_screen_position", |state, ()| {
+        state.frame.input.cursor_screen
+    })?;
+    reader(lua, "get_gamepad_count", |state, ()| {
+        state.frame.input.gamepads.len()
+    })?;
+
+    // Gamepads are indexed from 1; unknown gamepads read as released / centered
+    reader(lua, "is_gamepad_pressed", |state, (index, button): (usize, String)| {
+        gamepad(&state.frame.input, index).is_some_and(|pad| pad.buttons.pressed(&button))
+    })?;
+    reader(lua, "is_gamepad_just_pressed", |state, (index, button): (usize, String)| {
+        gamepad(&state.frame.input, index).is_some_and(|pad| pad.buttons.just_pressed(&button))
+    })?;
+    reader(lua, "is_gamepad_just_released", |state, (index, button): (usize, String)| {
+        gamepad(&state.frame.input, index).is_some_and(|pad| pad.buttons.just_released(&button))
+    })?;
+    reader(lua, "get_gamepad_axis", |state, (index, axis): (usize, String)| {
+        gamepad(&state.frame.input, index).map_or(0.0, |pad| pad.axis(&axis))
+    })?;
+
+    reader(lua, "get_delta_time", |state, ()| state.frame.delta_time)?;
+    reader(lua, "get_camera_position", |state, ()| {
+        state.frame.camera_position
+    })?;
+    writer(lua, "set_camera_position", |state, (x, y): (f32, f32)| {
+        state.commands.push(LuaCommand::SetCameraPosition { x, y });
+        Ok(())
+    })?;
+
+    writer(lua, "mark_as_player", |state, entity_id: u32| {
+        state.commands.push(LuaCommand::Tag {
+            lua_id: entity_id,
+            tag: SpawnTag::Player,
+        });
+        state.player_id = Some(entity_id);
+        Ok(())
+    })?;
+    reader(lua, "get_player", |state, ()| state.player_id)?;
+    writer(lua, "mark_as_camera_target", |state, entity_id: u32| {
+        state.commands.push(LuaCommand::Tag {
+            lua_id: entity_id,
+            tag: SpawnTag::CameraTarget,
+        });
+        Ok(())
+    })?;
+    writer(lua, "mark_as_world_element", |state, entity_id: u32| {
+        state.commands.push(LuaCommand::Tag {
+            lua_id: entity_id,
+            tag: SpawnTag::WorldElement,
+        });
+        Ok(())
+    })?;
+
+    writer(lua, "set_health", |state, (entity_id, health): (u32, f32)| {
+        state.commands.push(LuaCommand::SetHealth {
+            lua_id: entity_id,
+            health,
+        });
+        Ok(())
+    })?;
+    reader(lua, "get_health", |state, entity_id: u32| {
+        state.frame.health.get(&entity_id).copied().unwrap_or((0.0, 0.0))
+    })?;
+    writer(lua, "set_sprite_size", |state, (entity_id, w, h): (u32, f32, f32)| {
+        state.commands.push(LuaCommand::SetSpriteSize {
+            lua_id: entity_id,
+            width: w,
+            height: h,
+        });
+        Ok(())
+    })?;
+
+    writer(lua, "despawn", |state, entity_id: u32| {
+        if !state.is_alive(entity_id) {
+            return Ok(false);
+        }
+        state.forget_entity(entity_id);
+        state.commands.push(LuaCommand::Despawn(entity_id));
+        Ok(true)
+    })?;
+    reader(lua, "is_alive", |state, entity_id: u32| state.is_alive(entity_id))?;
+
+    // Events are queued and delivered to handlers (and Rust) once per frame
+    register_binding(
+        lua,
+        "emit",
+        lua.create_function(|lua, (name, payload): (String, mlua::Value)| {
+            let payload: serde_json::Value = lua.from_value(payload)?;
+            game_state_mut(lua)?.emitted_events.push((name, payload));
             Ok(())
         })?,
     )?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "is_key_pressed",
-        lua.create_function(move |_, key: String| {
-            let inner = gs.inner.read().unwrap();
-            Ok(inner.keys_pressed.contains(&key.to_uppercase()))
-        })?,
-    )?;
+    reader(lua, "get_game_state", |state, ()| state.game_state.clone())?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "get_delta_time",
-        lua.create_function(move |_, ()| {
-            let inner = gs.inner.read().unwrap();
-            Ok(inner.delta_time)
-        })?,
-    )?;
-
-    let gs = game_state.clone();
-    globals.set(
-        "get_camera_position",
-        lua.create_function(move |_, ()| {
-            let inner = gs.inner.read().unwrap();
-            Ok(inner.current_camera_pos)
-        })?,
-    )?;
-
-    let gs = game_state.clone();
-    globals.set(
-        "set_camera_position",
-        lua.create_function(move |_, (x, y): (f32, f32)| {
-            gs.inner.write().unwrap().camera_position = Some((x, y));
-            Ok(())
-        })?,
-    )?;
-
-    let gs = game_state.clone();
-    globals.set(
-        "mark_as_player",
-        lua.create_function(move |_, entity_id: u32| {
-            gs.inner.write().unwrap().mark_player.push(entity_id);
-            Ok(())
-        })?,
-    )?;
+    // The switch happens at the end of the frame; the last request wins
+    writer(lua, "set_game_state", |state, name: String| {
+        if !state.game_states.is_empty() && !state.game_states.contains(&name) {
+            return Err(mlua::Error::RuntimeError(format!(
+                "set_game_state: unknown state '{}' (expected one of: {})",
+                name,
+                state.game_states.join(", ")
+            )));
+        }
+        state.requested_game_state = Some(name);
+        Ok(())
+    })?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "mark_as_camera_target",
-        lua.create_function(move |_, entity_id: u32| {
-            gs.inner.write().unwrap().mark_camera_target.push(entity_id);
+    register_binding(
+        lua,
+        "log",
+        lua.create_function(move |_, msg: String| {
+            info!("[Lua] {}", msg);
             Ok(())
         })?,
     )?;
 
-    let gs = game_state.clone();
-    globals.set(
-        "mark_as_world_element",
-        lua.create_function(move |_, entity_id: u32| {
-            gs.inner.write().unwrap().mark_world_element.push(entity_id);
-            Ok(())
-        })?,
-    )?;
+    Ok(())
+}
 
-    let gs = game_state.clone();
-    globals.set(
-        "set_health",
-        lua.create_function(move |_, (entity_id, health): (u32, f32)| {
-            gs.inner.write().unwrap().health_updates.push((entity_id, health));
-            Ok(())
-        })?,
-    )?;
+/// Registers a global that only reads the game state
+fn reader<A, R>(
+    lua: &Lua,
+    name: &str,
+    read: impl Fn(&LuaGameState, A) -> R + Send + 'static,
+) -> LuaResult<()>
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+{
+    let func = lua.create_function(move |lua, args: A| Ok(read(&game_state(lua)?, args)))?;
+    register_binding(lua, name, func)
+}
 
-    let gs = game_state.clone();
-    globals.set(
-        "get_health",
-        lua.create_function(move |_, entity_id: u32| {
-            let inner = gs.inner.read().unwrap();
-            if let Some((current, max)) = inner.entity_health.get(&entity_id) {
-                Ok((*current, *max))
-            } else {
-                Ok((0.0, 0.0))
-            }
-        })?,
-    )?;
+/// Registers a global that queues commands or otherwise changes the game state
+fn writer<A, R>(
+    lua: &Lua,
+    name: &str,
+    write: impl Fn(&mut LuaGameState, A) -> LuaResult<R> + Send + 'static,
+) -> LuaResult<()>
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+{
+    let func = lua.create_function(move |lua, args: A| write(&mut game_state_mut(lua)?, args))?;
+    register_binding(lua, name, func)
+}
 
-    let gs = game_state.clone();
-    globals.set(
-        "set_sprite_size",
-        lua.create_function(move |_, (entity_id, w, h): (u32, f32, f32)| {
-            gs.inner.write().unwrap().size_updates.push((entity_id, w, h));
-            Ok(())
-        })?,
-    )?;
+fn game_state(lua: &Lua) -> LuaResult<AppDataRef<'_, LuaGameState>> {
+    lua.app_data_ref::<LuaGameState>()
+        .ok_or_else(|| mlua::Error::RuntimeError("game bindings are not set up".to_string()))
+}
 
-    globals.set(
-        "log",
-        lua.create_function(move |_, msg: String| {
-            info!("[Lua] {}", msg);
-            Ok(())
-        })?,
-    )?;
+fn game_state_mut(lua: &Lua) -> LuaResult<AppDataRefMut<'_, LuaGameState>> {
+    lua.app_data_mut::<LuaGameState>()
+        .ok_or_else(|| mlua::Error::RuntimeError("game bindings are not set up".to_string()))
+}
 
-    Ok(())
+/// Gamepad by its 1-based Lua index
+fn gamepad(input: &InputState, index: usize) -> Option<&GamepadState> {
+    input.gamepads.get(index.checked_sub(1)?)
 }
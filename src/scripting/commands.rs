@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+use super::{PendingSpawn, SpawnTag};
+
+/// Something a script asked the game to do. Bindings queue commands in call order
+/// and the game applies them in that same order once per frame, so a command can
+/// rely on the ones before it (e.g. `set_health` right after `spawn`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaCommand {
+    Spawn(PendingSpawn),
+    /// `mark_as_player`, `mark_as_camera_target`, `mark_as_world_element`
+    Tag {
+        lua_id: u32,
+        tag: SpawnTag,
+    },
+    SetPosition {
+        lua_id: u32,
+        x: f32,
+        y: f32,
+    },
+    SetVelocity {
+        lua_id: u32,
+        x: f32,
+        y: f32,
+    },
+    /// New current health; clamped to the entity's max
+    SetHealth {
+        lua_id: u32,
+        health: f32,
+    },
+    SetSpriteSize {
+        lua_id: u32,
+        width: f32,
+        height: f32,
+    },
+    SetCameraPosition {
+        x: f32,
+        y: f32,
+    },
+    Despawn(u32),
+}
+
+impl LuaCommand {
+    /// Binding that queued the command
+    pub fn binding(&self) -> &'static str {
+        match self {
+            LuaCommand::Spawn(_) => "spawn",
+            LuaCommand::Tag { tag, .. } => match tag {
+                SpawnTag::Player => "mark_as_player",
+                SpawnTag::CameraTarget => "mark_as_camera_target",
+                SpawnTag::WorldElement => "mark_as_world_element",
+            },
+            LuaCommand::SetPosition { .. } => "set_position",
+            LuaCommand::SetVelocity { .. } => "set_velocity",
+            LuaCommand::SetHealth { .. } => "set_health",
+            LuaCommand::SetSpriteSize { .. } => "set_sprite_size",
+            LuaCommand::SetCameraPosition { .. } => "set_camera_position",
+            LuaCommand::Despawn(_) => "despawn",
+        }
+    }
+
+    /// Entity the command applies to, if any
+    pub fn lua_id(&self) -> Option<u32> {
+        match self {
+            LuaCommand::Spawn(spawn) => Some(spawn.lua_id),
+            LuaCommand::Tag { lua_id, .. }
+            | LuaCommand::SetPosition { lua_id, .. }
+            | LuaCommand::SetVelocity { lua_id, .. }
+            | LuaCommand::SetHealth { lua_id, .. }
+            | LuaCommand::SetSpriteSize { lua_id, .. } => Some(*lua_id),
+            LuaCommand::SetCameraPosition { .. } => None,
+            LuaCommand::Despawn(lua_id) => Some(*lua_id),
+        }
+    }
+
+    /// A failure of this command, to report back to scripts
+    pub fn failed(&self, message: impl Into<String>) -> LuaCommandFailure {
+        LuaCommandFailure {
+            command: self.binding().to_string(),
+            id: self.lua_id(),
+            message: message.into(),
+        }
+    }
+}
+
+/// A command that could not be applied, sent to scripts as a `command_failed` event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Event))]
+pub struct LuaCommandFailure {
+    /// Binding that queued the command, e.g. `set_health`
+    pub command: String,
+    /// Lua id of the entity it was for
+    pub id: Option<u32>,
+    pub message: String,
+}
@@ -0,0 +1,697 @@
+use mlua::{Debug as LuaDebug, Function, Lua, Result as LuaResult, Table, Value};
+use serde_json::{json, Value as Json};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use tracing::{info, warn};
+
+use super::runtime::module_for_path;
+use super::sandbox::with_budget;
+
+/// Named registry value holding the `debug` library, which scripts cannot reach
+pub(crate) const DEBUG_LIBRARY_KEY: &str = "revgame.debug";
+
+/// The only thread reported to the client; coroutines show up in its stack
+const THREAD_ID: u64 = 1;
+
+/// Debug adapter state, stored as Lua app data once the server is started
+struct Debugger {
+    requests: Receiver<Json>,
+    client: Client,
+    /// Breakpoint lines per file, keyed by `source_key`
+    breakpoints: HashMap<String, HashSet<u32>>,
+    step: Option<Step>,
+    pause_requested: bool,
+    /// `source_key` of every chunk name seen, e.g. "@scripts/player.lua"
+    sources: HashMap<String, Option<String>>,
+    /// Whether the installed hook reports line events
+    line_hook: bool,
+}
+
+/// Where a step request stops, by stack depth at the time it was made
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    In,
+    Over { depth: usize },
+    Out { depth: usize },
+}
+
+impl Step {
+    fn is_done(self, depth: usize) -> bool {
+        match self {
+            Step::In => true,
+            Step::Over { depth: start } => depth <= start,
+            Step::Out { depth: start } => depth < start,
+        }
+    }
+}
+
+/// The connected client, shared with the server thread
+#[derive(Clone, Default)]
+struct Client {
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    seq: Arc<AtomicU64>,
+}
+
+impl Client {
+    fn send(&self, mut message: Json) {
+        message["seq"] = json!(self.seq.fetch_add(1, Ordering::Relaxed) + 1);
+        let body = message.to_string();
+        let mut stream = self.stream.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(stream) = stream.as_mut() {
+            if let Err(e) = write!(stream, "Content-Length: {}\r\n\r\n{}", body.len(), body) {
+                warn!("Failed to write to the Lua debugger client: {}", e);
+            }
+        }
+    }
+
+    fn reply(&self, request: &Json, result: Result<Json, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// What the client can look at while the game is stopped
+struct Paused {
+    /// Stack depth where the game stopped, for stepping over and out
+    depth: usize,
+    frames: Vec<Frame>,
+    /// What each `variablesReference` handed out points at (id = index + 1)
+    references: Vec<Reference>,
+}
+
+struct Frame {
+    name: String,
+    path: Option<PathBuf>,
+    line: i32,
+}
+
+#[derive(Clone)]
+enum Reference {
+    Locals(usize),
+    Upvalues(usize),
+    Globals,
+    Table(Table),
+}
+
+impl Paused {
+    fn capture(lua: &Lua) -> Self {
+        let mut frames = Vec::new();
+        while let Some(debug) = lua.inspect_stack(frames.len()) {
+            let source = debug.source();
+            let path = source
+                .source
+                .as_deref()
+                .and_then(|source| source.strip_prefix('@'))
+                .map(|path| {
+                    let path = Path::new(path);
+                    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+                });
+            let name = match debug.names().name {
+                Some(name) => name.to_string(),
+                None if source.what == "main" => "main chunk".to_string(),
+                None => "?".to_string(),
+            };
+            frames.push(Frame {
+                name,
+                path,
+                line: debug.curr_line(),
+            });
+        }
+        Self {
+            depth: frames.len(),
+            frames,
+            references: Vec::new(),
+        }
+    }
+
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    /// Stack level of a `frameId` from `stackTrace`
+    fn level(&self, frame_id: &Json) -> Result<usize, String> {
+        frame_id
+            .as_u64()
+            .map(|id| id as usize)
+            .filter(|id| (1..=self.frames.len()).contains(id))
+            .map(|id| id - 1)
+            .ok_or_else(|| format!("unknown frame {}", frame_id))
+    }
+
+    /// A value as a DAP variable; tables get a reference to expand them
+    fn describe(&mut self, value: &Value) -> Json {
+        let reference = match value {
+            Value::Table(table) => self.reference(Reference::Table(table.clone())),
+            _ => 0,
+        };
+        json!({
+            "value": display(value),
+            "type": value.type_name(),
+            "variablesReference": reference,
+        })
+    }
+}
+
+enum Flow {
+    Stay,
+    Resume,
+}
+
+/// Starts listening on 127.0.0.1:`port` (0 picks a free port) and returns the port
+pub(crate) fn start_debugger(lua: &Lua, port: u16) -> io::Result<u16> {
+    if !matches!(
+        lua.named_registry_value::<Value>(DEBUG_LIBRARY_KEY),
+        Ok(Value::Table(_))
+    ) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the Lua runtime was created without `ScriptLimits::debugger`",
+        ));
+    }
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let port = listener.local_addr()?.port();
+    let (requests, received) = channel();
+    let client = Client::default();
+    let server_client = client.clone();
+    thread::Builder::new()
+        .name("lua-debugger".to_string())
+        .spawn(move || serve(listener, server_client, requests))?;
+
+    lua.set_app_data(Debugger {
+        requests: received,
+        client,
+        breakpoints: HashMap::new(),
+        step: None,
+        pause_requested: false,
+        sources: HashMap::new(),
+        line_hook: false,
+    });
+    info!("Lua debugger listening on 127.0.0.1:{}", port);
+    Ok(port)
+}
+
+/// Accepts one client at a time and forwards its requests to the game
+fn serve(listener: TcpListener, client: Client, requests: Sender<Json>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Lua debugger failed to accept a client: {}", e);
+                continue;
+            }
+        };
+        let Ok(writer) = stream.try_clone() else {
+            continue;
+        };
+        *client.stream.lock().unwrap_or_else(PoisonError::into_inner) = Some(writer);
+        info!("Lua debugger client connected");
+
+        let mut reader = BufReader::new(stream);
+        while let Ok(Some(request)) = read_message(&mut reader) {
+            if requests.send(request).is_err() {
+                return;
+            }
+        }
+
+        *client.stream.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        info!("Lua debugger client disconnected");
+        // Resumes the game if the client went away while it was stopped
+        let disconnect = json!({ "seq": 0, "type": "request", "command": "disconnect" });
+        if requests.send(disconnect).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads one `Content-Length` framed message; `None` at the end of the stream
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Handles requests that arrived while the game was running. Returns whether
+/// the hook has to be updated because line events are now needed or not.
+pub(crate) fn poll_debugger(lua: &Lua) -> bool {
+    loop {
+        let request = match lua.app_data_ref::<Debugger>() {
+            Some(debugger) => debugger.requests.try_recv(),
+            None => return false,
+        };
+        let Ok(request) = request else {
+            break;
+        };
+        handle_request(lua, &request, None);
+    }
+
+    let wanted = wants_line_events(lua);
+    let Some(mut debugger) = lua.app_data_mut::<Debugger>() else {
+        return false;
+    };
+    let changed = debugger.line_hook != wanted;
+    debugger.line_hook = wanted;
+    changed
+}
+
+/// Whether breakpoints, a step or a pause request need the line hook
+pub(crate) fn wants_line_events(lua: &Lua) -> bool {
+    lua.app_data_ref::<Debugger>().is_some_and(|debugger| {
+        debugger.pause_requested || debugger.step.is_some() || !debugger.breakpoints.is_empty()
+    })
+}
+
+/// Line hook: stops the game here if a breakpoint, step or pause says so
+pub(crate) fn on_line(lua: &Lua, debug: &LuaDebug) {
+    if let Some(reason) = stop_reason(lua, debug) {
+        pause(lua, reason);
+    }
+}
+
+fn stop_reason(lua: &Lua, debug: &LuaDebug) -> Option<&'static str> {
+    let source = {
+        let mut debugger = lua.app_data_mut::<Debugger>()?;
+        if std::mem::take(&mut debugger.pause_requested) {
+            return Some("pause");
+        }
+        if let Some(step) = debugger.step {
+            if step.is_done(stack_depth(lua)) {
+                debugger.step = None;
+                return Some("step");
+            }
+        }
+        if debugger.breakpoints.is_empty() {
+            return None;
+        }
+        debug.source().source?.to_string()
+    };
+    let line = u32::try_from(debug.curr_line()).ok()?;
+
+    // Resolving a chunk name touches the file system, so each is looked up once
+    let cached = lua
+        .app_data_ref::<Debugger>()?
+        .sources
+        .get(&source)
+        .cloned();
+    let key = match cached {
+        Some(key) => key,
+        None => {
+            let key = source
+                .strip_prefix('@')
+                .map(|path| source_key(lua, Path::new(path)));
+            lua.app_data_mut::<Debugger>()?
+                .sources
+                .insert(source, key.clone());
+            key
+        }
+    }?;
+    let debugger = lua.app_data_ref::<Debugger>()?;
+    debugger
+        .breakpoints
+        .get(&key)?
+        .contains(&line)
+        .then_some("breakpoint")
+}
+
+/// Blocks the game inside the hook until the client resumes it
+fn pause(lua: &Lua, reason: &str) {
+    let Some(client) = lua
+        .app_data_ref::<Debugger>()
+        .map(|debugger| debugger.client.clone())
+    else {
+        return;
+    };
+    let mut paused = Paused::capture(lua);
+    client.event(
+        "stopped",
+        json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+    );
+
+    loop {
+        let request = match lua.app_data_ref::<Debugger>() {
+            Some(debugger) => debugger.requests.recv(),
+            None => return,
+        };
+        // The server thread is gone, so no client can resume the game
+        let Ok(request) = request else {
+            return;
+        };
+        if let Flow::Resume = handle_request(lua, &request, Some(&mut paused)) {
+            return;
+        }
+    }
+}
+
+fn handle_request(lua: &Lua, request: &Json, mut paused: Option<&mut Paused>) -> Flow {
+    let Some(client) = lua
+        .app_data_ref::<Debugger>()
+        .map(|debugger| debugger.client.clone())
+    else {
+        return Flow::Resume;
+    };
+    let arguments = &request["arguments"];
+    let command = request["command"].as_str().unwrap_or_default();
+
+    let result = match command {
+        "initialize" => {
+            client.reply(
+                request,
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                })),
+            );
+            client.event("initialized", json!({}));
+            return Flow::Stay;
+        }
+        "attach" | "launch" | "configurationDone" | "setExceptionBreakpoints" => Ok(json!({})),
+        "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "Lua" }] })),
+        "setBreakpoints" => set_breakpoints(lua, arguments),
+        "pause" => {
+            if paused.is_none() {
+                with_debugger(lua, |debugger| debugger.pause_requested = true);
+            }
+            Ok(json!({}))
+        }
+        "continue" | "next" | "stepIn" | "stepOut" => {
+            let Some(paused) = paused else {
+                client.reply(request, Err("the game is not stopped".to_string()));
+                return Flow::Stay;
+            };
+            let step = match command {
+                "next" => Some(Step::Over {
+                    depth: paused.depth,
+                }),
+                "stepIn" => Some(Step::In),
+                "stepOut" => Some(Step::Out {
+                    depth: paused.depth,
+                }),
+                _ => None,
+            };
+            with_debugger(lua, |debugger| debugger.step = step);
+            client.reply(request, Ok(json!({ "allThreadsContinued": true })));
+            return Flow::Resume;
+        }
+        "disconnect" => {
+            with_debugger(lua, |debugger| {
+                debugger.breakpoints.clear();
+                debugger.step = None;
+                debugger.pause_requested = false;
+            });
+            client.reply(request, Ok(json!({})));
+            return Flow::Resume;
+        }
+        "stackTrace" | "scopes" | "variables" => match paused.as_deref_mut() {
+            Some(paused) => match command {
+                "stackTrace" => Ok(stack_trace(paused)),
+                "scopes" => scopes(paused, arguments),
+                _ => variables(lua, paused, arguments),
+            },
+            None => Err("the game is not stopped".to_string()),
+        },
+        "evaluate" => evaluate(lua, paused, arguments),
+        _ => Err(format!("unsupported request: {}", command)),
+    };
+    client.reply(request, result);
+    Flow::Stay
+}
+
+fn with_debugger(lua: &Lua, f: impl FnOnce(&mut Debugger)) {
+    if let Some(mut debugger) = lua.app_data_mut::<Debugger>() {
+        f(&mut debugger);
+    }
+}
+
+/// Breakpoints are keyed by module name, so a path the client sends, the path
+/// the file watcher reports and the chunk name Lua reports all agree. Files
+/// outside the scripts directory fall back to their canonical path.
+fn source_key(lua: &Lua, path: &Path) -> String {
+    module_for_path(lua, path).unwrap_or_else(|| {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        path.display().to_string()
+    })
+}
+
+fn set_breakpoints(lua: &Lua, arguments: &Json) -> Result<Json, String> {
+    let source = &arguments["source"];
+    let path = source["path"]
+        .as_str()
+        .ok_or("setBreakpoints needs a source path")?;
+    let path = Path::new(path);
+    let key = source_key(lua, path);
+    let lines: Vec<u32> = arguments["breakpoints"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        .filter_map(|line| u32::try_from(line).ok())
+        .collect();
+
+    with_debugger(lua, |debugger| {
+        if lines.is_empty() {
+            debugger.breakpoints.remove(&key);
+        } else {
+            debugger
+                .breakpoints
+                .insert(key, lines.iter().copied().collect());
+        }
+    });
+    let verified = path.is_file();
+    let breakpoints: Vec<_> = lines
+        .iter()
+        .map(|line| json!({ "verified": verified, "line": line, "source": source }))
+        .collect();
+    Ok(json!({ "breakpoints": breakpoints }))
+}
+
+fn stack_trace(paused: &Paused) -> Json {
+    let frames: Vec<_> = paused
+        .frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let mut json = json!({
+                "id": index + 1,
+                "name": frame.name,
+                "line": frame.line.max(0),
+                "column": 1,
+            });
+            if let Some(path) = &frame.path {
+                let name = path.file_name().map(|name| name.to_string_lossy());
+                json["source"] = json!({ "name": name, "path": path });
+            }
+            json
+        })
+        .collect();
+    json!({ "totalFrames": frames.len(), "stackFrames": frames })
+}
+
+fn scopes(paused: &mut Paused, arguments: &Json) -> Result<Json, String> {
+    let level = paused.level(&arguments["frameId"])?;
+    let scopes = [
+        ("Locals", Reference::Locals(level), false),
+        ("Upvalues", Reference::Upvalues(level), false),
+        ("Globals", Reference::Globals, true),
+    ]
+    .map(|(name, reference, expensive)| {
+        json!({
+            "name": name,
+            "variablesReference": paused.reference(reference),
+            "expensive": expensive,
+        })
+    });
+    Ok(json!({ "scopes": scopes }))
+}
+
+fn variables(lua: &Lua, paused: &mut Paused, arguments: &Json) -> Result<Json, String> {
+    let id = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+    let reference = paused
+        .references
+        .get(id.wrapping_sub(1))
+        .cloned()
+        .ok_or_else(|| format!("unknown variables reference {}", id))?;
+    let entries = match reference {
+        Reference::Locals(level) => frame_locals(lua, level),
+        Reference::Upvalues(level) => frame_upvalues(lua, level),
+        Reference::Globals => table_entries(lua.globals()),
+        Reference::Table(table) => table_entries(table),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let variables: Vec<_> = entries
+        .into_iter()
+        .map(|(name, value)| {
+            let mut variable = paused.describe(&value);
+            variable["name"] = json!(name);
+            variable
+        })
+        .collect();
+    Ok(json!({ "variables": variables }))
+}
+
+fn evaluate(lua: &Lua, paused: Option<&mut Paused>, arguments: &Json) -> Result<Json, String> {
+    let expression = arguments["expression"]
+        .as_str()
+        .ok_or("evaluate needs an expression")?;
+    let level = paused
+        .as_ref()
+        .and_then(|paused| paused.level(&arguments["frameId"]).ok());
+    // While stopped this runs inside the hook, where Lua turns hooks off, so the
+    // instruction limit cannot apply; a running game gets a fresh budget
+    let value = match paused {
+        Some(_) => evaluate_in_frame(lua, expression, level),
+        None => with_budget(lua, |lua| evaluate_in_frame(lua, expression, None)),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let described = match paused {
+        Some(paused) => paused.describe(&value),
+        // Nothing to expand once the game runs on
+        None => {
+            json!({ "value": display(&value), "type": value.type_name(), "variablesReference": 0 })
+        }
+    };
+    Ok(json!({
+        "result": described["value"],
+        "type": described["type"],
+        "variablesReference": described["variablesReference"],
+    }))
+}
+
+/// Evaluates an expression with the locals and upvalues of the function at
+/// stack `level` in scope. Statements are rejected as syntax errors.
+fn evaluate_in_frame(lua: &Lua, expression: &str, level: Option<usize>) -> LuaResult<Value> {
+    let env = lua.create_table()?;
+    let meta = lua.create_table()?;
+    meta.set("__index", lua.globals())?;
+    env.set_metatable(Some(meta));
+    if let Some(level) = level {
+        let upvalues = frame_upvalues(lua, level)?;
+        // Locals come last so they shadow upvalues of the same name
+        for (name, value) in upvalues.into_iter().chain(frame_locals(lua, level)?) {
+            env.raw_set(name, value)?;
+        }
+    }
+
+    lua.load(format!("return {}", expression))
+        .set_name("=evaluate")
+        .set_environment(env)
+        .eval()
+}
+
+/// Number of active functions on the stack
+fn stack_depth(lua: &Lua) -> usize {
+    (0..)
+        .take_while(|level| lua.inspect_stack(*level).is_some())
+        .count()
+}
+
+fn debug_library(lua: &Lua) -> LuaResult<Table> {
+    lua.named_registry_value(DEBUG_LIBRARY_KEY)
+}
+
+/// Locals of the function at stack `level`, in declaration order
+fn frame_locals(lua: &Lua, level: usize) -> LuaResult<Vec<(String, Value)>> {
+    let getlocal: Function = debug_library(lua)?.get("getlocal")?;
+    let mut locals = Vec::new();
+    for index in 1.. {
+        // Level 1 of `debug.getlocal` is the function the hook interrupted
+        let (name, value): (Option<String>, Value) = getlocal.call((level + 1, index))?;
+        let Some(name) = name else {
+            break;
+        };
+        // Skips internals such as "(for state)" and "(temporary)"
+        if !name.starts_with('(') {
+            locals.push((name, value));
+        }
+    }
+    Ok(locals)
+}
+
+/// Upvalues of the function at stack `level`, without `_ENV`
+fn frame_upvalues(lua: &Lua, level: usize) -> LuaResult<Vec<(String, Value)>> {
+    let debug = debug_library(lua)?;
+    let info: Option<Table> = debug.get::<Function>("getinfo")?.call((level + 1, "f"))?;
+    let Some(func) = info.map(|info| info.get::<Function>("func")).transpose()? else {
+        return Ok(Vec::new());
+    };
+    let getupvalue: Function = debug.get("getupvalue")?;
+    let mut upvalues = Vec::new();
+    for index in 1.. {
+        let (name, value): (Option<String>, Value) = getupvalue.call((func.clone(), index))?;
+        let Some(name) = name else {
+            break;
+        };
+        if name != "_ENV" {
+            upvalues.push((name, value));
+        }
+    }
+    Ok(upvalues)
+}
+
+/// Entries of a table by key, sorted by name
+fn table_entries(table: Table) -> LuaResult<Vec<(String, Value)>> {
+    let mut entries = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let name = match &key {
+            Value::String(key) => key.to_string_lossy().to_string(),
+            key => format!("[{}]", display(key)),
+        };
+        entries.push((name, value));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// Short text for a value, as shown next to its name
+fn display(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::String(text) => format!("{:?}", text.to_string_lossy().to_string()),
+        Value::Table(table) => format!(
+            "table ({} entries)",
+            table.clone().pairs::<Value, Value>().count()
+        ),
+        value => value
+            .to_string()
+            .unwrap_or_else(|_| value.type_name().to_string()),
+    }
+}
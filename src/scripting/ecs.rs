@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::{reload_changed_scripts, LuaGameState, LuaRuntime, ScriptErrors, ScriptWatcher};
+
+/// Tags a Bevy entity with the Lua id it was spawned under
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LuaEntity(pub u32);
+
+/// Maps Lua entity ids to the Bevy entities they were spawned as
+#[derive(Resource, Default)]
+pub struct LuaEntityMap {
+    entities: HashMap<u32, Entity>,
+    lua_ids: HashMap<Entity, u32>,
+}
+
+impl LuaEntityMap {
+    pub fn insert(&mut self, lua_id: u32, entity: Entity) {
+        self.entities.insert(lua_id, entity);
+        self.lua_ids.insert(entity, lua_id);
+    }
+
+    pub fn get(&self, lua_id: u32) -> Option<Entity> {
+        self.entities.get(&lua_id).copied()
+    }
+
+    /// Reverse lookup: the Lua id a Bevy entity was spawned under
+    pub fn lua_id(&self, entity: Entity) -> Option<u32> {
+        self.lua_ids.get(&entity).copied()
+    }
+
+    pub fn remove(&mut self, lua_id: u32) -> Option<Entity> {
+        let entity = self.entities.remove(&lua_id)?;
+        self.lua_ids.remove(&entity);
+        Some(entity)
+    }
+
+    /// Removes the mapping for a Bevy entity, returning its Lua id
+    pub fn remove_entity(&mut self, entity: Entity) -> Option<u32> {
+        let lua_id = self.lua_ids.remove(&entity)?;
+        self.entities.remove(&lua_id);
+        Some(lua_id)
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.lua_ids.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Entity)> + '_ {
+        self.entities.iter().map(|(id, entity)| (*id, *entity))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// Attaches a Lua script to an entity. The script returns a table of callbacks and
+/// every entity gets its own `self` table, passed to `on_spawn(self)`,
+/// `on_update(self, dt)` and `on_despawn(self)`.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ScriptComponent {
+    /// Script name, e.g. `enemy`, `enemy.lua` or `ai/enemy.lua`
+    pub script: String,
+}
+
+impl ScriptComponent {
+    pub fn new(script: impl Into<String>) -> Self {
+        Self {
+            script: script.into(),
+        }
+    }
+}
+
+/// System that checks for script changes and triggers reloads.
+/// Scripts that fail to reload are reported to `ScriptErrors`, and callbacks it
+/// disabled are re-enabled when their script reloads.
+pub fn check_script_changes(
+    watcher: Option<Res<ScriptWatcher>>,
+    mut runtime: Option<ResMut<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+) {
+    let Some(watcher) = watcher else { return };
+    let Some(ref mut runtime) = runtime else { return };
+
+    let reloaded = reload_changed_scripts(&watcher, runtime, |script, e| match errors {
+        Some(ref mut errors) => errors.report_load(script, e),
+        None => error!("Failed to reload {}: {}", script, e),
+    });
+    for script in reloaded {
+        if let Some(ref mut errors) = errors {
+            errors.script_reloaded(&script);
+        }
+    }
+}
+
+/// An event for scripts, delivered to every Lua `on(name, fn)` handler
+#[derive(Event, Debug, Clone)]
+pub struct ScriptEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+impl ScriptEvent {
+    pub fn new(name: impl Into<String>, payload: impl Serialize) -> Self {
+        Self {
+            name: name.into(),
+            payload: serde_json::to_value(payload).unwrap_or(serde_json::Value::Null),
+        }
+    }
+}
+
+/// An event a script emitted with `emit(name, payload)`
+#[derive(Event, Debug, Clone)]
+pub struct LuaEmittedEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+/// Delivers queued events to Lua handlers: first the `ScriptEvent`s sent by Rust
+/// this frame, then the events scripts emitted since the last run (which are also
+/// forwarded to Rust as `LuaEmittedEvent`s). Events emitted by handlers run next frame.
+pub fn lua_dispatch_events(
+    runtime: Option<Res<LuaRuntime>>,
+    mut errors: Option<ResMut<ScriptErrors>>,
+    mut incoming: EventReader<ScriptEvent>,
+    mut emitted: EventWriter<LuaEmittedEvent>,
+) {
+    let Some(runtime) = runtime else { return };
+    let Some(emitted_events) = runtime.with_game_state(LuaGameState::take_emitted_events) else {
+        return;
+    };
+
+    let mut queue: Vec<(String, serde_json::Value)> = incoming
+        .read()
+        .map(|event| (event.name.clone(), event.payload.clone()))
+        .collect();
+    for (name, payload) in emitted_events {
+        emitted.send(LuaEmittedEvent {
+            name: name.clone(),
+            payload: payload.clone(),
+        });
+        queue.push((name, payload));
+    }
+
+    for (name, payload) in queue {
+        let callback = format!("on({})", name);
+        let result = runtime.dispatch_event(&name, &payload, |module, e| match errors {
+            Some(ref mut errors) => errors.report(module.unwrap_or("<unknown>"), &callback, &e),
+            None => error!("Lua handler for {} failed: {}", name, e),
+        });
+        if let Err(e) = result {
+            error!("Failed to dispatch Lua event {}: {}", name, e);
+        }
+    }
+}
+
+/// Systems that connect typed Bevy events to the Lua event bus
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LuaEventSet {
+    /// Turns Bevy events into `ScriptEvent`s, before `lua_dispatch_events`. Systems
+    /// sending events added with `add_event_to_lua` must run before this set, or
+    /// scripts only see their events a frame later.
+    ToLua,
+    /// Turns `LuaEmittedEvent`s into typed events, after `lua_dispatch_events`
+    FromLua,
+}
+
+/// Connects typed Bevy events to the Lua event bus
+pub trait LuaEventAppExt {
+    /// Sends every `T` event to Lua `on(name, fn)` handlers, serialized as a table.
+    /// The forwarding system runs in `LuaEventSet::ToLua`.
+    fn add_event_to_lua<T: Event + Serialize>(&mut self, name: &str) -> &mut Self;
+
+    /// Turns Lua `emit(name, payload)` calls into `T` events, in `LuaEventSet::FromLua`.
+    /// Don't also send the same event to Lua, or scripts will see it twice.
+    fn add_event_from_lua<T: Event + DeserializeOwned>(&mut self, name: &str) -> &mut Self;
+}
+
+impl LuaEventAppExt for App {
+    fn add_event_to_lua<T: Event + Serialize>(&mut self, name: &str) -> &mut Self {
+        let name = name.to_string();
+        self.add_event::<T>()
+            .configure_sets(Update, LuaEventSet::ToLua.before(lua_dispatch_events))
+            .add_systems(
+                Update,
+                (move |mut events: EventReader<T>, mut script_events: EventWriter<ScriptEvent>| {
+                    for event in events.read() {
+                        script_events.send(ScriptEvent::new(name.as_str(), event));
+                    }
+                })
+                .in_set(LuaEventSet::ToLua),
+            )
+    }
+
+    fn add_event_from_lua<T: Event + DeserializeOwned>(&mut self, name: &str) -> &mut Self {
+        let name = name.to_string();
+        self.add_event::<T>()
+            .configure_sets(Update, LuaEventSet::FromLua.after(lua_dispatch_events))
+            .add_systems(
+                Update,
+                (move |mut emitted: EventReader<LuaEmittedEvent>, mut events: EventWriter<T>| {
+                    for event in emitted.read().filter(|e| e.name == name) {
+                        match serde_json::from_value(event.payload.clone()) {
+                            Ok(typed) => {
+                                events.send(typed);
+                            }
+                            Err(e) => {
+                                warn!("Ignoring Lua event {} with invalid payload: {}", name, e)
+                            }
+                        }
+                    }
+                })
+                .in_set(LuaEventSet::FromLua),
+            )
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// How many errors `ScriptErrors` keeps for querying
+const MAX_RECENT_ERRORS: usize = 64;
+
+/// Script of a failing callback whose defining script is not known, e.g. a
+/// global that is missing because it was renamed
+pub const UNKNOWN_SCRIPT: &str = "<unknown>";
+
+/// Callback that errors raised while loading or reloading a script are filed under
+pub const LOAD_CALLBACK: &str = "<load>";
+
+/// A Lua error raised by a script callback
+#[derive(Debug, Clone)]
+pub struct ScriptError {
+    /// Module the failing callback was defined in (e.g. `player`)
+    pub script: String,
+    /// Callback that was being run (e.g. `spawn_player`)
+    pub callback: String,
+    /// Line the error was raised at, if Lua reported one
+    pub line: Option<u32>,
+    /// Error message without location prefix or traceback
+    pub message: String,
+    pub traceback: Option<String>,
+    /// How many times this exact error happened within the repeat window
+    pub count: u32,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+/// Collects errors from Lua callbacks instead of discarding them.
+///
+/// Identical errors repeated within `repeat_window` are folded into one entry
+/// (and logged once). With `disable_after` set, a callback that fails that many
+/// times in a row is disabled until its script is hot-reloaded.
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Resource))]
+pub struct ScriptErrors {
+    recent: VecDeque<ScriptError>,
+    repeat_window: Duration,
+    disable_after: Option<u32>,
+    /// Consecutive failures per callback
+    failures: HashMap<String, u32>,
+    /// Disabled callbacks and the script that defines them
+    disabled: HashMap<String, String>,
+}
+
+impl ScriptErrors {
+    pub fn new(disable_after: Option<u32>) -> Self {
+        Self {
+            recent: VecDeque::new(),
+            repeat_window: Duration::from_secs(5),
+            disable_after,
+            failures: HashMap::new(),
+            disabled: HashMap::new(),
+        }
+    }
+
+    pub fn with_repeat_window(mut self, window: Duration) -> Self {
+        self.repeat_window = window;
+        self
+    }
+
+    /// Records a failed callback
+    pub fn report(&mut self, script: &str, callback: &str, err: &mlua::Error) {
+        self.record(script, callback, err);
+
+        let failures = self.failures.entry(callback.to_string()).or_insert(0);
+        *failures += 1;
+        if let Some(limit) = self.disable_after {
+            if *failures >= limit && !self.disabled.contains_key(callback) {
+                warn!(
+                    "Disabling Lua callback {} after {} consecutive errors; edit {} to re-enable it",
+                    callback, failures, script
+                );
+                self.disabled
+                    .insert(callback.to_string(), script.to_string());
+            }
+        }
+    }
+
+    /// Records a script that failed to load or hot-reload, as a `LOAD_CALLBACK`
+    /// error. Load errors do not count towards disabling callbacks.
+    pub fn report_load(&mut self, script: &str, err: &mlua::Error) {
+        self.record(script, LOAD_CALLBACK, err);
+    }
+
+    /// Adds an error to `recent`, folding it into a matching recent one
+    fn record(&mut self, script: &str, callback: &str, err: &mlua::Error) {
+        let (message, traceback) = describe_error(err);
+        let (line, message) = split_location(&message);
+        let now = Instant::now();
+
+        let repeated = self.recent.iter_mut().rev().find(|e| {
+            e.script == script
+                && e.callback == callback
+                && e.message == message
+                && now.duration_since(e.last_seen) <= self.repeat_window
+        });
+        match repeated {
+            Some(existing) => {
+                existing.count += 1;
+                existing.last_seen = now;
+            }
+            None => {
+                match line {
+                    Some(line) => error!(
+                        "Lua error in {}:{} ({}): {}",
+                        script, line, callback, message
+                    ),
+                    None => error!("Lua error in {} ({}): {}", script, callback, message),
+                }
+                if self.recent.len() == MAX_RECENT_ERRORS {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(ScriptError {
+                    script: script.to_string(),
+                    callback: callback.to_string(),
+                    line,
+                    message,
+                    traceback,
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                });
+            }
+        }
+    }
+
+    /// Records a successful call, resetting the callback's failure streak
+    pub fn record_success(&mut self, callback: &str) {
+        self.failures.remove(callback);
+    }
+
+    pub fn is_disabled(&self, callback: &str) -> bool {
+        self.disabled.contains_key(callback)
+    }
+
+    /// Re-enables every callback defined by a script that was just reloaded, and
+    /// those of unknown scripts, since the reload may have defined them
+    pub fn script_reloaded(&mut self, script: &str) {
+        let enabled: Vec<String> = self
+            .disabled
+            .iter()
+            .filter(|(_, s)| s.as_str() == script || s.as_str() == UNKNOWN_SCRIPT)
+            .map(|(callback, _)| callback.clone())
+            .collect();
+        for callback in enabled {
+            self.disabled.remove(&callback);
+            self.failures.remove(&callback);
+        }
+    }
+
+    /// Recent errors, oldest first
+    pub fn recent(&self) -> impl DoubleEndedIterator<Item = &ScriptError> {
+        self.recent.iter()
+    }
+
+    pub fn latest(&self) -> Option<&ScriptError> {
+        self.recent.back()
+    }
+
+    pub fn for_script<'a>(&'a self, script: &'a str) -> impl Iterator<Item = &'a ScriptError> {
+        self.recent.iter().filter(move |e| e.script == script)
+    }
+
+    pub fn disabled_callbacks(&self) -> impl Iterator<Item = &str> {
+        self.disabled.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recent.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.recent.clear();
+    }
+}
+
+impl Default for ScriptErrors {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+/// Splits an mlua error into its message and Lua traceback
+fn describe_error(err: &mlua::Error) -> (String, Option<String>) {
+    match err {
+        mlua::Error::CallbackError { traceback, cause } => {
+            let (message, _) = describe_error(cause);
+            (message, Some(traceback.clone()))
+        }
+        mlua::Error::RuntimeError(message) => match message.split_once("\nstack traceback:") {
+            Some((message, traceback)) => (
+                message.to_string(),
+                Some(format!("stack traceback:{}", traceback)),
+            ),
+            None => (message.clone(), None),
+        },
+        other => (other.to_string(), None),
+    }
+}
+
+/// Strips a leading `chunk:line:` location from a Lua error message
+fn split_location(message: &str) -> (Option<u32>, String) {
+    let mut search_from = 0;
+    while let Some(offset) = message[search_from..].find(':') {
+        let colon = search_from + offset;
+        // Only the first line can carry the location
+        if message[search_from..colon].contains('\n') {
+            break;
+        }
+        let rest = &message[colon + 1..];
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        if digits > 0 && rest[digits..].starts_with(':') {
+            if let Ok(line) = rest[..digits].parse() {
+                return (Some(line), rest[digits + 1..].trim_start().to_string());
+            }
+        }
+        search_from = colon + 1;
+    }
+    (None, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime_error(message: &str) -> mlua::Error {
+        mlua::Error::RuntimeError(message.to_string())
+    }
+
+    #[test]
+    fn split_location_strips_chunk_and_line() {
+        assert_eq!(
+            split_location("scripts/player.lua:12: attempt to index a nil value"),
+            (Some(12), "attempt to index a nil value".to_string())
+        );
+    }
+
+    #[test]
+    fn split_location_skips_windows_drive_letters() {
+        assert_eq!(
+            split_location(r"C:\game\scripts\player.lua:7: boom"),
+            (Some(7), "boom".to_string())
+        );
+    }
+
+    #[test]
+    fn split_location_keeps_messages_without_a_line() {
+        assert_eq!(
+            split_location("spawn: unknown field 'sprit'"),
+            (None, "spawn: unknown field 'sprit'".to_string())
+        );
+        // A location on a later line is part of the message
+        assert_eq!(
+            split_location("boom\nscripts/player.lua:3: in function"),
+            (None, "boom\nscripts/player.lua:3: in function".to_string())
+        );
+    }
+
+    #[test]
+    fn repeated_errors_are_folded_within_the_window() {
+        let mut errors = ScriptErrors::new(None);
+        errors.report("player", "update", &runtime_error("player.lua:4: boom"));
+        errors.report("player", "update", &runtime_error("player.lua:4: boom"));
+        errors.report("player", "update", &runtime_error("player.lua:5: other"));
+
+        let recent: Vec<_> = errors.recent().collect();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].count, 2);
+        assert_eq!(recent[0].line, Some(4));
+        assert_eq!(recent[1].message, "other");
+    }
+
+    #[test]
+    fn repeats_after_the_window_get_their_own_entry() {
+        let mut errors = ScriptErrors::new(None).with_repeat_window(Duration::ZERO);
+        errors.report("player", "update", &runtime_error("boom"));
+        std::thread::sleep(Duration::from_millis(2));
+        errors.report("player", "update", &runtime_error("boom"));
+        assert_eq!(errors.recent().count(), 2);
+    }
+
+    #[test]
+    fn reloading_any_script_re_enables_callbacks_of_unknown_scripts() {
+        let mut errors = ScriptErrors::new(Some(1));
+        errors.report(
+            UNKNOWN_SCRIPT,
+            "spawn_wrold",
+            &runtime_error("no such function"),
+        );
+        errors.report("player", "update", &runtime_error("boom"));
+        assert!(errors.is_disabled("spawn_wrold"));
+
+        errors.script_reloaded("world");
+        assert!(!errors.is_disabled("spawn_wrold"));
+        assert!(errors.is_disabled("update"));
+    }
+
+    #[test]
+    fn load_errors_never_disable_anything() {
+        let mut errors = ScriptErrors::new(Some(1));
+        errors.report_load("player", &runtime_error("player.lua:3: unexpected symbol"));
+        errors.report_load("enemy", &runtime_error("enemy.lua:9: unexpected symbol"));
+
+        assert_eq!(errors.recent().count(), 2);
+        let latest = errors.latest().unwrap();
+        assert_eq!(latest.script, "enemy");
+        assert_eq!(latest.callback, LOAD_CALLBACK);
+        assert_eq!(latest.line, Some(9));
+        assert_eq!(errors.disabled_callbacks().count(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use mlua::{Function, Lua, LuaSerdeExt, Result as LuaResult, SerializeOptions, Table, Value};
+
+/// Named registry table of event handlers: event name -> { {func = f, module = m}, ... }
+const EVENT_HANDLERS_KEY: &str = "revgame.event_handlers";
+
+/// Handlers a module registered, detached while the module is being reloaded
+pub(crate) type DetachedHandlers = Vec<(String, Table)>;
+
+pub(crate) fn init_event_handlers(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(EVENT_HANDLERS_KEY, lua.create_table()?)
+}
+
+/// `on(name, fn)`: subscribes `func` to an event. `module` is the script that was
+/// loading at the time, so its handlers can be replaced when it is hot-reloaded.
+pub(crate) fn add_event_handler(
+    lua: &Lua,
+    event: &str,
+    func: Function,
+    module: Option<String>,
+) -> LuaResult<()> {
+    let handlers: Table = lua.named_registry_value(EVENT_HANDLERS_KEY)?;
+    let list = match handlers.get::<Option<Table>>(event)? {
+        Some(list) => list,
+        None => {
+            let list = lua.create_table()?;
+            handlers.set(event, list.clone())?;
+            list
+        }
+    };
+    let entry = lua.create_table()?;
+    entry.set("func", func)?;
+    entry.set("module", module)?;
+    list.raw_set(list.raw_len() + 1, entry)
+}
+
+/// `off(name, fn)`: unsubscribes `func`, returning whether it was subscribed
+pub(crate) fn remove_event_handler(lua: &Lua, event: &str, func: Function) -> LuaResult<bool> {
+    let handlers: Table = lua.named_registry_value(EVENT_HANDLERS_KEY)?;
+    let Some(list) = handlers.get::<Option<Table>>(event)? else {
+        return Ok(false);
+    };
+    let (removed, kept) =
+        split_handlers(&list, |entry| Ok(entry.get::<Function>("func")? == func))?;
+    handlers.set(event, lua.create_sequence_from(kept)?)?;
+    Ok(!removed.is_empty())
+}
+
+/// Removes and returns every handler a module registered
+pub(crate) fn detach_module_handlers(lua: &Lua, module: &str) -> LuaResult<DetachedHandlers> {
+    let handlers: Table = lua.named_registry_value(EVENT_HANDLERS_KEY)?;
+    let mut detached = Vec::new();
+    for pair in handlers.clone().pairs::<String, Table>() {
+        let (event, list) = pair?;
+        let (removed, kept) = split_handlers(&list, |entry| {
+            Ok(entry.get::<Option<String>>("module")?.as_deref() == Some(module))
+        })?;
+        if removed.is_empty() {
+            continue;
+        }
+        handlers.set(event.as_str(), lua.create_sequence_from(kept)?)?;
+        detached.extend(removed.into_iter().map(|entry| (event.clone(), entry)));
+    }
+    Ok(detached)
+}
+
+/// Puts detached handlers back, e.g. after a reload failed
+pub(crate) fn restore_handlers(lua: &Lua, detached: DetachedHandlers) -> LuaResult<()> {
+    let handlers: Table = lua.named_registry_value(EVENT_HANDLERS_KEY)?;
+    for (event, entry) in detached {
+        let list = match handlers.get::<Option<Table>>(event.as_str())? {
+            Some(list) => list,
+            None => {
+                let list = lua.create_table()?;
+                handlers.set(event.as_str(), list.clone())?;
+                list
+            }
+        };
+        list.raw_set(list.raw_len() + 1, entry)?;
+    }
+    Ok(())
+}
+
+/// Calls every handler of an event with the payload converted to a Lua value.
+/// Returns the module and error of each handler that failed.
+pub(crate) fn dispatch_event(
+    lua: &Lua,
+    event: &str,
+    payload: &serde_json::Value,
+    mut call: impl FnMut(&Function, Value) -> LuaResult<()>,
+) -> LuaResult<Vec<(Option<String>, mlua::Error)>> {
+    let handlers: Table = lua.named_registry_value(EVENT_HANDLERS_KEY)?;
+    let Some(list) = handlers.get::<Option<Table>>(event)? else {
+        return Ok(Vec::new());
+    };
+
+    // JSON null becomes nil rather than a light userdata sentinel
+    let options = SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    let payload = lua.to_value_with(payload, options)?;
+
+    // Copy the list first: handlers may subscribe or unsubscribe while running
+    let entries: Vec<Table> = list.sequence_values::<Table>().collect::<LuaResult<_>>()?;
+    let mut failures = Vec::new();
+    for entry in entries {
+        let func: Function = entry.get("func")?;
+        if let Err(e) = call(&func, payload.clone()) {
+            failures.push((entry.get("module")?, e));
+        }
+    }
+    Ok(failures)
+}
+
+/// Splits a handler list into entries matching `pred` and the rest
+fn split_handlers(
+    list: &Table,
+    mut pred: impl FnMut(&Table) -> LuaResult<bool>,
+) -> LuaResult<(Vec<Table>, Vec<Table>)> {
+    let mut matching = Vec::new();
+    let mut rest = Vec::new();
+    for entry in list.sequence_values::<Table>() {
+        let entry = entry?;
+        if pred(&entry)? {
+            matching.push(entry);
+        } else {
+            rest.push(entry);
+        }
+    }
+    Ok((matching, rest))
+}
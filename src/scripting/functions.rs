@@ -0,0 +1,70 @@
+use mlua::{Function, Lua, RegistryKey, Result as LuaResult, Table};
+use std::collections::HashMap;
+
+use super::instances::instance_callback;
+
+/// Functions Rust calls every frame, resolved once and kept in the Lua registry.
+/// Stored as Lua app data; cleared whenever a module runs, since that is when
+/// scripts (re)define their globals and callbacks.
+#[derive(Default)]
+struct FunctionCache {
+    /// Global functions by name
+    globals: HashMap<String, RegistryKey>,
+    /// Instance callbacks by (module, callback); `None` when the module has none
+    callbacks: HashMap<(String, String), Option<RegistryKey>>,
+}
+
+pub(crate) fn init_function_cache(lua: &Lua) {
+    lua.set_app_data(FunctionCache::default());
+}
+
+/// Forgets every cached function, e.g. after a module was (re)loaded
+pub(crate) fn invalidate_functions(lua: &Lua) {
+    if let Some(mut cache) = lua.app_data_mut::<FunctionCache>() {
+        cache.globals.clear();
+        cache.callbacks.clear();
+    }
+    lua.expire_registry_values();
+}
+
+/// A global function, looked up by name the first time only
+pub(crate) fn global_function(lua: &Lua, name: &str) -> LuaResult<Function> {
+    if let Some(key) = cache(lua)?.globals.get(name) {
+        return lua.registry_value(key);
+    }
+    let func: Function = lua.globals().get(name)?;
+    let key = lua.create_registry_value(func.clone())?;
+    cache_mut(lua)?.globals.insert(name.to_string(), key);
+    Ok(func)
+}
+
+/// A callback such as `on_update` of instances of `module`, looked up in the
+/// instance's module the first time only
+pub(crate) fn module_callback(
+    lua: &Lua,
+    module: &str,
+    this: &Table,
+    name: &str,
+) -> LuaResult<Option<Function>> {
+    let cache_key = (module.to_string(), name.to_string());
+    if let Some(key) = cache(lua)?.callbacks.get(&cache_key) {
+        return key.as_ref().map(|key| lua.registry_value(key)).transpose();
+    }
+    let func = instance_callback(this, name)?;
+    let key = func
+        .as_ref()
+        .map(|func| lua.create_registry_value(func.clone()))
+        .transpose()?;
+    cache_mut(lua)?.callbacks.insert(cache_key, key);
+    Ok(func)
+}
+
+fn cache(lua: &Lua) -> LuaResult<mlua::AppDataRef<'_, FunctionCache>> {
+    lua.app_data_ref::<FunctionCache>()
+        .ok_or_else(|| mlua::Error::RuntimeError("function cache is not set up".to_string()))
+}
+
+fn cache_mut(lua: &Lua) -> LuaResult<mlua::AppDataRefMut<'_, FunctionCache>> {
+    lua.app_data_mut::<FunctionCache>()
+        .ok_or_else(|| mlua::Error::RuntimeError("function cache is not set up".to_string()))
+}
@@ -0,0 +1,768 @@
+use mlua::{Function, Lua, LuaSerdeExt, Result as LuaResult, SerializeOptions, Table, Thread};
+use mlua::{ThreadStatus, Value};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::sandbox::with_budget;
+use super::{
+    script_module_name, setup_lua_bindings, InputState, LuaCommand, LuaCommandFailure,
+    LuaGameState, LuaRuntime, PendingSpawn, ScriptErrors, ScriptLimits, ScriptStage, SpawnTag,
+};
+
+/// Directory inside the scripts directory holding Lua test files. It is not
+/// loaded as part of the game.
+pub const SCRIPT_TESTS_DIR: &str = "tests";
+
+/// Frame time `step` uses when a test does not give one
+pub const DEFAULT_TEST_DT: f32 = 1.0 / 60.0;
+
+/// Named registry value holding the tests a test file registered with `test`
+const TESTS_KEY: &str = "revgame.tests";
+
+/// Lua side of the test runner: harness calls yield a request back to Rust
+const TEST_PRELUDE: &str = r#"
+local tests = {}
+
+function test(name, fn)
+    if type(name) ~= "string" or type(fn) ~= "function" then
+        error("test expects a name and a function", 2)
+    end
+    tests[#tests + 1] = { name = name, fn = fn }
+end
+
+local function request(op, ...)
+    if not coroutine.isyieldable() then
+        error(op .. "() can only be used inside a test", 3)
+    end
+    return coroutine.yield(op, ...)
+end
+
+function step(frames, dt) request("step", frames or 1, dt) end
+function press(key) request("press", key) end
+function release(key) request("release", key) end
+function spawned() return request("spawned") end
+function position_updates() return request("position_updates") end
+function health_updates() return request("health_updates") end
+function entity(id) return request("entity", id) end
+function script_errors() return request("script_errors") end
+
+local function fail(message, default)
+    error((message and (message .. ": ") or "") .. default, 3)
+end
+
+function expect_eq(actual, expected, message)
+    if actual ~= expected then
+        fail(message, "expected " .. tostring(expected) .. ", got " .. tostring(actual))
+    end
+end
+
+function expect_near(actual, expected, tolerance, message)
+    tolerance = tolerance or 1e-3
+    if type(actual) ~= "number" or math.abs(actual - expected) > tolerance then
+        fail(message, "expected " .. tostring(expected) .. " +/- " .. tostring(tolerance)
+            .. ", got " .. tostring(actual))
+    end
+end
+
+return tests
+"#;
+
+/// What the harness knows about an entity, updated the way the game would
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockEntity {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub velocity: Option<(f32, f32)>,
+    /// (current, max)
+    pub health: Option<(f32, f32)>,
+    /// Whether the entity has a sprite
+    pub sprite: bool,
+    /// Custom sprite size, if one was given
+    pub size: Option<(f32, f32)>,
+    pub tags: Vec<SpawnTag>,
+    pub script: Option<String>,
+}
+
+/// Everything scripts asked the game to do, in order, since the last `take_output`
+#[derive(Debug, Clone, Default)]
+pub struct HarnessOutput {
+    pub spawns: Vec<PendingSpawn>,
+    pub position_updates: Vec<(u32, f32, f32)>,
+    pub velocity_updates: Vec<(u32, f32, f32)>,
+    pub health_updates: Vec<(u32, f32)>,
+    pub size_updates: Vec<(u32, f32, f32)>,
+    pub despawns: Vec<u32>,
+    pub camera_position: Option<(f32, f32)>,
+    pub emitted_events: Vec<(String, serde_json::Value)>,
+    /// Commands that could not be applied, in order
+    pub command_failures: Vec<LuaCommandFailure>,
+    /// Last game state requested with `set_game_state`
+    pub requested_game_state: Option<String>,
+}
+
+/// Runs game scripts without Bevy: feed input, step frames, then inspect what the
+/// scripts queued and the resulting entity state.
+///
+/// Each `step` runs the same phases in the same order as `ScriptedGameplayPlugin`
+/// (events, the script stages, entity scripts and tasks) and then applies the
+/// queued commands to a set of mock entities.
+pub struct ScriptHarness {
+    runtime: LuaRuntime,
+    errors: ScriptErrors,
+    held: HashSet<String>,
+    previously_held: HashSet<String>,
+    entities: BTreeMap<u32, MockEntity>,
+    /// Health changes to deliver as `health_changed` events next frame
+    health_changes: Vec<(u32, f32, f32)>,
+    /// Failed commands to deliver as `command_failed` events next frame
+    command_failures: Vec<LuaCommandFailure>,
+    output: HarnessOutput,
+    frame: u64,
+}
+
+impl ScriptHarness {
+    /// Loads every script in `scripts_dir`, like the game does at startup
+    pub fn new(scripts_dir: impl Into<PathBuf>) -> LuaResult<Self> {
+        Self::with_limits(scripts_dir, ScriptLimits::default())
+    }
+
+    /// Like `new`, with a runtime created with the given `ScriptLimits`
+    pub fn with_limits(scripts_dir: impl Into<PathBuf>, limits: ScriptLimits) -> LuaResult<Self> {
+        let scripts_dir = scripts_dir.into();
+        let mut harness = Self::from_runtime(LuaRuntime::with_limits(limits)?)?;
+        harness.runtime.set_scripts_dir(scripts_dir.clone());
+        for script in LuaRuntime::discover_scripts(&scripts_dir)? {
+            harness.runtime.require(&script)?;
+        }
+        Ok(harness)
+    }
+
+    /// A harness with the bindings set up but no scripts loaded
+    pub fn empty() -> LuaResult<Self> {
+        Self::from_runtime(LuaRuntime::new()?)
+    }
+
+    fn from_runtime(runtime: LuaRuntime) -> LuaResult<Self> {
+        setup_lua_bindings(&runtime.lua(), LuaGameState::new())?;
+        Ok(Self {
+            runtime,
+            errors: ScriptErrors::new(None),
+            held: HashSet::new(),
+            previously_held: HashSet::new(),
+            entities: BTreeMap::new(),
+            health_changes: Vec::new(),
+            command_failures: Vec::new(),
+            output: HarnessOutput::default(),
+            frame: 0,
+        })
+    }
+
+    pub fn runtime(&self) -> &LuaRuntime {
+        &self.runtime
+    }
+
+    pub fn runtime_mut(&mut self) -> &mut LuaRuntime {
+        &mut self.runtime
+    }
+
+    /// Runs `f` on the state the bindings read from and queue commands into
+    pub fn with_game_state<R>(&self, f: impl FnOnce(&mut LuaGameState) -> R) -> R {
+        self.runtime
+            .with_game_state(f)
+            .expect("ScriptHarness sets up the bindings")
+    }
+
+    /// Errors scripts raised so far
+    pub fn errors(&self) -> &ScriptErrors {
+        &self.errors
+    }
+
+    pub fn clear_errors(&mut self) {
+        self.errors.clear();
+    }
+
+    /// Frames stepped so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Runs a chunk of Lua, e.g. to call a script function or change its state
+    pub fn exec(&self, code: &str) -> LuaResult<()> {
+        let lua = self.runtime.lua();
+        with_budget(&lua, |lua| lua.load(code).set_name("=harness").exec())
+    }
+
+    /// Evaluates a Lua expression
+    pub fn eval<T: mlua::FromLua>(&self, expression: &str) -> LuaResult<T> {
+        let lua = self.runtime.lua();
+        with_budget(&lua, |lua| {
+            lua.load(expression).set_name("=harness").eval::<T>()
+        })
+    }
+
+    /// Calls the game's spawn functions, as entering `InGame` does. Their
+    /// spawns are applied by the next `step`.
+    pub fn enter_game(&mut self) {
+        for callback in ["spawn_world", "spawn_player"] {
+            if !self.runtime.has_function(callback) {
+                continue;
+            }
+            if let Err(e) = self.runtime.call_function(callback) {
+                let script = self.runtime.function_script(callback);
+                self.report(script.as_deref(), callback, &e);
+            }
+        }
+    }
+
+    /// Holds a key down from the next frame on. Use Bevy names (`KeyW`,
+    /// `ArrowUp`) to get their short aliases too.
+    pub fn press(&mut self, key: &str) {
+        self.held.insert(key.to_string());
+    }
+
+    pub fn release(&mut self, key: &str) {
+        self.held.remove(key);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Runs one frame of `dt` seconds
+    pub fn step(&mut self, dt: f32) {
+        self.frame += 1;
+        let input = self.input_state();
+        self.with_game_state(|state| {
+            state.set_delta_time(dt);
+            state.set_input(input);
+            state.publish_frame();
+        });
+        self.previously_held = self.held.clone();
+
+        self.dispatch_events();
+        self.run_stage(ScriptStage::Input, dt);
+        self.run_stage(ScriptStage::Simulation, dt);
+        self.update_instances(dt);
+        self.update_tasks(dt);
+        self.run_stage(ScriptStage::Late, dt);
+        self.run_stage(ScriptStage::Camera, dt);
+        let despawned = self.apply_commands();
+
+        let mut moved = Vec::new();
+        for (lua_id, entity) in self.entities.iter_mut() {
+            if let Some((vx, vy)) = entity.velocity {
+                entity.x += vx * dt;
+                entity.y += vy * dt;
+                moved.push((*lua_id, entity.x, entity.y));
+            }
+        }
+        self.with_game_state(|state| {
+            for (lua_id, x, y) in moved {
+                state.update_entity_position(lua_id, x, y);
+            }
+        });
+
+        for lua_id in despawned {
+            self.forget_entity(lua_id);
+        }
+        // Lets code run between steps (`exec`, `eval`) see the end of this frame
+        self.with_game_state(LuaGameState::publish_frame);
+    }
+
+    /// Runs `frames` frames of `dt` seconds each
+    pub fn step_frames(&mut self, frames: u32, dt: f32) {
+        for _ in 0..frames {
+            self.step(dt);
+        }
+    }
+
+    pub fn entity(&self, lua_id: u32) -> Option<&MockEntity> {
+        self.entities.get(&lua_id)
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = (u32, &MockEntity)> {
+        self.entities.iter().map(|(id, entity)| (*id, entity))
+    }
+
+    /// The entity tagged as player, if one is alive
+    pub fn player(&self) -> Option<u32> {
+        self.entities
+            .iter()
+            .find(|(_, entity)| entity.tags.contains(&SpawnTag::Player))
+            .map(|(id, _)| *id)
+    }
+
+    pub fn output(&self) -> &HarnessOutput {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> HarnessOutput {
+        std::mem::take(&mut self.output)
+    }
+
+    fn input_state(&self) -> InputState {
+        let mut input = InputState::default();
+        for key in &self.held {
+            let just_pressed = !self.previously_held.contains(key);
+            input.keys.set(key, true, just_pressed, false);
+        }
+        for key in self.previously_held.difference(&self.held) {
+            input.keys.set(key, false, false, true);
+        }
+        input
+    }
+
+    fn dispatch_events(&mut self) {
+        let mut events: Vec<(String, serde_json::Value)> = self
+            .health_changes
+            .drain(..)
+            .map(|(id, current, max)| {
+                let payload = json!({ "id": id, "current": current, "max": max });
+                ("health_changed".to_string(), payload)
+            })
+            .collect();
+        for failure in self.command_failures.drain(..) {
+            let payload = serde_json::to_value(failure).unwrap_or_default();
+            events.push(("command_failed".to_string(), payload));
+        }
+        for (name, payload) in self.with_game_state(LuaGameState::take_emitted_events) {
+            self.output
+                .emitted_events
+                .push((name.clone(), payload.clone()));
+            events.push((name, payload));
+        }
+
+        for (name, payload) in events {
+            let callback = format!("on({})", name);
+            let errors = &mut self.errors;
+            let result = self.runtime.dispatch_event(&name, &payload, |module, e| {
+                errors.report(module.unwrap_or("<unknown>"), &callback, &e)
+            });
+            if let Err(e) = result {
+                self.report(None, &callback, &e);
+            }
+        }
+    }
+
+    fn run_stage(&mut self, stage: ScriptStage, dt: f32) {
+        let systems = match self.runtime.systems(stage) {
+            Ok(systems) => systems,
+            Err(e) => return self.report(None, &format!("{} systems", stage), &e),
+        };
+        for system in systems {
+            if let Err(e) = self.runtime.run_system(&system.name, dt) {
+                self.report(system.module.as_deref(), &system.name, &e);
+            }
+        }
+    }
+
+    fn update_instances(&mut self, dt: f32) {
+        let instances = match self.runtime.instances() {
+            Ok(instances) => instances,
+            Err(e) => return self.report(None, "script instances", &e),
+        };
+        let errors = &mut self.errors;
+        self.runtime.update(dt, &instances, |instance, result| {
+            if let Err(e) = result {
+                let callback = format!("{}.on_update", instance.script);
+                errors.report(&instance.script, &callback, &e);
+            }
+        });
+    }
+
+    fn update_tasks(&mut self, dt: f32) {
+        let errors = &mut self.errors;
+        let result = self.runtime.update_tasks(dt, |id, module, e| {
+            errors.report(module.unwrap_or("<unknown>"), &format!("task {}", id), &e)
+        });
+        if let Err(e) = result {
+            self.report(None, "tasks", &e);
+        }
+    }
+
+    /// Applies queued commands in order, the way `lua_apply_commands` does,
+    /// returning the entities that were despawned
+    fn apply_commands(&mut self) -> Vec<u32> {
+        let mut attach = Vec::new();
+        let mut despawned = Vec::new();
+        for command in self.with_game_state(LuaGameState::take_commands) {
+            if let Err(message) = self.apply_command(&command, &mut attach, &mut despawned) {
+                let failure = command.failed(message);
+                self.command_failures.push(failure.clone());
+                self.output.command_failures.push(failure);
+            }
+        }
+        if let Some(state) = self.with_game_state(LuaGameState::take_requested_game_state) {
+            self.output.requested_game_state = Some(state);
+        }
+
+        for (lua_id, script) in attach {
+            if let Err(e) = self.runtime.spawn_instance(lua_id, &script) {
+                self.report(Some(&script), &format!("{}.on_spawn", script), &e);
+            }
+        }
+        despawned
+    }
+
+    fn apply_command(
+        &mut self,
+        command: &LuaCommand,
+        attach: &mut Vec<(u32, String)>,
+        despawned: &mut Vec<u32>,
+    ) -> Result<(), String> {
+        let lua_id = command.lua_id();
+        if !matches!(command, LuaCommand::Spawn(_)) {
+            if let Some(lua_id) = lua_id.filter(|id| !self.entities.contains_key(id)) {
+                return Err(format!("entity {} does not exist", lua_id));
+            }
+        }
+
+        match command {
+            LuaCommand::Spawn(spawn) => {
+                let mut entity = MockEntity {
+                    x: spawn.x,
+                    y: spawn.y,
+                    z: spawn.z,
+                    velocity: spawn.velocity.map(|[x, y]| (x, y)),
+                    health: spawn.health.map(|[current, max]| (current, max)),
+                    sprite: spawn.sprite.is_some(),
+                    size: spawn
+                        .sprite
+                        .as_ref()
+                        .and_then(|sprite| sprite.size)
+                        .map(|[w, h]| (w, h)),
+                    tags: spawn.tags.clone(),
+                    script: spawn.script.clone(),
+                };
+                if entity.tags.contains(&SpawnTag::Player) {
+                    entity.velocity.get_or_insert((0.0, 0.0));
+                    entity.health.get_or_insert((100.0, 100.0));
+                }
+                self.with_game_state(|state| {
+                    state.update_entity_position(spawn.lua_id, entity.x, entity.y)
+                });
+                let health = entity.health;
+                self.entities.insert(spawn.lua_id, entity);
+                if let Some((current, max)) = health {
+                    self.set_health(spawn.lua_id, current, max);
+                }
+                if let Some(script) = &spawn.script {
+                    attach.push((spawn.lua_id, script_module_name(script)));
+                }
+                self.output.spawns.push(spawn.clone());
+            }
+            LuaCommand::Tag { lua_id, tag } => {
+                let entity = self.mock_entity(*lua_id)?;
+                entity.tags.push(*tag);
+                if *tag == SpawnTag::Player {
+                    entity.velocity = Some((0.0, 0.0));
+                    self.set_health(*lua_id, 100.0, 100.0);
+                }
+            }
+            LuaCommand::SetPosition { lua_id, x, y } => {
+                self.output.position_updates.push((*lua_id, *x, *y));
+                let entity = self.mock_entity(*lua_id)?;
+                entity.x = *x;
+                entity.y = *y;
+                self.with_game_state(|state| state.update_entity_position(*lua_id, *x, *y));
+            }
+            LuaCommand::SetVelocity { lua_id, x, y } => {
+                self.output.velocity_updates.push((*lua_id, *x, *y));
+                self.mock_entity(*lua_id)?.velocity = Some((*x, *y));
+            }
+            LuaCommand::SetHealth { lua_id, health } => {
+                self.output.health_updates.push((*lua_id, *health));
+                let Some((_, max)) = self.mock_entity(*lua_id)?.health else {
+                    return Err(format!("entity {} has no health", lua_id));
+                };
+                self.set_health(*lua_id, health.clamp(0.0, max), max);
+            }
+            LuaCommand::SetSpriteSize {
+                lua_id,
+                width,
+                height,
+            } => {
+                self.output.size_updates.push((*lua_id, *width, *height));
+                let entity = self.mock_entity(*lua_id)?;
+                if !entity.sprite {
+                    return Err(format!("entity {} has no sprite", lua_id));
+                }
+                entity.size = Some((*width, *height));
+            }
+            LuaCommand::SetCameraPosition { x, y } => {
+                self.output.camera_position = Some((*x, *y));
+                self.with_game_state(|state| state.set_camera_position_read(*x, *y));
+            }
+            LuaCommand::Despawn(lua_id) => {
+                self.output.despawns.push(*lua_id);
+                self.entities.remove(lua_id);
+                despawned.push(*lua_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn mock_entity(&mut self, lua_id: u32) -> Result<&mut MockEntity, String> {
+        self.entities
+            .get_mut(&lua_id)
+            .ok_or_else(|| format!("entity {} does not exist", lua_id))
+    }
+
+    fn set_health(&mut self, lua_id: u32, current: f32, max: f32) {
+        if let Some(entity) = self.entities.get_mut(&lua_id) {
+            entity.health = Some((current, max));
+        }
+        self.with_game_state(|state| state.update_entity_health(lua_id, current, max));
+        self.health_changes.push((lua_id, current, max));
+    }
+
+    /// Drops a despawned entity the way `lua_cleanup_despawned` does
+    fn forget_entity(&mut self, lua_id: u32) {
+        if let Some(script) = self.runtime.instance_script(lua_id) {
+            if let Err(e) = self.runtime.despawn_instance(lua_id) {
+                self.report(Some(&script), &format!("{}.on_despawn", script), &e);
+            }
+        }
+        self.with_game_state(|state| state.forget_entity(lua_id));
+        if let Err(e) = self.runtime.cancel_entity_tasks(lua_id) {
+            self.report(None, "tasks", &e);
+        }
+    }
+
+    fn report(&mut self, script: Option<&str>, callback: &str, e: &mlua::Error) {
+        self.errors
+            .report(script.unwrap_or("<unknown>"), callback, e);
+    }
+}
+
+/// Outcome of one Lua test
+#[derive(Debug, Clone)]
+pub struct LuaTestResult {
+    pub file: PathBuf,
+    pub name: String,
+    /// Why the test failed, if it did
+    pub failure: Option<String>,
+}
+
+impl LuaTestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Runs every Lua test file in `<scripts_dir>/tests`, in file name order
+pub fn run_lua_tests(scripts_dir: &Path) -> std::io::Result<Vec<LuaTestResult>> {
+    let tests_dir = scripts_dir.join(SCRIPT_TESTS_DIR);
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(&tests_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("lua") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut results = Vec::new();
+    for file in files {
+        results.extend(run_lua_test_file(scripts_dir, &file));
+    }
+    Ok(results)
+}
+
+/// Runs the tests a Lua file registers with `test(name, fn)`. Every test gets a
+/// fresh `ScriptHarness` with the game scripts loaded, and fails if it raises an
+/// error or if a game script raised one it did not collect with `script_errors()`.
+///
+/// ```lua
+/// test("player moves right", function()
+///     local id = spawn_player()
+///     press("KeyD")
+///     step(30)
+///     local player = entity(id)
+///     assert(player.x > 0, "player should have moved right")
+/// end)
+/// ```
+pub fn run_lua_test_file(scripts_dir: &Path, file: &Path) -> Vec<LuaTestResult> {
+    let result = |name: &str, failure: Option<String>| LuaTestResult {
+        file: file.to_path_buf(),
+        name: name.to_string(),
+        failure,
+    };
+
+    let names = match load_test_file(scripts_dir, file) {
+        Ok((_, tests)) => tests,
+        Err(e) => return vec![result("<load>", Some(e.to_string()))],
+    };
+
+    names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let failure = load_test_file(scripts_dir, file)
+                .and_then(|(mut harness, _)| run_test(&mut harness, index + 1))
+                .err()
+                .map(|e| first_line(&e.to_string()));
+            result(name, failure)
+        })
+        .collect()
+}
+
+/// A fresh harness with the test file loaded, plus the names of its tests
+fn load_test_file(scripts_dir: &Path, file: &Path) -> LuaResult<(ScriptHarness, Vec<String>)> {
+    let harness = ScriptHarness::new(scripts_dir)?;
+    let source = std::fs::read_to_string(file)?;
+    let names = {
+        let lua = harness.runtime.lua();
+        with_budget(&lua, |lua| {
+            let tests: Table = lua.load(TEST_PRELUDE).set_name("=revgame.tests").eval()?;
+            lua.set_named_registry_value(TESTS_KEY, tests.clone())?;
+            lua.load(source.as_str())
+                .set_name(format!("@{}", file.display()))
+                .exec()?;
+            tests
+                .sequence_values::<Table>()
+                .map(|test| test?.get::<String>("name"))
+                .collect::<LuaResult<Vec<_>>>()
+        })?
+    };
+    Ok((harness, names))
+}
+
+/// Runs test number `index` as a coroutine, serving its harness requests
+fn run_test(harness: &mut ScriptHarness, index: usize) -> LuaResult<()> {
+    let thread: Thread = {
+        let lua = harness.runtime.lua();
+        let tests: Table = lua.named_registry_value(TESTS_KEY)?;
+        let test: Table = tests.get(index)?;
+        lua.create_thread(test.get::<Function>("fn")?)?
+    };
+
+    let mut reply = Value::Nil;
+    loop {
+        let (op, first, second) = {
+            let lua = harness.runtime.lua();
+            with_budget(&lua, |_| {
+                thread.resume::<(Option<String>, Value, Value)>(reply.clone())
+            })?
+        };
+        if thread.status() != ThreadStatus::Resumable {
+            break;
+        }
+        reply = serve_request(harness, op.as_deref().unwrap_or(""), first, second)?;
+    }
+
+    match harness.errors.latest() {
+        Some(error) => Err(mlua::Error::RuntimeError(format!(
+            "{} raised an error in {}: {}",
+            error.script, error.callback, error.message
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Performs one harness request from a test and returns the value to resume with
+fn serve_request(
+    harness: &mut ScriptHarness,
+    op: &str,
+    first: Value,
+    second: Value,
+) -> LuaResult<Value> {
+    let lua = harness.runtime.lua();
+    let payload = match op {
+        "step" => {
+            let frames: u32 = lua.unpack(first)?;
+            let dt: Option<f32> = lua.unpack(second)?;
+            drop(lua);
+            harness.step_frames(frames, dt.unwrap_or(DEFAULT_TEST_DT));
+            return Ok(Value::Nil);
+        }
+        "press" | "release" => {
+            let key: String = lua.unpack(first)?;
+            drop(lua);
+            if op == "press" {
+                harness.press(&key);
+            } else {
+                harness.release(&key);
+            }
+            return Ok(Value::Nil);
+        }
+        "spawned" => json!(harness
+            .output
+            .spawns
+            .iter()
+            .map(|spawn| json!({
+                "id": spawn.lua_id,
+                "x": spawn.x,
+                "y": spawn.y,
+                "z": spawn.z,
+                "tags": spawn.tags.iter().map(|tag| tag.name()).collect::<Vec<_>>(),
+                "script": spawn.script,
+            }))
+            .collect::<Vec<_>>()),
+        "position_updates" => json!(harness
+            .output
+            .position_updates
+            .iter()
+            .map(|(id, x, y)| json!({ "id": id, "x": x, "y": y }))
+            .collect::<Vec<_>>()),
+        "health_updates" => json!(harness
+            .output
+            .health_updates
+            .iter()
+            .map(|(id, health)| json!({ "id": id, "health": health }))
+            .collect::<Vec<_>>()),
+        "entity" => {
+            let lua_id: u32 = lua.unpack(first)?;
+            match harness.entities.get(&lua_id) {
+                Some(entity) => json!({
+                    "x": entity.x,
+                    "y": entity.y,
+                    "z": entity.z,
+                    "vx": entity.velocity.map(|(x, _)| x),
+                    "vy": entity.velocity.map(|(_, y)| y),
+                    "health": entity.health.map(|(current, _)| current),
+                    "max_health": entity.health.map(|(_, max)| max),
+                    "width": entity.size.map(|(w, _)| w),
+                    "height": entity.size.map(|(_, h)| h),
+                    "tags": entity.tags.iter().map(|tag| tag.name()).collect::<Vec<_>>(),
+                }),
+                None => serde_json::Value::Null,
+            }
+        }
+        "script_errors" => {
+            let messages: Vec<_> = harness
+                .errors
+                .recent()
+                .map(|error| format!("{}: {}", error.callback, error.message))
+                .collect();
+            drop(lua);
+            harness.errors.clear();
+            return to_lua(&harness.runtime.lua(), &json!(messages));
+        }
+        other => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "unexpected yield '{}' from a test (wait() and friends only work in spawn_task)",
+                other
+            )))
+        }
+    };
+    to_lua(&lua, &payload)
+}
+
+/// JSON to Lua, with null as nil
+fn to_lua(lua: &Lua, value: &serde_json::Value) -> LuaResult<Value> {
+    let options = SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    lua.to_value_with(value, options)
+}
+
+/// Error message without the Lua traceback
+fn first_line(message: &str) -> String {
+    match message.split_once("\nstack traceback:") {
+        Some((message, _)) => message.to_string(),
+        None => message.to_string(),
+    }
+}
@@ -1,14 +1,14 @@
-use bevy::prelude::*;
 use notify_debouncer_mini::{new_debouncer, DebouncedEventKind, notify::RecursiveMode};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Mutex;
 use std::time::Duration;
+use tracing::{error, info};
 
-use super::LuaRuntime;
+use super::{LuaRuntime, SCRIPT_TESTS_DIR};
 
 /// Resource that watches the scripts directory for changes
-#[derive(Resource)]
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Resource))]
 pub struct ScriptWatcher {
     rx: Mutex<Receiver<Result<Vec<notify_debouncer_mini::DebouncedEvent>, notify_debouncer_mini::notify::Error>>>,
     scripts_dir: PathBuf,
@@ -53,31 +53,53 @@ impl ScriptWatcher {
     }
 }
 
-/// System that checks for script changes and triggers reloads
-pub fn check_script_changes(
-    watcher: Option<Res<ScriptWatcher>>,
-    mut runtime: Option<ResMut<LuaRuntime>>,
-) {
-    let Some(watcher) = watcher else { return };
-    let Some(ref mut runtime) = runtime else { return };
-
+/// Reloads every changed `.lua` file reported by the watcher, returning the names
+/// of the modules that were reloaded. Modules that fail to reload are passed to
+/// `on_error` with the error and keep running their previous version.
+pub fn reload_changed_scripts(
+    watcher: &ScriptWatcher,
+    runtime: &mut LuaRuntime,
+    mut on_error: impl FnMut(&str, &mlua::Error),
+) -> Vec<String> {
+    let mut reloaded = Vec::new();
     let events = watcher.try_recv();
     for event in events {
         if event.kind == DebouncedEventKind::Any {
             let path = &event.path;
 
-            // Only reload .lua files
-            if path.extension().map(|e| e == "lua").unwrap_or(false) {
-                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
-                    match runtime.reload_script(name, path) {
-                        Ok(true) => info!("Hot-reloaded: {}", name),
+            // Only reload .lua files that still exist (editors may delete/rename),
+            // skipping editor type stubs and the Lua test files
+            let is_stub = path.to_string_lossy().ends_with(".d.lua");
+            if path.extension().map(|e| e == "lua").unwrap_or(false)
+                && path.is_file()
+                && !is_stub
+                && !is_script_test(watcher, path)
+            {
+                let name = runtime
+                    .module_name(path)
+                    .or_else(|| path.file_stem().and_then(|s| s.to_str()).map(str::to_string));
+                if let Some(name) = name {
+                    match runtime.reload_script(&name, path) {
+                        Ok(true) => {
+                            info!("Hot-reloaded: {}", name);
+                            reloaded.push(name);
+                        }
                         Ok(false) => {} // No change
-                        Err(e) => error!("Failed to reload {}: {}", name, e),
+                        Err(e) => on_error(&name, &e),
                     }
                 }
             }
         }
     }
+    reloaded
+}
+
+/// Whether `path` is one of the Lua test files `run_lua_tests` runs
+fn is_script_test(watcher: &ScriptWatcher, path: &Path) -> bool {
+    let tests_dir = watcher.scripts_dir().join(SCRIPT_TESTS_DIR);
+    let tests_dir = tests_dir.canonicalize().unwrap_or(tests_dir);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.starts_with(tests_dir)
 }
 
 /// Initialize the script watcher for the scripts directory
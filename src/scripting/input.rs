@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+
+/// Pressed / just pressed / just released buttons for one frame, by upper-case name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ButtonState {
+    pub pressed: HashSet<String>,
+    pub just_pressed: HashSet<String>,
+    pub just_released: HashSet<String>,
+    /// Every button recorded with `set`, by upper-case full name
+    buttons: HashMap<String, ButtonFlags>,
+}
+
+/// What one physical button did this frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ButtonFlags {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+impl ButtonFlags {
+    /// Whether the button was held the frame before
+    fn was_pressed(&self) -> bool {
+        (self.pressed || self.just_released) && !self.just_pressed
+    }
+}
+
+impl ButtonState {
+    /// Records a button under its name and all of its aliases (see `input_aliases`).
+    ///
+    /// An alias shared by several buttons, like `SHIFT`, is pressed while any of
+    /// them is held. It is only just pressed when the first of them goes down and
+    /// only just released when the last one comes up.
+    pub fn set(&mut self, name: &str, pressed: bool, just_pressed: bool, just_released: bool) {
+        let flags = self.buttons.entry(name.to_uppercase()).or_default();
+        flags.pressed |= pressed;
+        flags.just_pressed |= just_pressed;
+        flags.just_released |= just_released;
+        self.update(name);
+    }
+
+    /// Forgets a button, as if it had not been held this frame or the one before
+    pub fn unset(&mut self, name: &str) {
+        self.buttons.remove(&name.to_uppercase());
+        self.update(name);
+    }
+
+    /// Recomputes a button's name and aliases from every button sharing them
+    fn update(&mut self, name: &str) {
+        let aliases = input_aliases(name);
+        let full_name = &aliases[0];
+        let flags = self.buttons.get(full_name).copied().unwrap_or_default();
+        toggle(&mut self.pressed, full_name, flags.pressed);
+        toggle(&mut self.just_pressed, full_name, flags.just_pressed);
+        toggle(&mut self.just_released, full_name, flags.just_released);
+
+        for alias in &aliases[1..] {
+            let mut combined = ButtonFlags::default();
+            let mut was_pressed = false;
+            for (button, flags) in &self.buttons {
+                if !input_aliases(button).contains(alias) {
+                    continue;
+                }
+                combined.pressed |= flags.pressed;
+                combined.just_pressed |= flags.just_pressed;
+                combined.just_released |= flags.just_released;
+                was_pressed |= flags.was_pressed();
+            }
+            toggle(&mut self.pressed, alias, combined.pressed);
+            toggle(
+                &mut self.just_pressed,
+                alias,
+                combined.just_pressed && !was_pressed,
+            );
+            toggle(
+                &mut self.just_released,
+                alias,
+                combined.just_released && !combined.pressed,
+            );
+        }
+    }
+
+    pub fn pressed(&self, name: &str) -> bool {
+        self.pressed.contains(&name.to_uppercase())
+    }
+
+    pub fn just_pressed(&self, name: &str) -> bool {
+        self.just_pressed.contains(&name.to_uppercase())
+    }
+
+    pub fn just_released(&self, name: &str) -> bool {
+        self.just_released.contains(&name.to_uppercase())
+    }
+
+    pub fn clear(&mut self) {
+        self.pressed.clear();
+        self.just_pressed.clear();
+        self.just_released.clear();
+        self.buttons.clear();
+    }
+}
+
+/// Adds `name` to `set` or removes it
+fn toggle(set: &mut HashSet<String>, name: &str, present: bool) {
+    if present {
+        set.insert(name.to_string());
+    } else {
+        set.remove(name);
+    }
+}
+
+/// One connected gamepad
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GamepadState {
+    pub buttons: ButtonState,
+    /// Axis values by upper-case name (e.g. `LEFTSTICKX`), including analog buttons
+    pub axes: HashMap<String, f32>,
+}
+
+impl GamepadState {
+    pub fn axis(&self, name: &str) -> f32 {
+        self.axes.get(&name.to_uppercase()).copied().unwrap_or(0.0)
+    }
+}
+
+/// Everything scripts can read about input this frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputState {
+    pub keys: ButtonState,
+    pub mouse: ButtonState,
+    /// Cursor position in world coordinates, if it is over the window
+    pub cursor_world: Option<(f32, f32)>,
+    /// Cursor position in window coordinates (origin top-left), if it is over the window
+    pub cursor_screen: Option<(f32, f32)>,
+    /// Connected gamepads in connection order; Lua indexes them from 1
+    pub gamepads: Vec<GamepadState>,
+}
+
+/// Names a button can be looked up by: its full name plus short forms.
+///
+/// `KeyW` is also `W`, `Digit1` is `1`, `ArrowUp` is `UP`, `ShiftLeft` is `SHIFT`,
+/// and the gamepad face buttons `South`/`East`/`West`/`North` are `A`/`B`/`X`/`Y`.
+/// Names with a payload (e.g. `Unidentified(..)`) have no aliases.
+pub fn input_aliases(name: &str) -> Vec<String> {
+    let upper = name.to_uppercase();
+    let mut aliases = vec![upper.clone()];
+    if upper.contains('(') {
+        return aliases;
+    }
+
+    let short = if let Some(letter) = upper.strip_prefix("KEY").filter(|s| s.len() == 1) {
+        Some(letter)
+    } else if let Some(digit) = upper.strip_prefix("DIGIT") {
+        Some(digit)
+    } else if let Some(direction) = upper.strip_prefix("ARROW") {
+        Some(direction)
+    } else {
+        ["SHIFT", "CONTROL", "ALT", "SUPER"]
+            .into_iter()
+            .find(|modifier| {
+                upper == format!("{}LEFT", modifier) || upper == format!("{}RIGHT", modifier)
+            })
+    };
+    if let Some(short) = short {
+        aliases.push(short.to_string());
+    }
+
+    let face = match upper.as_str() {
+        "SOUTH" => Some("A"),
+        "EAST" => Some("B"),
+        "WEST" => Some("X"),
+        "NORTH" => Some("Y"),
+        _ => None,
+    };
+    if let Some(face) = face {
+        aliases.push(face.to_string());
+    }
+    aliases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_cover_short_names() {
+        let cases: &[(&str, &[&str])] = &[
+            ("KeyW", &["KEYW", "W"]),
+            ("Digit1", &["DIGIT1", "1"]),
+            ("ArrowUp", &["ARROWUP", "UP"]),
+            ("ShiftLeft", &["SHIFTLEFT", "SHIFT"]),
+            ("ControlRight", &["CONTROLRIGHT", "CONTROL"]),
+            ("South", &["SOUTH", "A"]),
+            ("North", &["NORTH", "Y"]),
+            ("Space", &["SPACE"]),
+            ("KeyboardLayout", &["KEYBOARDLAYOUT"]),
+            (
+                "Unidentified(NativeKeyCode(5))",
+                &["UNIDENTIFIED(NATIVEKEYCODE(5))"],
+            ),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(input_aliases(name), *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn lookups_ignore_case_and_include_aliases() {
+        let mut keys = ButtonState::default();
+        keys.set("KeyW", true, true, false);
+        for name in ["KeyW", "keyw", "W", "w"] {
+            assert!(keys.pressed(name), "{}", name);
+            assert!(keys.just_pressed(name), "{}", name);
+        }
+        assert!(!keys.just_released("W"));
+    }
+
+    #[test]
+    fn a_shared_alias_follows_all_of_its_buttons() {
+        // ShiftRight is held while ShiftLeft is released
+        let mut keys = ButtonState::default();
+        keys.set("ShiftRight", true, false, false);
+        keys.set("ShiftLeft", false, false, true);
+        assert!(keys.just_released("ShiftLeft"));
+        assert!(keys.pressed("SHIFT"));
+        assert!(!keys.just_released("SHIFT"));
+
+        // ShiftRight goes down while ShiftLeft is held
+        let mut keys = ButtonState::default();
+        keys.set("ShiftLeft", true, false, false);
+        keys.set("ShiftRight", true, true, false);
+        assert!(keys.just_pressed("ShiftRight"));
+        assert!(!keys.just_pressed("SHIFT"));
+
+        // The last one comes up, in either order of recording
+        for order in [["ShiftLeft", "ShiftRight"], ["ShiftRight", "ShiftLeft"]] {
+            let mut keys = ButtonState::default();
+            keys.set(order[0], false, false, true);
+            keys.set(order[1], false, false, true);
+            assert!(!keys.pressed("SHIFT"));
+            assert!(keys.just_released("SHIFT"));
+        }
+
+        // The first one goes down
+        let mut keys = ButtonState::default();
+        keys.set("ShiftLeft", true, true, false);
+        assert!(keys.just_pressed("SHIFT"));
+    }
+
+    #[test]
+    fn unset_buttons_drop_out_of_their_aliases() {
+        let mut keys = ButtonState::default();
+        keys.set("ShiftLeft", true, false, false);
+        keys.set("ShiftRight", true, false, false);
+        keys.unset("ShiftLeft");
+        assert!(!keys.pressed("ShiftLeft"));
+        assert!(keys.pressed("SHIFT"));
+        keys.unset("ShiftRight");
+        assert!(!keys.pressed("SHIFT"));
+    }
+}
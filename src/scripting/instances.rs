@@ -0,0 +1,138 @@
+use mlua::{Function, Lua, Result as LuaResult, Table, Value};
+
+/// Named registry table of script instances: lua id -> {self, script}
+const INSTANCES_KEY: &str = "revgame.instances";
+
+/// A script attached to an entity, with its own `self` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptInstance {
+    pub lua_id: u32,
+    /// Module that defines the instance's callbacks
+    pub script: String,
+}
+
+pub(crate) fn init_instances(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(INSTANCES_KEY, lua.create_table()?)
+}
+
+/// Module name for a script given as `enemy`, `enemy.lua` or `ai/enemy.lua`
+pub fn script_module_name(script: &str) -> String {
+    script
+        .strip_suffix(".lua")
+        .unwrap_or(script)
+        .replace(['/', '\\'], ".")
+}
+
+/// Creates the `self` table of a new instance of `module` (the table the script
+/// returned). `self.id` is the entity's Lua id and missing fields fall back to the
+/// module, so scripts can define methods and defaults there.
+pub(crate) fn create_instance(
+    lua: &Lua,
+    lua_id: u32,
+    script: &str,
+    module: Value,
+) -> LuaResult<Table> {
+    let Value::Table(module) = module else {
+        return Err(mlua::Error::RuntimeError(format!(
+            "script '{}' must return a table of callbacks to be attached to entities, got {}",
+            script,
+            module.type_name()
+        )));
+    };
+
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    if !instances.get::<Value>(lua_id)?.is_nil() {
+        return Err(mlua::Error::RuntimeError(format!(
+            "entity {} already has a script attached",
+            lua_id
+        )));
+    }
+
+    let this = lua.create_table()?;
+    this.set("id", lua_id)?;
+    this.set_metatable(Some(instance_metatable(lua, module)?));
+
+    let entry = lua.create_table()?;
+    entry.set("self", this.clone())?;
+    entry.set("script", script)?;
+    instances.set(lua_id, entry)?;
+    Ok(this)
+}
+
+/// Removes an instance, returning its `self` table
+pub(crate) fn remove_instance(lua: &Lua, lua_id: u32) -> LuaResult<Option<Table>> {
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    let Some(entry) = instances.get::<Option<Table>>(lua_id)? else {
+        return Ok(None);
+    };
+    instances.set(lua_id, Value::Nil)?;
+    entry.get("self")
+}
+
+/// Module of the script attached to an entity
+pub(crate) fn instance_script(lua: &Lua, lua_id: u32) -> LuaResult<Option<String>> {
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    match instances.get::<Option<Table>>(lua_id)? {
+        Some(entry) => entry.get("script"),
+        None => Ok(None),
+    }
+}
+
+/// The `self` table of an entity's script, if it has one
+pub(crate) fn instance_table(lua: &Lua, lua_id: u32) -> LuaResult<Option<Table>> {
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    match instances.get::<Option<Table>>(lua_id)? {
+        Some(entry) => entry.get("self"),
+        None => Ok(None),
+    }
+}
+
+/// Every instance, in spawn (id) order
+pub(crate) fn list_instances(lua: &Lua) -> LuaResult<Vec<ScriptInstance>> {
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    let mut found = Vec::new();
+    for pair in instances.pairs::<u32, Table>() {
+        let (lua_id, entry) = pair?;
+        found.push(ScriptInstance {
+            lua_id,
+            script: entry.get("script")?,
+        });
+    }
+    found.sort_by_key(|instance| instance.lua_id);
+    Ok(found)
+}
+
+/// Points the instances of a reloaded script at its new module table, so they
+/// pick up new methods and defaults while keeping their own state
+pub(crate) fn refresh_instances(lua: &Lua, script: &str, module: Value) -> LuaResult<()> {
+    let Value::Table(module) = module else {
+        return Ok(());
+    };
+    let instances: Table = lua.named_registry_value(INSTANCES_KEY)?;
+    let metatable = instance_metatable(lua, module)?;
+    for pair in instances.pairs::<u32, Table>() {
+        let (_, entry) = pair?;
+        if entry.get::<String>("script")? == script {
+            let this: Table = entry.get("self")?;
+            this.set_metatable(Some(metatable.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Looks up a callback such as `on_update` in the instance's current module
+pub(crate) fn instance_callback(this: &Table, name: &str) -> LuaResult<Option<Function>> {
+    let Some(metatable) = this.metatable() else {
+        return Ok(None);
+    };
+    match metatable.raw_get::<Value>("__index")? {
+        Value::Table(module) => module.get(name),
+        _ => Ok(None),
+    }
+}
+
+fn instance_metatable(lua: &Lua, module: Table) -> LuaResult<Table> {
+    let metatable = lua.create_table()?;
+    metatable.set("__index", module)?;
+    Ok(metatable)
+}
@@ -1,7 +1,37 @@
+mod api;
 mod bindings;
+mod commands;
+mod debugger;
+#[cfg(feature = "engine")]
+mod ecs;
+mod errors;
+mod events;
+mod functions;
+mod harness;
 mod hot_reload;
+mod input;
+mod instances;
+mod profiler;
 mod runtime;
+mod sandbox;
+mod spawn;
+mod systems;
+mod tasks;
 
+pub use api::*;
 pub use bindings::*;
+pub use commands::*;
+#[cfg(feature = "engine")]
+pub use ecs::*;
+pub use errors::*;
+pub use harness::*;
 pub use hot_reload::*;
+pub use input::*;
+pub use instances::{script_module_name, ScriptInstance};
+pub use profiler::{
+    CallbackSample, CallbackStats, ScriptProfile, MAX_TRACE_EVENTS, PROFILE_WINDOW,
+};
 pub use runtime::*;
+pub use sandbox::{ScriptLimitError, ScriptLimits};
+pub use spawn::*;
+pub use systems::{ScriptStage, ScriptSystem};
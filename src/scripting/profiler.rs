@@ -0,0 +1,266 @@
+use mlua::{Debug as LuaDebug, Lua, Result as LuaResult};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Calls each callback's rolling averages cover
+pub const PROFILE_WINDOW: usize = 120;
+
+/// Timed calls `ScriptProfile` keeps for the trace export (oldest are dropped)
+pub const MAX_TRACE_EVENTS: usize = 100_000;
+
+/// One timed call from Rust into Lua
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallbackSample {
+    /// Callback that ran, e.g. `update_player`, `enemy.on_update` or `on(hit)`
+    pub name: String,
+    /// Kind of callback: `function`, `system`, `script`, `task` or `event`
+    pub category: &'static str,
+    pub start: Instant,
+    pub duration: Duration,
+    /// Change of the Lua heap size over the call in bytes; negative if the
+    /// garbage collector freed more than the call allocated
+    pub memory_delta: i64,
+}
+
+/// What the runtime recorded since it was last collected, stored as Lua app data
+#[derive(Default)]
+struct ProfilerState {
+    enabled: bool,
+    sampling: bool,
+    calls: Vec<CallbackSample>,
+    /// Hook samples per running function
+    samples: HashMap<String, u64>,
+}
+
+pub(crate) fn init_profiler(lua: &Lua) {
+    lua.set_app_data(ProfilerState::default());
+}
+
+pub(crate) fn set_profiling(lua: &Lua, enabled: bool) {
+    if let Some(mut state) = lua.app_data_mut::<ProfilerState>() {
+        state.enabled = enabled;
+    }
+}
+
+pub(crate) fn is_profiling(lua: &Lua) -> bool {
+    lua.app_data_ref::<ProfilerState>()
+        .is_some_and(|state| state.enabled)
+}
+
+pub(crate) fn set_sampling(lua: &Lua, enabled: bool) {
+    if let Some(mut state) = lua.app_data_mut::<ProfilerState>() {
+        state.sampling = enabled;
+    }
+}
+
+pub(crate) fn is_sampling(lua: &Lua) -> bool {
+    lua.app_data_ref::<ProfilerState>()
+        .is_some_and(|state| state.sampling)
+}
+
+/// Runs a call from Rust into Lua, timing it while profiling is on. `name` is
+/// only evaluated then, so callers can build it with `format!`.
+pub(crate) fn profiled<T>(
+    lua: &Lua,
+    category: &'static str,
+    name: impl FnOnce() -> String,
+    call: impl FnOnce() -> LuaResult<T>,
+) -> LuaResult<T> {
+    if !is_profiling(lua) {
+        return call();
+    }
+
+    let memory_before = lua.used_memory();
+    let start = Instant::now();
+    let result = call();
+    let duration = start.elapsed();
+    let memory_delta = lua.used_memory() as i64 - memory_before as i64;
+
+    if let Some(mut state) = lua.app_data_mut::<ProfilerState>() {
+        state.calls.push(CallbackSample {
+            name: name(),
+            category,
+            start,
+            duration,
+            memory_delta,
+        });
+    }
+    result
+}
+
+/// Counts the function running when the instruction hook fired
+pub(crate) fn record_sample(lua: &Lua, debug: &LuaDebug) {
+    let source = debug.source();
+    let function = debug.names().name.map(|name| name.to_string());
+    let key = format!(
+        "{} ({}:{})",
+        function.as_deref().unwrap_or("?"),
+        source.short_src.as_deref().unwrap_or("?"),
+        source.line_defined.unwrap_or(0)
+    );
+    if let Some(mut state) = lua.app_data_mut::<ProfilerState>() {
+        *state.samples.entry(key).or_default() += 1;
+    }
+}
+
+/// Moves everything recorded so far into `profile`
+pub(crate) fn collect_profile(lua: &Lua, profile: &mut ScriptProfile) {
+    let Some(mut state) = lua.app_data_mut::<ProfilerState>() else {
+        return;
+    };
+    for call in state.calls.drain(..) {
+        profile.record(call);
+    }
+    for (function, count) in state.samples.drain() {
+        *profile.samples.entry(function).or_default() += count;
+    }
+}
+
+/// Timing and allocation stats of one callback
+#[derive(Debug, Clone, Default)]
+pub struct CallbackStats {
+    pub category: &'static str,
+    pub calls: u64,
+    pub total_time: Duration,
+    pub last_time: Duration,
+    pub max_time: Duration,
+    /// The last `PROFILE_WINDOW` calls: (duration, memory delta)
+    window: VecDeque<(Duration, i64)>,
+}
+
+impl CallbackStats {
+    /// Average wall time over the last `PROFILE_WINDOW` calls
+    pub fn average_time(&self) -> Duration {
+        if self.window.is_empty() {
+            return Duration::ZERO;
+        }
+        let total: Duration = self.window.iter().map(|(time, _)| *time).sum();
+        total / self.window.len() as u32
+    }
+
+    /// Average Lua heap change in bytes over the last `PROFILE_WINDOW` calls
+    pub fn average_memory_delta(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        let total: i64 = self.window.iter().map(|(_, delta)| *delta).sum();
+        total as f64 / self.window.len() as f64
+    }
+}
+
+/// Where the frame's Lua time goes: per-callback wall time and heap change with
+/// rolling averages, the timed calls for a Chrome trace, and, with sampling on,
+/// how often each function was caught running.
+///
+/// Filled from `LuaRuntime::collect_profile` once profiling is turned on with
+/// `LuaRuntime::set_profiling`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Resource))]
+pub struct ScriptProfile {
+    callbacks: BTreeMap<String, CallbackStats>,
+    trace: VecDeque<CallbackSample>,
+    samples: HashMap<String, u64>,
+    /// Time zero of the trace export
+    epoch: Instant,
+}
+
+impl ScriptProfile {
+    pub fn new() -> Self {
+        Self {
+            callbacks: BTreeMap::new(),
+            trace: VecDeque::new(),
+            samples: HashMap::new(),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Adds one timed call
+    pub fn record(&mut self, call: CallbackSample) {
+        let stats = self.callbacks.entry(call.name.clone()).or_default();
+        stats.category = call.category;
+        stats.calls += 1;
+        stats.total_time += call.duration;
+        stats.last_time = call.duration;
+        stats.max_time = stats.max_time.max(call.duration);
+        if stats.window.len() == PROFILE_WINDOW {
+            stats.window.pop_front();
+        }
+        stats.window.push_back((call.duration, call.memory_delta));
+
+        if self.trace.len() == MAX_TRACE_EVENTS {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(call);
+    }
+
+    pub fn stats(&self, callback: &str) -> Option<&CallbackStats> {
+        self.callbacks.get(callback)
+    }
+
+    /// Every callback seen, slowest (by rolling average) first
+    pub fn hottest(&self) -> Vec<(&str, &CallbackStats)> {
+        let mut callbacks: Vec<_> = self
+            .callbacks
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats))
+            .collect();
+        callbacks.sort_by(|a, b| b.1.average_time().cmp(&a.1.average_time()));
+        callbacks
+    }
+
+    /// Hook samples per function, most sampled first
+    pub fn samples(&self) -> Vec<(&str, u64)> {
+        let mut samples: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(function, count)| (function.as_str(), *count))
+            .collect();
+        samples.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        samples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty() && self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The recorded calls in the Chrome trace-event format, which
+    /// `chrome://tracing` and Perfetto open directly
+    pub fn chrome_trace(&self) -> serde_json::Value {
+        let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+        let events: Vec<_> = self
+            .trace
+            .iter()
+            .map(|call| {
+                json!({
+                    "name": call.name,
+                    "cat": call.category,
+                    "ph": "X",
+                    "ts": micros(call.start.saturating_duration_since(self.epoch)),
+                    "dur": micros(call.duration),
+                    "pid": 1,
+                    "tid": 1,
+                    "args": { "memory_delta": call.memory_delta },
+                })
+            })
+            .collect();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+
+    /// Writes `chrome_trace` to a file
+    pub fn write_chrome_trace(&self, path: &Path) -> std::io::Result<()> {
+        let text = serde_json::to_string(&self.chrome_trace())?;
+        std::fs::write(path, text)
+    }
+}
+
+impl Default for ScriptProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
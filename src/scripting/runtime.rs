@@ -1,90 +1,624 @@
-use bevy::prelude::*;
-use mlua::{Lua, Result as LuaResult};
+use mlua::{Function, Lua, Result as LuaResult, Table, Value};
 use std::collections::HashMap;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use tracing::{info, warn};
+
+use super::api::register_binding;
+use super::bindings::LuaGameState;
+use super::debugger::{poll_debugger, start_debugger};
+use super::events::{
+    add_event_handler, detach_module_handlers, dispatch_event, init_event_handlers,
+    remove_event_handler, restore_handlers,
+};
+use super::functions::{
+    global_function, init_function_cache, invalidate_functions, module_callback,
+};
+use super::harness::SCRIPT_TESTS_DIR;
+use super::instances::{
+    create_instance, init_instances, instance_callback, instance_script, instance_table,
+    list_instances, refresh_instances, remove_instance, script_module_name, ScriptInstance,
+};
+use super::profiler::{
+    collect_profile, init_profiler, is_profiling, is_sampling, profiled, set_profiling,
+    set_sampling, ScriptProfile,
+};
+use super::sandbox::{new_sandboxed_lua, update_hook, with_budget, ScriptLimits};
+use super::systems::{
+    clear_systems, detach_module_systems, init_systems, register_system, restore_systems,
+    stage_systems, system_function, unregister_system, ScriptStage, ScriptSystem,
+};
+use super::tasks::{
+    cancel_task, cancel_tasks_where, init_tasks, last_task_id, spawn_task, task_count,
+    update_tasks,
+};
+
+/// Named registry table caching the value each module returned
+const MODULES_KEY: &str = "revgame.modules";
+
+/// Named registry table holding `persist`ed state tables by key
+const PERSISTENT_KEY: &str = "revgame.persistent";
+
+/// Named registry table of reload hooks: module -> { before = {...}, after = {...} }
+const RELOAD_HOOKS_KEY: &str = "revgame.reload_hooks";
+
+/// Optional file in the scripts directory listing modules to load, in order
+pub const SCRIPT_MANIFEST: &str = "manifest.json";
+
+/// Module bookkeeping, stored as Lua app data so `require` can reach it
+#[derive(Default)]
+struct ModuleRegistry {
+    scripts_dir: Option<PathBuf>,
+    /// Source of every loaded module, used to skip reloads when nothing changed
+    sources: HashMap<String, String>,
+    /// Modules currently executing, outermost first (for cycle detection)
+    loading: Vec<String>,
+}
 
 /// Resource that manages the Lua runtime
-#[derive(Resource)]
+#[cfg_attr(feature = "engine", derive(bevy::prelude::Resource))]
 pub struct LuaRuntime {
     lua: Arc<RwLock<Lua>>,
-    loaded_scripts: HashMap<String, String>,
 }
 
 impl LuaRuntime {
+    /// Creates a sandboxed runtime with the default `ScriptLimits`
     pub fn new() -> LuaResult<Self> {
-        let lua = Lua::new();
+        Self::with_limits(ScriptLimits::default())
+    }
+
+    /// Creates a sandboxed runtime with the given CPU and memory limits
+    pub fn with_limits(limits: ScriptLimits) -> LuaResult<Self> {
+        let lua = new_sandboxed_lua(limits)?;
+
+        lua.set_app_data(ModuleRegistry::default());
+        init_function_cache(&lua);
+        init_profiler(&lua);
+        lua.set_named_registry_value(MODULES_KEY, lua.create_table()?)?;
+        lua.set_named_registry_value(PERSISTENT_KEY, lua.create_table()?)?;
+        lua.set_named_registry_value(RELOAD_HOOKS_KEY, lua.create_table()?)?;
+        init_event_handlers(&lua)?;
+        init_systems(&lua)?;
+        init_tasks(&lua)?;
+        init_instances(&lua)?;
+
+        register_binding(
+            &lua,
+            "require",
+            lua.create_function(|lua, name: String| require_module(lua, &name))?,
+        )?;
+        register_binding(
+            &lua,
+            "persist",
+            lua.create_function(|lua, (key, defaults): (String, Option<Table>)| {
+                persistent_table(lua, &key, defaults)
+            })?,
+        )?;
+        register_binding(
+            &lua,
+            "on_before_reload",
+            lua.create_function(|lua, func: Function| add_reload_hook(lua, "before", func))?,
+        )?;
+        register_binding(
+            &lua,
+            "on_after_reload",
+            lua.create_function(|lua, func: Function| add_reload_hook(lua, "after", func))?,
+        )?;
+        register_binding(
+            &lua,
+            "on",
+            lua.create_function(|lua, (event, func): (String, Function)| {
+                let module = loading_module(lua);
+                add_event_handler(lua, &event, func, module)
+            })?,
+        )?;
+        register_binding(
+            &lua,
+            "off",
+            lua.create_function(|lua, (event, func): (String, Function)| {
+                remove_event_handler(lua, &event, func)
+            })?,
+        )?;
+        register_binding(
+            &lua,
+            "register_system",
+            lua.create_function(
+                |lua, (name, stage, func, order): (String, String, Function, Option<i64>)| {
+                    let module = loading_module(lua);
+                    register_system(lua, &name, &stage, func, order, module)
+                },
+            )?,
+        )?;
+        register_binding(
+            &lua,
+            "unregister_system",
+            lua.create_function(|lua, name: String| unregister_system(lua, &name))?,
+        )?;
+        register_binding(
+            &lua,
+            "spawn_task",
+            lua.create_function(|lua, (func, owner): (Function, Option<u32>)| {
+                let module = loading_module(lua).or_else(|| function_module(lua, &func));
+                spawn_task(lua, func, owner, module)
+            })?,
+        )?;
+        register_binding(
+            &lua,
+            "cancel_task",
+            lua.create_function(|lua, id: u64| cancel_task(lua, id))?,
+        )?;
+        register_binding(
+            &lua,
+            "get_instance",
+            lua.create_function(|lua, id: u32| instance_table(lua, id))?,
+        )?;
 
         Ok(Self {
             lua: Arc::new(RwLock::new(lua)),
-            loaded_scripts: HashMap::new(),
         })
     }
 
+    /// Sets the directory `require` resolves module names against
+    pub fn set_scripts_dir(&mut self, dir: PathBuf) {
+        let lua = self.lua();
+        let mut registry = lua.app_data_mut::<ModuleRegistry>().unwrap();
+        registry.scripts_dir = Some(dir);
+    }
+
+    pub fn scripts_dir(&self) -> Option<PathBuf> {
+        let lua = self.lua();
+        let registry = lua.app_data_ref::<ModuleRegistry>().unwrap();
+        registry.scripts_dir.clone()
+    }
+
+    /// Lists the modules to load from a scripts directory.
+    ///
+    /// If the directory has a `manifest.json` (`{"scripts": ["world", ...]}`) its
+    /// list is used as-is; otherwise every `.lua` file is found by a recursive scan,
+    /// leaving out the Lua tests in `tests/`.
+    /// `ai/enemy.lua` becomes module `ai.enemy` and `ai/init.lua` becomes `ai`.
+    pub fn discover_scripts(dir: &Path) -> std::io::Result<Vec<String>> {
+        let manifest = dir.join(SCRIPT_MANIFEST);
+        if manifest.exists() {
+            #[derive(serde::Deserialize)]
+            struct Manifest {
+                scripts: Vec<String>,
+            }
+            let content = std::fs::read_to_string(&manifest)?;
+            let manifest: Manifest = serde_json::from_str(&content)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            return Ok(manifest.scripts);
+        }
+
+        let mut names = Vec::new();
+        collect_modules(dir, dir, &mut names)?;
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Loads a module from the scripts directory (and, first, everything it requires).
+    /// Modules that are already loaded are not executed again.
+    pub fn require(&self, name: &str) -> LuaResult<()> {
+        let lua = self.lua();
+        with_budget(&lua, |lua| require_module(lua, name))?;
+        Ok(())
+    }
+
+    /// Module name for a file inside the scripts directory
+    pub fn module_name(&self, path: &Path) -> Option<String> {
+        let lua = self.lua();
+        module_for_path(&lua, path)
+    }
+
+    /// Module that defines a global function, e.g. `player` for `spawn_player`
+    pub fn function_script(&self, name: &str) -> Option<String> {
+        let lua = self.lua();
+        let func: Function = lua.globals().get(name).ok()?;
+        function_module(&lua, &func)
+    }
+
     /// Load a script from a file path
     pub fn load_script(&mut self, name: &str, path: &Path) -> LuaResult<()> {
         let script = std::fs::read_to_string(path)?;
-        self.load_script_content(name, &script)
+        let lua = self.lua_mut();
+        with_budget(&lua, |lua| run_module(lua, name, Some(path), script))?;
+        info!("Loaded Lua script: {}", name);
+        Ok(())
     }
 
     /// Load a script from string content
     pub fn load_script_content(&mut self, name: &str, content: &str) -> LuaResult<()> {
-        let lua = self.lua.write().unwrap();
-        lua.load(content).set_name(name).exec()?;
-        drop(lua);
-        self.loaded_scripts.insert(name.to_string(), content.to_string());
+        let lua = self.lua_mut();
+        with_budget(&lua, |lua| run_module(lua, name, None, content.to_string()))?;
         info!("Loaded Lua script: {}", name);
         Ok(())
     }
 
-    /// Reload a script (re-execute its content)
+    /// Reload a script (re-execute its content). Returns `false` without running
+    /// anything if the module was never loaded or its source did not change.
     pub fn reload_script(&mut self, name: &str, path: &Path) -> LuaResult<bool> {
         let new_content = std::fs::read_to_string(path)?;
 
         // Check if content actually changed
-        if let Some(old_content) = self.loaded_scripts.get(name) {
-            if old_content == &new_content {
-                return Ok(false);
+        {
+            let lua = self.lua();
+            let registry = lua.app_data_ref::<ModuleRegistry>().unwrap();
+            match registry.sources.get(name) {
+                // Never loaded, e.g. left out of the manifest
+                None => return Ok(false),
+                Some(source) if *source == new_content => return Ok(false),
+                Some(_) => {}
             }
         }
 
-        self.load_script_content(name, &new_content)?;
+        let lua = self.lua_mut();
+        with_budget(&lua, |lua| {
+            // A failed before hook leaves the old version in place
+            run_reload_hooks(lua, name, "before")?;
+
+            // The new version registers its own hooks, event handlers and systems;
+            // keep the old ones if it fails to load
+            let hooks: Table = lua.named_registry_value(RELOAD_HOOKS_KEY)?;
+            let previous_hooks: Value = hooks.get(name)?;
+            hooks.set(name, Value::Nil)?;
+            let previous_handlers = detach_module_handlers(lua, name)?;
+            let previous_systems = detach_module_systems(lua, name)?;
+            let last_task = last_task_id(lua)?;
+            let module = match run_module(lua, name, Some(path), new_content) {
+                Ok(module) => module,
+                Err(e) => {
+                    // Drop whatever the new version registered before it failed
+                    detach_module_handlers(lua, name)?;
+                    detach_module_systems(lua, name)?;
+                    cancel_tasks_where(lua, |id, task| {
+                        let module = task.get::<Option<String>>("module")?;
+                        Ok(id > last_task && module.as_deref() == Some(name))
+                    })?;
+                    hooks.set(name, previous_hooks)?;
+                    restore_handlers(lua, previous_handlers)?;
+                    restore_systems(lua, previous_systems)?;
+                    return Err(e);
+                }
+            };
+            refresh_instances(lua, name, module)?;
+
+            // Tasks started by the old version would keep running old code
+            cancel_tasks_where(lua, |id, task| {
+                let module = task.get::<Option<String>>("module")?;
+                Ok(id <= last_task && module.as_deref() == Some(name))
+            })?;
+
+            // The new version is already attached, so only log
+            if let Err(e) = run_reload_hooks(lua, name, "after") {
+                warn!("on_after_reload hook in {} failed: {}", name, e);
+            }
+            Ok(())
+        })?;
         info!("Hot-reloaded Lua script: {}", name);
         Ok(true)
     }
 
-    /// Call a Lua function with no arguments
-    pub fn call_function(&self, name: &str) -> LuaResult<()> {
-        let lua = self.lua.read().unwrap();
-        let func: mlua::Function = lua.globals().get(name)?;
-        func.call::<()>(())?;
+    /// Drops all `persist`ed state, so the next load starts from the defaults
+    pub fn reset_persistent_state(&self) -> LuaResult<()> {
+        let lua = self.lua();
+        lua.set_named_registry_value(PERSISTENT_KEY, lua.create_table()?)
+    }
+
+    /// Starts every loaded script over, as if the game had just launched: `persist`ed
+    /// state, tasks, script instances, reload hooks, event handlers and systems are
+    /// dropped and each module runs again. Modules that fail to run are passed to
+    /// `on_error` and left unloaded.
+    pub fn restart_scripts(
+        &mut self,
+        mut on_error: impl FnMut(&str, mlua::Error),
+    ) -> LuaResult<()> {
+        let lua = self.lua_mut();
+        let (scripts_dir, sources) = {
+            let mut registry = lua.app_data_mut::<ModuleRegistry>().unwrap();
+            (registry.scripts_dir.clone(), std::mem::take(&mut registry.sources))
+        };
+
+        cancel_tasks_where(&lua, |_, _| Ok(true))?;
+        lua.set_named_registry_value(MODULES_KEY, lua.create_table()?)?;
+        lua.set_named_registry_value(PERSISTENT_KEY, lua.create_table()?)?;
+        lua.set_named_registry_value(RELOAD_HOOKS_KEY, lua.create_table()?)?;
+        init_event_handlers(&lua)?;
+        clear_systems(&lua)?;
+        init_instances(&lua)?;
+        invalidate_functions(&lua);
+
+        let mut names: Vec<_> = sources.keys().cloned().collect();
+        names.sort();
+        let modules: Table = lua.named_registry_value(MODULES_KEY)?;
+        for name in names {
+            // Already run as a dependency of an earlier module
+            if !modules.get::<Value>(name.as_str())?.is_nil() {
+                continue;
+            }
+            let path = scripts_dir
+                .as_deref()
+                .and_then(|dir| resolve_module(dir, &name));
+            let result = with_budget(&lua, |lua| match path {
+                Some(_) => require_module(lua, &name),
+                // Loaded with `load_script_content`; run the same source again
+                None => run_module(lua, &name, None, sources[&name].clone()),
+            });
+            if let Err(e) = result {
+                on_error(&name, e);
+            }
+        }
+        info!("Restarted Lua scripts");
         Ok(())
     }
 
+    /// Whether a global function of this name is defined
+    pub fn has_function(&self, name: &str) -> bool {
+        let lua = self.lua();
+        matches!(lua.globals().get::<Value>(name), Ok(Value::Function(_)))
+    }
+
+    /// Call a Lua function with no arguments.
+    ///
+    /// Global functions are resolved once and cached until a module is loaded or
+    /// reloaded; a global a script reassigns at any other time is picked up after
+    /// `invalidate_function_cache`.
+    pub fn call_function(&self, name: &str) -> LuaResult<()> {
+        let lua = self.lua();
+        let func = global_function(&lua, name)?;
+        profiled(
+            &lua,
+            "function",
+            || name.to_string(),
+            || with_budget(&lua, |_| func.call::<()>(())),
+        )
+    }
+
     /// Call a Lua function that returns an entity ID
     pub fn call_spawn_function(&self, name: &str) -> LuaResult<u32> {
-        let lua = self.lua.read().unwrap();
-        let func: mlua::Function = lua.globals().get(name)?;
-        let result: u32 = func.call(())?;
-        Ok(result)
+        let lua = self.lua();
+        let func = global_function(&lua, name)?;
+        profiled(
+            &lua,
+            "function",
+            || name.to_string(),
+            || with_budget(&lua, |_| func.call::<u32>(())),
+        )
     }
 
     /// Call a Lua update function with entity ID and delta time
     pub fn call_update_function(&self, name: &str, entity_id: u32, delta: f32) -> LuaResult<()> {
-        let lua = self.lua.read().unwrap();
-        let func: mlua::Function = lua.globals().get(name)?;
-        func.call::<()>((entity_id, delta))?;
+        let lua = self.lua();
+        let func = global_function(&lua, name)?;
+        profiled(
+            &lua,
+            "function",
+            || name.to_string(),
+            || with_budget(&lua, |_| func.call::<()>((entity_id, delta))),
+        )
+    }
+
+    /// Drops every cached function handle, so the next call looks them up again
+    pub fn invalidate_function_cache(&self) {
+        invalidate_functions(&self.lua());
+    }
+
+    /// Systems scripts registered for a stage, in run order
+    pub fn systems(&self, stage: ScriptStage) -> LuaResult<Vec<ScriptSystem>> {
+        let lua = self.lua();
+        stage_systems(&lua, stage)
+    }
+
+    /// Calls a registered system with the frame's delta time
+    pub fn run_system(&self, name: &str, delta: f32) -> LuaResult<()> {
+        let lua = self.lua();
+        let func = system_function(&lua, name)?;
+        profiled(
+            &lua,
+            "system",
+            || name.to_string(),
+            || with_budget(&lua, |_| func.call::<()>(delta)),
+        )
+    }
+
+    /// Advances every `spawn_task` coroutine by one frame. Each wait check and resume
+    /// gets its own instruction budget; failed tasks are removed and passed to
+    /// `on_error` with their id and module.
+    pub fn update_tasks(
+        &self,
+        delta: f32,
+        mut on_error: impl FnMut(u64, Option<&str>, mlua::Error),
+    ) -> LuaResult<()> {
+        let lua = self.lua();
+        let failures = update_tasks(&lua, delta, |id, step| {
+            profiled(
+                &lua,
+                "task",
+                || format!("task {}", id),
+                || with_budget(&lua, |_| step()),
+            )
+        })?;
+        for (id, module, e) in failures {
+            on_error(id, module.as_deref(), e);
+        }
         Ok(())
     }
 
-    /// Get the Lua instance for binding setup
+    /// Cancels the tasks owned by a Lua entity, returning how many were running
+    pub fn cancel_entity_tasks(&self, lua_id: u32) -> LuaResult<usize> {
+        let lua = self.lua();
+        cancel_tasks_where(&lua, |_, task| {
+            Ok(task.get::<Option<u32>>("owner")? == Some(lua_id))
+        })
+    }
+
+    /// Number of tasks that have not finished yet
+    pub fn task_count(&self) -> LuaResult<usize> {
+        let lua = self.lua();
+        task_count(&lua)
+    }
+
+    /// Attaches a script to an entity: loads it if needed, creates the entity's
+    /// `self` table and calls the script's `on_spawn(self)`
+    pub fn spawn_instance(&self, lua_id: u32, script: &str) -> LuaResult<()> {
+        let lua = self.lua();
+        let name = script_module_name(script);
+        profiled(
+            &lua,
+            "script",
+            || format!("{}.on_spawn", name),
+            || {
+                with_budget(&lua, |lua| {
+                    let module = require_module(lua, &name)?;
+                    let this = create_instance(lua, lua_id, &name, module)?;
+                    match instance_callback(&this, "on_spawn")? {
+                        Some(on_spawn) => on_spawn.call::<()>(this),
+                        None => Ok(()),
+                    }
+                })
+            },
+        )
+    }
+
+    /// Calls `on_update(self, dt)` of the script attached to an entity
+    pub fn update_instance(&self, lua_id: u32, delta: f32) -> LuaResult<()> {
+        let lua = self.lua();
+        let Some(script) = instance_script(&lua, lua_id)? else {
+            return Ok(());
+        };
+        run_instance_update(&lua, lua_id, &script, delta)
+    }
+
+    /// Calls `on_update(self, dt)` of each of `entities`, in the given order, taking
+    /// the runtime lock once for the whole batch. Each call gets its own instruction
+    /// budget; its outcome is passed to `on_result`. Entities whose script has been
+    /// detached in the meantime are skipped.
+    pub fn update(
+        &self,
+        delta: f32,
+        entities: &[ScriptInstance],
+        mut on_result: impl FnMut(&ScriptInstance, LuaResult<()>),
+    ) {
+        let lua = self.lua();
+        for instance in entities {
+            let result = run_instance_update(&lua, instance.lua_id, &instance.script, delta);
+            on_result(instance, result);
+        }
+    }
+
+    /// Drops the script instance of an entity, calling its `on_despawn(self)` first.
+    /// Returns whether the entity had a script attached.
+    pub fn despawn_instance(&self, lua_id: u32) -> LuaResult<bool> {
+        let lua = self.lua();
+        let script = instance_script(&lua, lua_id)?;
+        let Some(this) = remove_instance(&lua, lua_id)? else {
+            return Ok(false);
+        };
+        if let Some(on_despawn) = instance_callback(&this, "on_despawn")? {
+            let name = || format!("{}.on_despawn", script.as_deref().unwrap_or("?"));
+            profiled(&lua, "script", name, || {
+                with_budget(&lua, |_| on_despawn.call::<()>(this))
+            })?;
+        }
+        Ok(true)
+    }
+
+    /// Entities with a script attached, in spawn order
+    pub fn instances(&self) -> LuaResult<Vec<ScriptInstance>> {
+        let lua = self.lua();
+        list_instances(&lua)
+    }
+
+    /// Module of the script attached to an entity
+    pub fn instance_script(&self, lua_id: u32) -> Option<String> {
+        let lua = self.lua();
+        instance_script(&lua, lua_id).ok().flatten()
+    }
+
+    /// Delivers an event to every `on(name, fn)` handler. Each handler gets its own
+    /// instruction budget; failures are passed to `on_error` with the handler's module.
+    pub fn dispatch_event(
+        &self,
+        name: &str,
+        payload: &serde_json::Value,
+        mut on_error: impl FnMut(Option<&str>, mlua::Error),
+    ) -> LuaResult<()> {
+        let lua = self.lua();
+        let failures = dispatch_event(&lua, name, payload, |func, payload| {
+            profiled(
+                &lua,
+                "event",
+                || format!("on({})", name),
+                || with_budget(&lua, |_| func.call::<()>(payload)),
+            )
+        })?;
+        for (module, e) in failures {
+            on_error(module.as_deref(), e);
+        }
+        Ok(())
+    }
+
+    /// Turns timing of every call into Lua on or off; see `collect_profile`
+    pub fn set_profiling(&self, enabled: bool) {
+        set_profiling(&self.lua(), enabled);
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        is_profiling(&self.lua())
+    }
+
+    /// Turns sampling on or off: every 1000 VM instructions the running Lua
+    /// function is counted, which shows where time goes inside a slow callback
+    pub fn set_sampling(&self, enabled: bool) {
+        let lua = self.lua();
+        set_sampling(&lua, enabled);
+        update_hook(&lua);
+    }
+
+    pub fn is_sampling(&self) -> bool {
+        is_sampling(&self.lua())
+    }
+
+    /// Moves the calls timed and functions sampled since the last call into `profile`
+    pub fn collect_profile(&self, profile: &mut ScriptProfile) {
+        collect_profile(&self.lua(), profile);
+    }
+
+    /// Starts a Debug Adapter Protocol server on 127.0.0.1:`port` (0 picks a free
+    /// port) and returns the port it listens on. A client can set breakpoints in
+    /// script files, step, and inspect the stack, locals and expressions; while
+    /// the game is stopped, the Lua call that hit the breakpoint blocks the frame,
+    /// and expressions evaluated then run without an instruction limit.
+    /// Fails unless the runtime was created with `ScriptLimits::debugger`.
+    pub fn start_debugger(&self, port: u16) -> std::io::Result<u16> {
+        start_debugger(&self.lua(), port)
+    }
+
+    /// Handles debugger requests that arrived since the last call, such as new
+    /// breakpoints or a pause; call once per frame
+    pub fn poll_debugger(&self) {
+        let lua = self.lua();
+        if poll_debugger(&lua) {
+            update_hook(&lua);
+        }
+    }
+
+    /// Runs `f` on the game state the bindings work on, if they are set up
+    pub fn with_game_state<R>(&self, f: impl FnOnce(&mut LuaGameState) -> R) -> Option<R> {
+        let lua = self.lua();
+        let mut game_state = lua.app_data_mut::<LuaGameState>()?;
+        Some(f(&mut game_state))
+    }
+
+    /// Get the Lua instance for binding setup. A panic while the lock was held
+    /// does not leave the VM unusable, so poisoning is ignored.
     pub fn lua(&self) -> std::sync::RwLockReadGuard<'_, Lua> {
-        self.lua.read().unwrap()
+        self.lua.read().unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Get mutable Lua instance for binding setup
     pub fn lua_mut(&self) -> std::sync::RwLockWriteGuard<'_, Lua> {
-        self.lua.write().unwrap()
+        self.lua.write().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
@@ -93,3 +627,246 @@ impl Default for LuaRuntime {
         Self::new().expect("Failed to create Lua runtime")
     }
 }
+
+/// `require` implementation: returns the cached module value or loads the module
+/// from the scripts directory, detecting require cycles
+fn require_module(lua: &Lua, name: &str) -> LuaResult<Value> {
+    let modules: Table = lua.named_registry_value(MODULES_KEY)?;
+    let cached: Value = modules.get(name)?;
+    if !cached.is_nil() {
+        return Ok(cached);
+    }
+
+    let path = {
+        let registry = lua.app_data_ref::<ModuleRegistry>().unwrap();
+        if let Some(start) = registry.loading.iter().position(|m| m == name) {
+            return Err(mlua::Error::RuntimeError(format!(
+                "circular require: {} -> {}",
+                registry.loading[start..].join(" -> "),
+                name
+            )));
+        }
+        let Some(dir) = registry.scripts_dir.as_ref() else {
+            return Err(mlua::Error::RuntimeError(format!(
+                "cannot require '{}': no scripts directory configured",
+                name
+            )));
+        };
+        resolve_module(dir, name).ok_or_else(|| {
+            mlua::Error::RuntimeError(format!(
+                "module '{}' not found in {}",
+                name,
+                dir.display()
+            ))
+        })?
+    };
+
+    let source = std::fs::read_to_string(&path)?;
+    run_module(lua, name, Some(&path), source)
+}
+
+/// Calls `on_update(self, dt)` of an instance of `script`, with its own budget
+fn run_instance_update(lua: &Lua, lua_id: u32, script: &str, delta: f32) -> LuaResult<()> {
+    let Some(this) = instance_table(lua, lua_id)? else {
+        return Ok(());
+    };
+    match module_callback(lua, script, &this, "on_update")? {
+        Some(on_update) => profiled(
+            lua,
+            "script",
+            || format!("{}.on_update", script),
+            || with_budget(lua, |_| on_update.call::<()>((this, delta))),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Executes a module's source, caching its return value (or `true`) for `require`
+fn run_module(lua: &Lua, name: &str, path: Option<&Path>, source: String) -> LuaResult<Value> {
+    // "@path" chunk names make Lua report errors as "scripts/player.lua:12: ..."
+    let chunk_name = match path {
+        Some(path) => format!("@{}", path.display()),
+        None => name.to_string(),
+    };
+
+    lua.app_data_mut::<ModuleRegistry>()
+        .unwrap()
+        .loading
+        .push(name.to_string());
+    // The registry borrow must not be held here: the chunk may `require` others
+    let result = lua.load(source.as_str()).set_name(chunk_name).eval::<Value>();
+    // The module may have redefined globals and callbacks, even if it failed
+    invalidate_functions(lua);
+    let mut registry = lua.app_data_mut::<ModuleRegistry>().unwrap();
+    registry.loading.pop();
+
+    let value = match result? {
+        Value::Nil => Value::Boolean(true),
+        value => value,
+    };
+    registry.sources.insert(name.to_string(), source);
+    drop(registry);
+
+    let modules: Table = lua.named_registry_value(MODULES_KEY)?;
+    modules.set(name, value.clone())?;
+    Ok(value)
+}
+
+/// `persist(key, defaults)`: returns the state table stored under `key`, creating it
+/// from `defaults` the first time. On later calls (e.g. when a script is hot-reloaded)
+/// the existing table is returned with any newly added default fields filled in.
+fn persistent_table(lua: &Lua, key: &str, defaults: Option<Table>) -> LuaResult<Table> {
+    let store: Table = lua.named_registry_value(PERSISTENT_KEY)?;
+    let defaults = match defaults {
+        Some(defaults) => defaults,
+        None => lua.create_table()?,
+    };
+
+    match store.get::<Option<Table>>(key)? {
+        Some(existing) => {
+            for pair in defaults.pairs::<Value, Value>() {
+                let (field, value) = pair?;
+                if existing.raw_get::<Value>(field.clone())?.is_nil() {
+                    existing.raw_set(field, value)?;
+                }
+            }
+            Ok(existing)
+        }
+        None => {
+            store.set(key, defaults.clone())?;
+            Ok(defaults)
+        }
+    }
+}
+
+/// Module name for a file inside the configured scripts directory
+pub(crate) fn module_for_path(lua: &Lua, path: &Path) -> Option<String> {
+    let dir = lua
+        .app_data_ref::<ModuleRegistry>()
+        .unwrap()
+        .scripts_dir
+        .clone()?;
+    let dir = dir.canonicalize().unwrap_or(dir);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    module_name_for(path.strip_prefix(&dir).ok()?)
+}
+
+/// Module a Lua function was defined in, or the chunk path outside the scripts directory
+fn function_module(lua: &Lua, func: &Function) -> Option<String> {
+    let source = func.info().source?;
+    let path = source.strip_prefix('@')?;
+    Some(module_for_path(lua, Path::new(path)).unwrap_or_else(|| path.to_string()))
+}
+
+/// The module whose chunk is currently executing, if any
+fn loading_module(lua: &Lua) -> Option<String> {
+    lua.app_data_ref::<ModuleRegistry>()
+        .unwrap()
+        .loading
+        .last()
+        .cloned()
+}
+
+/// Registers an `on_before_reload` / `on_after_reload` callback for the loading module
+fn add_reload_hook(lua: &Lua, kind: &str, func: Function) -> LuaResult<()> {
+    let module = loading_module(lua).ok_or_else(|| {
+        mlua::Error::RuntimeError(format!(
+            "on_{}_reload must be called while a script is loading",
+            kind
+        ))
+    })?;
+
+    let hooks: Table = lua.named_registry_value(RELOAD_HOOKS_KEY)?;
+    let module_hooks = match hooks.get::<Option<Table>>(module.as_str())? {
+        Some(table) => table,
+        None => {
+            let table = lua.create_table()?;
+            hooks.set(module.as_str(), table.clone())?;
+            table
+        }
+    };
+    let list = match module_hooks.get::<Option<Table>>(kind)? {
+        Some(table) => table,
+        None => {
+            let table = lua.create_table()?;
+            module_hooks.set(kind, table.clone())?;
+            table
+        }
+    };
+    list.raw_set(list.raw_len() + 1, func)
+}
+
+/// Calls a module's reload hooks in registration order, stopping at the first failure
+fn run_reload_hooks(lua: &Lua, module: &str, kind: &str) -> LuaResult<()> {
+    let hooks: Table = lua.named_registry_value(RELOAD_HOOKS_KEY)?;
+    let Some(module_hooks) = hooks.get::<Option<Table>>(module)? else {
+        return Ok(());
+    };
+    let Some(list) = module_hooks.get::<Option<Table>>(kind)? else {
+        return Ok(());
+    };
+    for func in list.sequence_values::<Function>() {
+        func?.call::<()>(module)?;
+    }
+    Ok(())
+}
+
+/// Finds `<dir>/a/b.lua` or `<dir>/a/b/init.lua` for module `a.b`
+fn resolve_module(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.split('.').any(|part| part.is_empty() || part == "..") {
+        return None;
+    }
+    let relative: PathBuf = name.split('.').collect();
+    let file = dir.join(&relative).with_extension("lua");
+    if file.is_file() {
+        return Some(file);
+    }
+    let init = dir.join(relative).join("init.lua");
+    init.is_file().then_some(init)
+}
+
+fn collect_modules(root: &Path, dir: &Path, names: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.starts_with('.'))
+            .unwrap_or(true);
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            if path == root.join(SCRIPT_TESTS_DIR) {
+                continue;
+            }
+            collect_modules(root, &path, names)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            if let Some(name) = module_name_for(relative) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `ai/enemy.lua` -> `ai.enemy`, `ai/init.lua` -> `ai`. Type stubs (`*.d.lua`)
+/// are for the editor only and are not modules.
+fn module_name_for(relative: &Path) -> Option<String> {
+    if relative.extension().and_then(|e| e.to_str()) != Some("lua") {
+        return None;
+    }
+    let file_name = relative.file_name().and_then(|n| n.to_str())?;
+    if file_name.ends_with(".d.lua") {
+        return None;
+    }
+    let mut parts: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_str().map(str::to_string))
+        .collect::<Option<_>>()?;
+    if parts.len() > 1 && parts.last().map(String::as_str) == Some("init") {
+        parts.pop();
+    }
+    Some(parts.join("."))
+}
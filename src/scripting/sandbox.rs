@@ -0,0 +1,291 @@
+use mlua::{
+    DebugEvent, HookTriggers, Lua, LuaOptions, Result as LuaResult, StdLib, Table, VmState,
+};
+use std::fmt;
+
+use super::debugger::{on_line, wants_line_events, DEBUG_LIBRARY_KEY};
+use super::profiler::{is_sampling, record_sample};
+use crate::config::ScriptingConfig;
+
+/// Instructions executed between two checks of the per-call budget
+const HOOK_INSTRUCTION_STEP: u32 = 1000;
+
+/// Base library functions that load code from files or strings
+const REMOVED_GLOBALS: &[&str] = &["dofile", "loadfile", "load"];
+
+/// The only `os` functions scripts can reach
+const ALLOWED_OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Resource limits applied to every script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// Maximum VM instructions one call from Rust may run (`None` = unlimited)
+    pub instructions_per_call: Option<u64>,
+    /// Maximum size of the Lua heap in bytes (`None` = unlimited)
+    pub memory_bytes: Option<usize>,
+    /// Load the `debug` library for `LuaRuntime::start_debugger`. This turns
+    /// off mlua's safe mode, so precompiled bytecode is no longer rejected.
+    pub debugger: bool,
+}
+
+impl ScriptLimits {
+    /// Limits from the `scripting` config section, where 0 means unlimited
+    pub fn from_config(config: &ScriptingConfig) -> Self {
+        Self {
+            instructions_per_call: (config.instruction_limit > 0)
+                .then_some(config.instruction_limit),
+            memory_bytes: (config.memory_limit_mb > 0)
+                .then(|| (config.memory_limit_mb as usize).saturating_mul(1024 * 1024)),
+            debugger: config.debug_port > 0,
+        }
+    }
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self::from_config(&ScriptingConfig::default())
+    }
+}
+
+/// A script ran past one of its `ScriptLimits`.
+///
+/// `LuaRuntime` returns these as `mlua::Error::ExternalError`;
+/// use `err.downcast_ref::<ScriptLimitError>()` to tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptLimitError {
+    /// The call ran more VM instructions than allowed
+    Instructions { limit: u64 },
+    /// The Lua heap grew past the memory cap
+    Memory { limit: usize },
+}
+
+impl fmt::Display for ScriptLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptLimitError::Instructions { limit } => {
+                write!(f, "instruction limit of {} per call exceeded", limit)
+            }
+            ScriptLimitError::Memory { limit } => {
+                write!(f, "memory limit of {} bytes exceeded", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptLimitError {}
+
+/// Limits and the running instruction count, stored as Lua app data
+struct SandboxState {
+    limits: ScriptLimits,
+    instructions_used: u64,
+}
+
+/// Creates a Lua state with a whitelisted standard library and the given limits.
+///
+/// Scripts get `table`, `string`, `math`, `utf8`, `coroutine` and a read-only
+/// slice of `os` (clock and time). `io`, `package`, `debug` and the functions
+/// that load code (`load`, `loadfile`, `dofile`) are not available.
+pub fn new_sandboxed_lua(limits: ScriptLimits) -> LuaResult<Lua> {
+    let libs = StdLib::TABLE
+        | StdLib::STRING
+        | StdLib::MATH
+        | StdLib::UTF8
+        | StdLib::COROUTINE
+        | StdLib::OS;
+    let lua = if limits.debugger {
+        // SAFETY: `debug` is moved out of the globals into the registry below,
+        // so only the Rust side (the script debugger) can reach it
+        let lua = unsafe { Lua::unsafe_new_with(libs | StdLib::DEBUG, LuaOptions::default()) };
+        let globals = lua.globals();
+        lua.set_named_registry_value(DEBUG_LIBRARY_KEY, globals.get::<Table>("debug")?)?;
+        globals.set("debug", mlua::Value::Nil)?;
+        lua
+    } else {
+        Lua::new_with(libs, LuaOptions::default())?
+    };
+
+    let globals = lua.globals();
+    for name in REMOVED_GLOBALS {
+        globals.set(*name, mlua::Value::Nil)?;
+    }
+    let os: Table = globals.get("os")?;
+    let safe_os = lua.create_table()?;
+    for name in ALLOWED_OS_FUNCTIONS {
+        safe_os.set(*name, os.get::<mlua::Value>(*name)?)?;
+    }
+    globals.set("os", safe_os)?;
+
+    lua.set_app_data(SandboxState {
+        limits,
+        instructions_used: 0,
+    });
+    update_hook(&lua);
+
+    if let Some(limit) = limits.memory_bytes {
+        lua.set_memory_limit(limit)?;
+    }
+
+    Ok(lua)
+}
+
+/// Installs the instruction hook if the instruction limit or profiler sampling
+/// needs it, adding line events while the debugger has breakpoints or steps,
+/// and removes it otherwise. Call again when sampling or the debugger changes.
+pub(crate) fn update_hook(lua: &Lua) {
+    let limit = lua
+        .app_data_ref::<SandboxState>()
+        .and_then(|state| state.limits.instructions_per_call);
+    let lines = wants_line_events(lua);
+    if limit.is_none() && !is_sampling(lua) && !lines {
+        lua.remove_hook();
+        return;
+    }
+
+    let mut triggers = HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTION_STEP);
+    if lines {
+        triggers = triggers.every_line();
+    }
+    lua.set_hook(triggers, move |lua, debug| {
+        if let DebugEvent::Line = debug.event() {
+            on_line(lua, &debug);
+            return Ok(VmState::Continue);
+        }
+        if is_sampling(lua) {
+            record_sample(lua, &debug);
+        }
+        let Some(limit) = limit else {
+            return Ok(VmState::Continue);
+        };
+        // The count keeps growing after the limit is hit, so a script that
+        // catches the error with pcall is stopped again at the next check
+        let mut state = lua.app_data_mut::<SandboxState>().unwrap();
+        state.instructions_used += u64::from(HOOK_INSTRUCTION_STEP);
+        if state.instructions_used > limit {
+            return Err(mlua::Error::external(ScriptLimitError::Instructions {
+                limit,
+            }));
+        }
+        Ok(VmState::Continue)
+    });
+}
+
+/// Runs a call from Rust into Lua with a fresh instruction budget.
+/// Limit violations anywhere in the call come back as a `ScriptLimitError`.
+pub(crate) fn with_budget<T>(lua: &Lua, call: impl FnOnce(&Lua) -> LuaResult<T>) -> LuaResult<T> {
+    if let Some(mut state) = lua.app_data_mut::<SandboxState>() {
+        state.instructions_used = 0;
+    }
+    call(lua).map_err(|e| match limit_violation(lua, &e) {
+        Some(violation) => mlua::Error::external(violation),
+        None => e,
+    })
+}
+
+/// Finds a limit violation behind an error, looking through callback wrappers
+fn limit_violation(lua: &Lua, err: &mlua::Error) -> Option<ScriptLimitError> {
+    match err {
+        mlua::Error::ExternalError(_) => err.downcast_ref::<ScriptLimitError>().copied(),
+        mlua::Error::MemoryError(_) => {
+            let state = lua.app_data_ref::<SandboxState>()?;
+            let limit = state.limits.memory_bytes?;
+            Some(ScriptLimitError::Memory { limit })
+        }
+        mlua::Error::CallbackError { cause, .. } => limit_violation(lua, cause),
+        mlua::Error::WithContext { cause, .. } => limit_violation(lua, cause),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(instructions: Option<u64>, memory: Option<usize>) -> ScriptLimits {
+        ScriptLimits {
+            instructions_per_call: instructions,
+            memory_bytes: memory,
+            debugger: false,
+        }
+    }
+
+    #[test]
+    fn unsafe_globals_are_not_available() {
+        let lua = new_sandboxed_lua(ScriptLimits::default()).unwrap();
+        for expression in [
+            "io",
+            "debug",
+            "package",
+            "require",
+            "load",
+            "loadfile",
+            "dofile",
+            "os.execute",
+            "os.exit",
+            "os.getenv",
+            "os.remove",
+        ] {
+            let value: mlua::Value = lua.load(expression).eval().unwrap();
+            assert!(value.is_nil(), "{} = {:?}", expression, value);
+        }
+        assert!(lua.load("os.clock()").eval::<f64>().is_ok());
+    }
+
+    #[test]
+    fn binary_chunks_are_rejected() {
+        let lua = new_sandboxed_lua(ScriptLimits::default()).unwrap();
+        let bytecode = lua.load("return 1").into_function().unwrap().dump(true);
+        assert!(lua.load(&bytecode[..]).exec().is_err());
+    }
+
+    #[test]
+    fn infinite_loops_hit_the_instruction_limit() {
+        let lua = new_sandboxed_lua(limits(Some(100_000), None)).unwrap();
+        let err = with_budget(&lua, |lua| lua.load("while true do end").exec()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ScriptLimitError>(),
+            Some(&ScriptLimitError::Instructions { limit: 100_000 })
+        );
+
+        // Catching the error does not buy the script more instructions
+        let err = with_budget(&lua, |lua| {
+            lua.load("pcall(function() while true do end end) while true do end")
+                .exec()
+        })
+        .unwrap_err();
+        assert!(err.downcast_ref::<ScriptLimitError>().is_some(), "{}", err);
+    }
+
+    #[test]
+    fn large_allocations_hit_the_memory_limit() {
+        let lua = new_sandboxed_lua(limits(None, Some(4 * 1024 * 1024))).unwrap();
+        let err = with_budget(&lua, |lua| {
+            lua.load("local s = string.rep('x', 16 * 1024 * 1024)")
+                .exec()
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ScriptLimitError>(),
+            Some(&ScriptLimitError::Memory {
+                limit: 4 * 1024 * 1024
+            })
+        );
+    }
+
+    #[test]
+    fn the_vm_keeps_working_after_a_limit_error() {
+        let lua = new_sandboxed_lua(limits(Some(100_000), Some(4 * 1024 * 1024))).unwrap();
+        lua.load("count = 1").exec().unwrap();
+        for chunk in [
+            "while true do end",
+            "local s = string.rep('x', 16 * 1024 * 1024)",
+        ] {
+            assert!(with_budget(&lua, |lua| lua.load(chunk).exec()).is_err());
+            let count: i64 = with_budget(&lua, |lua| {
+                lua.load("count = count + 1 return count").eval()
+            })
+            .unwrap();
+            assert!(count > 1);
+        }
+        assert_eq!(lua.load("count").eval::<i64>().unwrap(), 3);
+    }
+}
@@ -0,0 +1,673 @@
+use mlua::{Table, Value};
+use std::fmt;
+
+/// Fields a `spawn{...}` table may have
+const SPAWN_FIELDS: &[&str] = &[
+    "sprite",
+    "transform",
+    "tags",
+    "health",
+    "velocity",
+    "script",
+];
+const SPRITE_FIELDS: &[&str] = &["size", "color", "image", "flip_x", "flip_y"];
+const TRANSFORM_FIELDS: &[&str] = &["x", "y", "z", "rotation", "scale"];
+
+/// Marker components a spawned entity can be given through `tags`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpawnTag {
+    /// Player-controlled; also gets velocity, move speed and health
+    Player,
+    CameraTarget,
+    WorldElement,
+}
+
+impl SpawnTag {
+    pub const ALL: [SpawnTag; 3] = [
+        SpawnTag::Player,
+        SpawnTag::CameraTarget,
+        SpawnTag::WorldElement,
+    ];
+
+    /// Name scripts use for the tag
+    pub fn name(self) -> &'static str {
+        match self {
+            SpawnTag::Player => "player",
+            SpawnTag::CameraTarget => "camera_target",
+            SpawnTag::WorldElement => "world_element",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tag| tag.name() == name)
+    }
+}
+
+/// Sprite part of a spawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteSpec {
+    /// Size in pixels; `None` uses the image's own size
+    pub size: Option<[f32; 2]>,
+    /// sRGBA color components in 0..=1 (a tint when an image is set)
+    pub color: [f32; 4],
+    /// Image path relative to the assets directory
+    pub image: Option<String>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+/// An entity a script asked to spawn, applied by Rust on the next command pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSpawn {
+    pub lua_id: u32,
+    /// Entities without a sprite are invisible (e.g. triggers or spawn points)
+    pub sprite: Option<SpriteSpec>,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    /// Rotation around the z axis in radians
+    pub rotation: f32,
+    pub scale: [f32; 2],
+    pub tags: Vec<SpawnTag>,
+    /// Initial (current, max) health
+    pub health: Option<[f32; 2]>,
+    pub velocity: Option<[f32; 2]>,
+    /// Script attached to the entity (see `ScriptComponent`)
+    pub script: Option<String>,
+}
+
+/// A spawn table field that failed validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnError {
+    /// Dotted path of the bad field, e.g. `sprite.color`
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid spawn field `{}`: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+impl PendingSpawn {
+    /// A plain colored rectangle, as made by `spawn_sprite`
+    pub fn sprite(lua_id: u32, size: [f32; 2], color: [f32; 4], position: [f32; 3]) -> Self {
+        Self {
+            lua_id,
+            sprite: Some(SpriteSpec {
+                size: Some(size),
+                color,
+                image: None,
+                flip_x: false,
+                flip_y: false,
+            }),
+            x: position[0],
+            y: position[1],
+            z: position[2],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+            tags: Vec::new(),
+            health: None,
+            velocity: None,
+            script: None,
+        }
+    }
+
+    /// Validates a `spawn{...}` table:
+    ///
+    /// ```lua
+    /// spawn{
+    ///     sprite = { size = {50, 50}, color = "#3498db", image = "player.png" },
+    ///     transform = { x = 0, y = 0, z = 1, rotation = 0.5, scale = 2 },
+    ///     tags = { "player", "camera_target" },
+    ///     health = 100,            -- or { current = 50, max = 100 }
+    ///     velocity = { 10, 0 },
+    ///     script = "enemy",        -- per-entity script with on_spawn/on_update/on_despawn
+    /// }
+    /// ```
+    ///
+    /// Unknown fields are rejected so typos don't go unnoticed.
+    pub fn from_table(lua_id: u32, table: &Table) -> Result<Self, SpawnError> {
+        check_fields(table, "", SPAWN_FIELDS)?;
+
+        let sprite = match field(table, "", "sprite")? {
+            Value::Nil => None,
+            Value::Table(sprite) => Some(sprite_spec(&sprite)?),
+            other => return Err(type_error("sprite", "a table", &other)),
+        };
+
+        let mut spawn = Self {
+            lua_id,
+            sprite,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+            tags: Vec::new(),
+            health: None,
+            velocity: None,
+            script: None,
+        };
+
+        match field(table, "", "transform")? {
+            Value::Nil => {}
+            Value::Table(transform) => {
+                check_fields(&transform, "transform", TRANSFORM_FIELDS)?;
+                let number_field = |name: &str| -> Result<Option<f32>, SpawnError> {
+                    number(
+                        field(&transform, "transform", name)?,
+                        &format!("transform.{}", name),
+                    )
+                };
+                spawn.x = number_field("x")?.unwrap_or(0.0);
+                spawn.y = number_field("y")?.unwrap_or(0.0);
+                spawn.z = number_field("z")?.unwrap_or(0.0);
+                spawn.rotation = number_field("rotation")?.unwrap_or(0.0);
+                if let Some(scale) = pair(
+                    field(&transform, "transform", "scale")?,
+                    "transform.scale",
+                    ["x", "y"],
+                    true,
+                )? {
+                    spawn.scale = scale;
+                }
+            }
+            other => return Err(type_error("transform", "a table", &other)),
+        }
+
+        match field(table, "", "tags")? {
+            Value::Nil => {}
+            Value::Table(tags) => spawn.tags = spawn_tags(&tags)?,
+            other => return Err(type_error("tags", "a list of tag names", &other)),
+        }
+
+        spawn.health = match field(table, "", "health")? {
+            Value::Table(health) => {
+                check_fields(&health, "health", &["current", "max"])?;
+                let max = number(field(&health, "health", "max")?, "health.max")?
+                    .ok_or_else(|| error("health.max", "is required"))?;
+                let current =
+                    number(field(&health, "health", "current")?, "health.current")?.unwrap_or(max);
+                Some([current, max])
+            }
+            value => number(value, "health")?.map(|max| [max, max]),
+        };
+        if let Some([current, max]) = spawn.health {
+            if max <= 0.0 {
+                return Err(error(
+                    "health.max",
+                    &format!("must be greater than 0, got {}", max),
+                ));
+            }
+            if !(0.0..=max).contains(&current) {
+                return Err(error(
+                    "health.current",
+                    &format!("must be between 0 and {}, got {}", max, current),
+                ));
+            }
+        }
+
+        spawn.velocity = pair(field(table, "", "velocity")?, "velocity", ["x", "y"], false)?;
+        spawn.script = match field(table, "", "script")? {
+            Value::Nil => None,
+            Value::String(script) => Some(script.to_string_lossy().to_string()),
+            other => return Err(type_error("script", "a script name", &other)),
+        };
+        Ok(spawn)
+    }
+}
+
+fn sprite_spec(sprite: &Table) -> Result<SpriteSpec, SpawnError> {
+    check_fields(sprite, "sprite", SPRITE_FIELDS)?;
+
+    let image = match field(sprite, "sprite", "image")? {
+        Value::Nil => None,
+        Value::String(path) => Some(path.to_string_lossy().to_string()),
+        other => return Err(type_error("sprite.image", "an image path", &other)),
+    };
+    let size = pair(
+        field(sprite, "sprite", "size")?,
+        "sprite.size",
+        ["width", "height"],
+        true,
+    )?;
+    if size.is_none() && image.is_none() {
+        return Err(error(
+            "sprite.size",
+            "is required for sprites without an image",
+        ));
+    }
+    if let Some([width, height]) = size {
+        if width < 0.0 || height < 0.0 {
+            return Err(error(
+                "sprite.size",
+                &format!("must not be negative, got {} x {}", width, height),
+            ));
+        }
+    }
+
+    let color = match field(sprite, "sprite", "color")? {
+        Value::Nil => [1.0, 1.0, 1.0, 1.0],
+        value => color(value, "sprite.color")?,
+    };
+
+    Ok(SpriteSpec {
+        size,
+        color,
+        image,
+        flip_x: boolean(field(sprite, "sprite", "flip_x")?, "sprite.flip_x")?,
+        flip_y: boolean(field(sprite, "sprite", "flip_y")?, "sprite.flip_y")?,
+    })
+}
+
+fn spawn_tags(tags: &Table) -> Result<Vec<SpawnTag>, SpawnError> {
+    let mut parsed = Vec::new();
+    for (index, value) in tags.clone().sequence_values::<Value>().enumerate() {
+        let path = format!("tags[{}]", index + 1);
+        let value = value.map_err(|e| error(&path, &e.to_string()))?;
+        let Value::String(name) = &value else {
+            return Err(type_error(&path, "a tag name", &value));
+        };
+        let name = name.to_string_lossy().to_string();
+        let tag = SpawnTag::from_name(&name).ok_or_else(|| {
+            let names: Vec<_> = SpawnTag::ALL.iter().map(|t| t.name()).collect();
+            error(
+                &path,
+                &format!(
+                    "unknown tag '{}' (expected one of: {})",
+                    name,
+                    names.join(", ")
+                ),
+            )
+        })?;
+        if !parsed.contains(&tag) {
+            parsed.push(tag);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parses a color given as `{r, g, b[, a]}`, `{r=, g=, b=[, a=]}` or `"#rrggbb[aa]"`
+fn color(value: Value, path: &str) -> Result<[f32; 4], SpawnError> {
+    let components = match value {
+        Value::String(hex) => return hex_color(&hex.to_string_lossy(), path),
+        Value::Table(table) => {
+            if table.raw_len() > 0 {
+                check_fields(&table, path, &[])?;
+                let values: Vec<Value> = table
+                    .sequence_values::<Value>()
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| error(path, &e.to_string()))?;
+                if !(3..=4).contains(&values.len()) {
+                    return Err(error(
+                        path,
+                        &format!("expected 3 or 4 components, got {}", values.len()),
+                    ));
+                }
+                let mut components = [1.0; 4];
+                for (i, value) in values.into_iter().enumerate() {
+                    components[i] = number(value, &format!("{}[{}]", path, i + 1))?.unwrap_or(1.0);
+                }
+                components
+            } else {
+                check_fields(&table, path, &["r", "g", "b", "a"])?;
+                let component = |name: &str| -> Result<Option<f32>, SpawnError> {
+                    number(field(&table, path, name)?, &format!("{}.{}", path, name))
+                };
+                let required = |name: &str| -> Result<f32, SpawnError> {
+                    component(name)?
+                        .ok_or_else(|| error(&format!("{}.{}", path, name), "is required"))
+                };
+                [
+                    required("r")?,
+                    required("g")?,
+                    required("b")?,
+                    component("a")?.unwrap_or(1.0),
+                ]
+            }
+        }
+        other => {
+            return Err(type_error(
+                path,
+                "a color table or \"#rrggbb\" string",
+                &other,
+            ))
+        }
+    };
+
+    if let Some(bad) = components.iter().find(|c| !(0.0..=1.0).contains(*c)) {
+        return Err(error(
+            path,
+            &format!("components must be between 0 and 1, got {}", bad),
+        ));
+    }
+    Ok(components)
+}
+
+fn hex_color(hex: &str, path: &str) -> Result<[f32; 4], SpawnError> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if !(digits.len() == 6 || digits.len() == 8) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(error(
+            path,
+            &format!("expected \"#rrggbb\" or \"#rrggbbaa\", got {:?}", hex),
+        ));
+    }
+    let mut components = [1.0; 4];
+    for (i, component) in components.iter_mut().enumerate().take(digits.len() / 2) {
+        let byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).unwrap_or(0);
+        *component = f32::from(byte) / 255.0;
+    }
+    Ok(components)
+}
+
+/// Reads a two-number field given as `{a, b}` or with the named keys
+/// (and, if `allow_scalar`, a single number used for both)
+fn pair(
+    value: Value,
+    path: &str,
+    names: [&str; 2],
+    allow_scalar: bool,
+) -> Result<Option<[f32; 2]>, SpawnError> {
+    match value {
+        Value::Nil => Ok(None),
+        Value::Integer(_) | Value::Number(_) if allow_scalar => {
+            let n = number(value, path)?.unwrap_or(0.0);
+            Ok(Some([n, n]))
+        }
+        Value::Table(table) => {
+            if table.raw_len() > 0 {
+                check_fields(&table, path, &[])?;
+                if table.raw_len() != 2 {
+                    return Err(error(
+                        path,
+                        &format!("expected 2 numbers, got {}", table.raw_len()),
+                    ));
+                }
+                let get = |index: usize| -> Result<f32, SpawnError> {
+                    let path = format!("{}[{}]", path, index);
+                    let value = table.raw_get(index).unwrap_or(Value::Nil);
+                    number(value, &path)?.ok_or_else(|| error(&path, "is required"))
+                };
+                Ok(Some([get(1)?, get(2)?]))
+            } else {
+                check_fields(&table, path, &names)?;
+                let get = |name: &str| -> Result<f32, SpawnError> {
+                    number(field(&table, path, name)?, &join(path, name))?
+                        .ok_or_else(|| error(&join(path, name), "is required"))
+                };
+                Ok(Some([get(names[0])?, get(names[1])?]))
+            }
+        }
+        other => {
+            let expected = if allow_scalar {
+                format!("a number or {{{}, {}}}", names[0], names[1])
+            } else {
+                format!("{{{}, {}}}", names[0], names[1])
+            };
+            Err(type_error(path, &expected, &other))
+        }
+    }
+}
+
+fn number(value: Value, path: &str) -> Result<Option<f32>, SpawnError> {
+    match value {
+        Value::Nil => Ok(None),
+        Value::Integer(n) => Ok(Some(n as f32)),
+        Value::Number(n) if n.is_finite() => Ok(Some(n as f32)),
+        Value::Number(n) => Err(error(path, &format!("must be a finite number, got {}", n))),
+        other => Err(type_error(path, "a number", &other)),
+    }
+}
+
+fn boolean(value: Value, path: &str) -> Result<bool, SpawnError> {
+    match value {
+        Value::Nil => Ok(false),
+        Value::Boolean(b) => Ok(b),
+        other => Err(type_error(path, "a boolean", &other)),
+    }
+}
+
+fn field(table: &Table, path: &str, name: &str) -> Result<Value, SpawnError> {
+    table
+        .raw_get::<Value>(name)
+        .map_err(|e| error(&join(path, name), &e.to_string()))
+}
+
+/// Rejects keys that are not in `allowed`; array tables pass `&[]` to allow only
+/// their sequence part
+fn check_fields(table: &Table, path: &str, allowed: &[&str]) -> Result<(), SpawnError> {
+    let len = table.raw_len() as i64;
+    for pair in table.clone().pairs::<Value, Value>() {
+        let (key, _) = pair.map_err(|e| error(path, &e.to_string()))?;
+        match key {
+            Value::String(name) => {
+                let name = name.to_string_lossy().to_string();
+                if !allowed.contains(&name.as_str()) {
+                    let expected = if allowed.is_empty() {
+                        "a list".to_string()
+                    } else {
+                        format!("one of: {}", allowed.join(", "))
+                    };
+                    return Err(error(
+                        &join(path, &name),
+                        &format!("unknown field (expected {})", expected),
+                    ));
+                }
+            }
+            Value::Integer(i) if (1..=len).contains(&i) && allowed.is_empty() => {}
+            other => {
+                return Err(error(
+                    path,
+                    &format!(
+                        "unexpected key {}",
+                        other
+                            .to_string()
+                            .unwrap_or_else(|_| other.type_name().to_string())
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+fn type_error(path: &str, expected: &str, value: &Value) -> SpawnError {
+    error(
+        path,
+        &format!("expected {}, got {}", expected, value.type_name()),
+    )
+}
+
+fn error(path: &str, message: &str) -> SpawnError {
+    SpawnError {
+        field: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::Lua;
+
+    fn parse(spec: &str) -> Result<PendingSpawn, SpawnError> {
+        let lua = Lua::new();
+        let table: Table = lua.load(spec).eval().unwrap();
+        PendingSpawn::from_table(1, &table)
+    }
+
+    /// Asserts that each spec fails validation at the given field
+    fn assert_rejected(cases: &[(&str, &str)]) {
+        for (spec, field) in cases {
+            match parse(spec) {
+                Ok(spawn) => panic!("{} was accepted: {:?}", spec, spawn),
+                Err(e) => assert_eq!(e.field, *field, "{}: {}", spec, e),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected_at_every_level() {
+        assert_rejected(&[
+            ("{ sprite = { size = 1 }, colour = '#fff' }", "colour"),
+            (
+                "{ sprite = { size = 1, colour = '#fff' } }",
+                "sprite.colour",
+            ),
+            ("{ transform = { x = 1, rot = 2 } }", "transform.rot"),
+            (
+                "{ health = { current = 1, max = 2, min = 0 } }",
+                "health.min",
+            ),
+            ("{ velocity = { x = 1, y = 2, z = 3 } }", "velocity.z"),
+            ("{ velocity = { 1, 2, x = 3 } }", "velocity.x"),
+            (
+                "{ sprite = { size = { width = 1, height = 2, depth = 3 } } }",
+                "sprite.size.depth",
+            ),
+            (
+                "{ sprite = { size = 1, color = { r = 1, g = 1, b = 1, alpha = 1 } } }",
+                "sprite.color.alpha",
+            ),
+        ]);
+        let err = parse("{ colour = '#fff' }").unwrap_err();
+        assert_eq!(
+            err.message,
+            "unknown field (expected one of: sprite, transform, tags, health, velocity, script)"
+        );
+    }
+
+    #[test]
+    fn size_scale_and_velocity_accept_each_form() {
+        for (size, expected) in [
+            ("10", [10.0, 10.0]),
+            ("{ 20, 30 }", [20.0, 30.0]),
+            ("{ width = 20, height = 30 }", [20.0, 30.0]),
+        ] {
+            let spawn = parse(&format!("{{ sprite = {{ size = {} }} }}", size)).unwrap();
+            assert_eq!(spawn.sprite.unwrap().size, Some(expected), "{}", size);
+        }
+        let spawn = parse("{ sprite = { image = 'player.png' } }").unwrap();
+        assert_eq!(spawn.sprite.unwrap().size, None);
+
+        for (scale, expected) in [
+            ("2", [2.0, 2.0]),
+            ("{ 2, 3 }", [2.0, 3.0]),
+            ("{ x = 2, y = 3 }", [2.0, 3.0]),
+        ] {
+            let spawn = parse(&format!("{{ transform = {{ scale = {} }} }}", scale)).unwrap();
+            assert_eq!(spawn.scale, expected, "{}", scale);
+        }
+        assert_eq!(parse("{}").unwrap().scale, [1.0, 1.0]);
+
+        for velocity in ["{ 1, 2 }", "{ x = 1, y = 2 }"] {
+            let spawn = parse(&format!("{{ velocity = {} }}", velocity)).unwrap();
+            assert_eq!(spawn.velocity, Some([1.0, 2.0]), "{}", velocity);
+        }
+
+        assert_rejected(&[
+            ("{ sprite = {} }", "sprite.size"),
+            ("{ sprite = { size = -1 } }", "sprite.size"),
+            ("{ sprite = { size = { 1, 2, 3 } } }", "sprite.size"),
+            (
+                "{ sprite = { size = { width = 1 } } }",
+                "sprite.size.height",
+            ),
+            ("{ sprite = { size = 'big' } }", "sprite.size"),
+            ("{ transform = { scale = { 1 } } }", "transform.scale"),
+            ("{ velocity = 5 }", "velocity"),
+            ("{ velocity = { 1, 'fast' } }", "velocity[2]"),
+        ]);
+    }
+
+    #[test]
+    fn health_accepts_a_number_or_a_table() {
+        for (health, expected) in [
+            ("100", [100.0, 100.0]),
+            ("{ max = 100 }", [100.0, 100.0]),
+            ("{ current = 50, max = 100 }", [50.0, 100.0]),
+        ] {
+            let spawn = parse(&format!("{{ health = {} }}", health)).unwrap();
+            assert_eq!(spawn.health, Some(expected), "{}", health);
+        }
+        assert_rejected(&[
+            ("{ health = { current = 50 } }", "health.max"),
+            ("{ health = 0 }", "health.max"),
+            (
+                "{ health = { current = 150, max = 100 } }",
+                "health.current",
+            ),
+            ("{ health = { current = -1, max = 100 } }", "health.current"),
+            ("{ health = 'lots' }", "health"),
+        ]);
+    }
+
+    #[test]
+    fn colors_are_parsed_or_rejected() {
+        for (color, expected) in [
+            ("'#ff0000'", [1.0, 0.0, 0.0, 1.0]),
+            ("'00ff0000'", [0.0, 1.0, 0.0, 0.0]),
+            ("{ 0, 0, 1 }", [0.0, 0.0, 1.0, 1.0]),
+            ("{ 0, 0, 1, 0.5 }", [0.0, 0.0, 1.0, 0.5]),
+            ("{ r = 1, g = 1, b = 0, a = 0.5 }", [1.0, 1.0, 0.0, 0.5]),
+        ] {
+            let spawn =
+                parse(&format!("{{ sprite = {{ size = 1, color = {} }} }}", color)).unwrap();
+            assert_eq!(spawn.sprite.unwrap().color, expected, "{}", color);
+        }
+        assert_rejected(&[
+            ("{ sprite = { size = 1, color = 'red' } }", "sprite.color"),
+            ("{ sprite = { size = 1, color = '#ff00' } }", "sprite.color"),
+            (
+                "{ sprite = { size = 1, color = '#gg0000' } }",
+                "sprite.color",
+            ),
+            (
+                "{ sprite = { size = 1, color = '#ff00000' } }",
+                "sprite.color",
+            ),
+            (
+                "{ sprite = { size = 1, color = { 1, 1 } } }",
+                "sprite.color",
+            ),
+            (
+                "{ sprite = { size = 1, color = { 1, 1, 2 } } }",
+                "sprite.color",
+            ),
+            (
+                "{ sprite = { size = 1, color = { r = 1, g = 1 } } }",
+                "sprite.color.b",
+            ),
+            ("{ sprite = { size = 1, color = 7 } }", "sprite.color"),
+        ]);
+    }
+
+    #[test]
+    fn tags_must_be_known_names() {
+        let spawn = parse("{ tags = { 'player', 'camera_target', 'player' } }").unwrap();
+        assert_eq!(spawn.tags, vec![SpawnTag::Player, SpawnTag::CameraTarget]);
+
+        assert_rejected(&[
+            ("{ tags = { 'player', 'enemy' } }", "tags[2]"),
+            ("{ tags = { 1 } }", "tags[1]"),
+            ("{ tags = 'player' }", "tags"),
+        ]);
+        let err = parse("{ tags = { 'enemy' } }").unwrap_err();
+        assert_eq!(
+            err.message,
+            "unknown tag 'enemy' (expected one of: player, camera_target, world_element)"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use mlua::{Function, Lua, Result as LuaResult, Table, Value};
+use std::fmt;
+
+/// Named registry table of script systems: name -> {stage, func, order, seq, module}
+const SYSTEMS_KEY: &str = "revgame.systems";
+
+/// Named registry table of registration sequence numbers by system name (key 0
+/// holds the last one handed out). Never cleared, so a system re-registered by
+/// a hot reload keeps its place in the order.
+const SYSTEM_SEQ_KEY: &str = "revgame.system_seq";
+
+/// Points in the frame where script systems run, in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ScriptStage {
+    /// Reading input and deciding intent
+    Input,
+    /// Gameplay logic and movement
+    Simulation,
+    /// Follow-up work that depends on simulation results (UI, attachments)
+    Late,
+    /// Camera placement, after everything else has moved
+    Camera,
+}
+
+impl ScriptStage {
+    pub const ALL: [ScriptStage; 4] = [
+        ScriptStage::Input,
+        ScriptStage::Simulation,
+        ScriptStage::Late,
+        ScriptStage::Camera,
+    ];
+
+    /// Name scripts use for the stage
+    pub fn name(self) -> &'static str {
+        match self {
+            ScriptStage::Input => "input",
+            ScriptStage::Simulation => "simulation",
+            ScriptStage::Late => "late",
+            ScriptStage::Camera => "camera",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|stage| stage.name() == name)
+    }
+}
+
+impl fmt::Display for ScriptStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A per-frame function registered by a script with `register_system`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptSystem {
+    pub name: String,
+    pub stage: ScriptStage,
+    /// Lower runs first within a stage; ties run in registration order
+    pub order: i64,
+    /// Script that registered the system, if it was registered while loading
+    pub module: Option<String>,
+}
+
+/// Systems a module registered, detached while the module is being reloaded
+pub(crate) type DetachedSystems = Vec<(String, Table)>;
+
+pub(crate) fn init_systems(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(SYSTEMS_KEY, lua.create_table()?)?;
+    lua.set_named_registry_value(SYSTEM_SEQ_KEY, lua.create_table()?)
+}
+
+/// Removes every system; systems registered again keep their place in the order
+pub(crate) fn clear_systems(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(SYSTEMS_KEY, lua.create_table()?)
+}
+
+/// `register_system(name, stage, fn, order)`: adds a system or replaces the one
+/// with the same name
+pub(crate) fn register_system(
+    lua: &Lua,
+    name: &str,
+    stage: &str,
+    func: Function,
+    order: Option<i64>,
+    module: Option<String>,
+) -> LuaResult<()> {
+    let stage = ScriptStage::from_name(stage).ok_or_else(|| {
+        let names: Vec<_> = ScriptStage::ALL.iter().map(|s| s.name()).collect();
+        mlua::Error::RuntimeError(format!(
+            "unknown stage '{}' for system '{}' (expected one of: {})",
+            stage,
+            name,
+            names.join(", ")
+        ))
+    })?;
+
+    let sequence: Table = lua.named_registry_value(SYSTEM_SEQ_KEY)?;
+    let seq = match sequence.get::<Option<i64>>(name)? {
+        Some(seq) => seq,
+        None => {
+            let seq = sequence.get::<Option<i64>>(0)?.unwrap_or(0) + 1;
+            sequence.set(0, seq)?;
+            sequence.set(name, seq)?;
+            seq
+        }
+    };
+
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    let entry = lua.create_table()?;
+    entry.set("stage", stage.name())?;
+    entry.set("func", func)?;
+    entry.set("order", order.unwrap_or(0))?;
+    entry.set("seq", seq)?;
+    entry.set("module", module)?;
+    systems.set(name, entry)
+}
+
+/// `unregister_system(name)`: removes a system, returning whether it existed
+pub(crate) fn unregister_system(lua: &Lua, name: &str) -> LuaResult<bool> {
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    let existed = !systems.get::<Value>(name)?.is_nil();
+    systems.set(name, Value::Nil)?;
+    Ok(existed)
+}
+
+/// Systems registered for a stage, in run order
+pub(crate) fn stage_systems(lua: &Lua, stage: ScriptStage) -> LuaResult<Vec<ScriptSystem>> {
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    let mut found = Vec::new();
+    for pair in systems.pairs::<String, Table>() {
+        let (name, entry) = pair?;
+        if entry.get::<String>("stage")? != stage.name() {
+            continue;
+        }
+        let seq: i64 = entry.get("seq")?;
+        found.push((
+            seq,
+            ScriptSystem {
+                name,
+                stage,
+                order: entry.get("order")?,
+                module: entry.get("module")?,
+            },
+        ));
+    }
+    found.sort_by_key(|(seq, system)| (system.order, *seq));
+    Ok(found.into_iter().map(|(_, system)| system).collect())
+}
+
+/// The function behind a registered system
+pub(crate) fn system_function(lua: &Lua, name: &str) -> LuaResult<Function> {
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    match systems.get::<Option<Table>>(name)? {
+        Some(entry) => entry.get("func"),
+        None => Err(mlua::Error::RuntimeError(format!(
+            "no system named '{}'",
+            name
+        ))),
+    }
+}
+
+/// Removes and returns every system a module registered
+pub(crate) fn detach_module_systems(lua: &Lua, module: &str) -> LuaResult<DetachedSystems> {
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    let mut detached = Vec::new();
+    for pair in systems.pairs::<String, Table>() {
+        let (name, entry) = pair?;
+        if entry.get::<Option<String>>("module")?.as_deref() == Some(module) {
+            detached.push((name, entry));
+        }
+    }
+    for (name, _) in &detached {
+        systems.set(name.as_str(), Value::Nil)?;
+    }
+    Ok(detached)
+}
+
+/// Puts detached systems back, e.g. after a reload failed
+pub(crate) fn restore_systems(lua: &Lua, detached: DetachedSystems) -> LuaResult<()> {
+    let systems: Table = lua.named_registry_value(SYSTEMS_KEY)?;
+    for (name, entry) in detached {
+        systems.set(name, entry)?;
+    }
+    Ok(())
+}
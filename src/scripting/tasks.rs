@@ -0,0 +1,320 @@
+use mlua::{Function, Lua, Result as LuaResult, Table, Thread, ThreadStatus, Value};
+
+use super::api::register_binding;
+
+/// Named registry table of running tasks: id -> {thread, module, owner, wait, remaining, until}
+const TASKS_KEY: &str = "revgame.tasks";
+
+/// Named registry value holding the last task id handed out
+const NEXT_TASK_ID_KEY: &str = "revgame.next_task_id";
+
+/// Wait functions the prelude defines
+const WAIT_FUNCTIONS: &[&str] = &["wait", "wait_frames", "wait_until"];
+
+/// Lua side of the scheduler: the wait functions yield a request back to Rust
+const TASK_PRELUDE: &str = r#"
+local waits = {}
+
+local function check_task(name)
+    if not coroutine.isyieldable() then
+        error(name .. "() can only be used inside spawn_task", 3)
+    end
+end
+
+function waits.wait(seconds)
+    check_task("wait")
+    coroutine.yield("time", seconds or 0)
+end
+
+function waits.wait_frames(frames)
+    check_task("wait_frames")
+    coroutine.yield("frames", frames or 1)
+end
+
+function waits.wait_until(predicate)
+    check_task("wait_until")
+    if type(predicate) ~= "function" then
+        error("wait_until expects a function", 2)
+    end
+    coroutine.yield("until", predicate)
+end
+
+return waits
+"#;
+
+/// Sets up the task table and registers the wait functions. Like every binding
+/// they are plain globals: a script that assigns to `wait` replaces it for all
+/// scripts, and its tasks stop waiting correctly.
+pub(crate) fn init_tasks(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(TASKS_KEY, lua.create_table()?)?;
+    lua.set_named_registry_value(NEXT_TASK_ID_KEY, 0)?;
+    let waits: Table = lua.load(TASK_PRELUDE).set_name("=revgame.tasks").eval()?;
+    for name in WAIT_FUNCTIONS {
+        register_binding(lua, name, waits.get(*name)?)?;
+    }
+    Ok(())
+}
+
+/// `spawn_task(fn, owner)`: queues `func` as a coroutine that starts on the next
+/// scheduler update. Tasks with an `owner` entity are cancelled when it despawns.
+pub(crate) fn spawn_task(
+    lua: &Lua,
+    func: Function,
+    owner: Option<u32>,
+    module: Option<String>,
+) -> LuaResult<u64> {
+    let id = lua.named_registry_value::<u64>(NEXT_TASK_ID_KEY)? + 1;
+    lua.set_named_registry_value(NEXT_TASK_ID_KEY, id)?;
+
+    let task = lua.create_table()?;
+    task.set("thread", lua.create_thread(func)?)?;
+    task.set("module", module)?;
+    task.set("owner", owner)?;
+    task.set("wait", "frames")?;
+    task.set("remaining", 0)?;
+
+    let tasks: Table = lua.named_registry_value(TASKS_KEY)?;
+    tasks.set(id, task)?;
+    Ok(id)
+}
+
+/// `cancel_task(id)`: stops a task, returning whether it was still running
+pub(crate) fn cancel_task(lua: &Lua, id: u64) -> LuaResult<bool> {
+    let tasks: Table = lua.named_registry_value(TASKS_KEY)?;
+    let existed = !tasks.get::<Value>(id)?.is_nil();
+    tasks.set(id, Value::Nil)?;
+    Ok(existed)
+}
+
+/// Cancels every task matching `pred`, returning how many were cancelled
+pub(crate) fn cancel_tasks_where(
+    lua: &Lua,
+    mut pred: impl FnMut(u64, &Table) -> LuaResult<bool>,
+) -> LuaResult<usize> {
+    let tasks: Table = lua.named_registry_value(TASKS_KEY)?;
+    let mut cancelled = Vec::new();
+    for pair in tasks.pairs::<u64, Table>() {
+        let (id, task) = pair?;
+        if pred(id, &task)? {
+            cancelled.push(id);
+        }
+    }
+    for id in &cancelled {
+        tasks.set(*id, Value::Nil)?;
+    }
+    Ok(cancelled.len())
+}
+
+/// The id of the most recently spawned task
+pub(crate) fn last_task_id(lua: &Lua) -> LuaResult<u64> {
+    lua.named_registry_value(NEXT_TASK_ID_KEY)
+}
+
+pub(crate) fn task_count(lua: &Lua) -> LuaResult<usize> {
+    let tasks: Table = lua.named_registry_value(TASKS_KEY)?;
+    let mut count = 0;
+    for pair in tasks.pairs::<Value, Value>() {
+        pair?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Advances every task by one frame: counts down waits, checks `wait_until`
+/// predicates and resumes tasks that are ready. `call` gets each task's id and
+/// runs its step (so the caller can apply limits and time it). Failed tasks are
+/// removed and returned with their id and module.
+pub(crate) fn update_tasks(
+    lua: &Lua,
+    delta: f32,
+    mut call: impl FnMut(u64, &dyn Fn() -> LuaResult<bool>) -> LuaResult<bool>,
+) -> LuaResult<Vec<(u64, Option<String>, mlua::Error)>> {
+    let tasks: Table = lua.named_registry_value(TASKS_KEY)?;
+
+    // Snapshot the ids: tasks spawned while updating start next frame
+    let mut ids = Vec::new();
+    for pair in tasks.pairs::<u64, Value>() {
+        ids.push(pair?.0);
+    }
+    ids.sort_unstable();
+
+    let mut failures = Vec::new();
+    for id in ids {
+        // Cancelled by a task that ran earlier this frame
+        let Some(task) = tasks.get::<Option<Table>>(id)? else {
+            continue;
+        };
+
+        let result = call(id, &|| advance_task(lua, &task, delta));
+        match result {
+            Ok(true) => {}
+            Ok(false) => tasks.set(id, Value::Nil)?,
+            Err(e) => {
+                tasks.set(id, Value::Nil)?;
+                failures.push((id, task.get("module")?, e));
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// Runs one task for one frame; returns whether it is still alive
+fn advance_task(lua: &Lua, task: &Table, delta: f32) -> LuaResult<bool> {
+    let ready = match task.get::<String>("wait")?.as_str() {
+        "time" => {
+            let remaining = task.get::<f64>("remaining")? - f64::from(delta);
+            task.set("remaining", remaining)?;
+            remaining <= 0.0
+        }
+        "frames" => {
+            let remaining = task.get::<i64>("remaining")? - 1;
+            task.set("remaining", remaining)?;
+            remaining < 0
+        }
+        "until" => {
+            let predicate: Function = task.get("until")?;
+            predicate.call::<bool>(())?
+        }
+        other => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "unknown task wait '{}'",
+                other
+            )))
+        }
+    };
+    if !ready {
+        return Ok(true);
+    }
+
+    let thread: Thread = task.get("thread")?;
+    let (kind, arg) = thread.resume::<(Option<String>, Value)>(())?;
+    if thread.status() != ThreadStatus::Resumable {
+        return Ok(false);
+    }
+
+    // A bare `coroutine.yield()` waits a single frame
+    match kind.as_deref() {
+        Some("time") => {
+            task.set("wait", "time")?;
+            task.set("remaining", lua.unpack::<f64>(arg)?)?;
+        }
+        Some("until") => {
+            task.set("wait", "until")?;
+            task.set("until", arg)?;
+        }
+        _ => {
+            task.set("wait", "frames")?;
+            let frames = lua.unpack::<Option<i64>>(arg)?.unwrap_or(1);
+            // `remaining` counts the frames to skip before resuming
+            task.set("remaining", frames.max(1) - 1)?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::sandbox::{new_sandboxed_lua, ScriptLimits};
+
+    type Failures = Vec<(u64, Option<String>, mlua::Error)>;
+
+    /// A Lua state with the scheduler and a `frame` counter and `clock`
+    fn scheduler() -> Lua {
+        let lua = new_sandboxed_lua(ScriptLimits::default()).unwrap();
+        init_tasks(&lua).unwrap();
+        lua.load("frame = 0 clock = 0 log = {}").exec().unwrap();
+        lua
+    }
+
+    fn start(lua: &Lua, body: &str, module: Option<&str>) -> u64 {
+        let func: Function = lua.load(format!("function() {} end", body)).eval().unwrap();
+        spawn_task(lua, func, None, module.map(str::to_string)).unwrap()
+    }
+
+    /// Advances the frame counter and clock, then the tasks
+    fn run_frame(lua: &Lua, delta: f32) -> Failures {
+        lua.load(format!("frame = frame + 1 clock = clock + {}", delta))
+            .exec()
+            .unwrap();
+        update_tasks(lua, delta, |_, step| step()).unwrap()
+    }
+
+    fn log(lua: &Lua) -> Vec<f64> {
+        lua.load("log").eval().unwrap()
+    }
+
+    #[test]
+    fn wait_resumes_after_the_game_time_passed() {
+        let lua = scheduler();
+        start(&lua, "log[1] = clock wait(0.5) log[2] = clock", None);
+        for _ in 0..2 {
+            assert!(run_frame(&lua, 0.25).is_empty());
+            assert_eq!(log(&lua), vec![0.25]);
+        }
+        assert!(run_frame(&lua, 0.25).is_empty());
+        assert_eq!(log(&lua), vec![0.25, 0.75]);
+        assert_eq!(task_count(&lua).unwrap(), 0);
+    }
+
+    #[test]
+    fn wait_frames_skips_that_many_frames() {
+        let lua = scheduler();
+        start(
+            &lua,
+            "log[1] = frame wait_frames(3) log[2] = frame wait_frames() log[3] = frame",
+            None,
+        );
+        for _ in 0..6 {
+            assert!(run_frame(&lua, 0.1).is_empty());
+        }
+        assert_eq!(log(&lua), vec![1.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn wait_until_checks_the_predicate_once_per_frame() {
+        let lua = scheduler();
+        lua.load("checks = 0").exec().unwrap();
+        start(
+            &lua,
+            "wait_until(function() checks = checks + 1 return frame >= 5 end) log[1] = frame",
+            None,
+        );
+        for _ in 0..8 {
+            assert!(run_frame(&lua, 0.1).is_empty());
+        }
+        assert_eq!(log(&lua), vec![5.0]);
+        assert_eq!(lua.load("checks").eval::<u32>().unwrap(), 4);
+    }
+
+    #[test]
+    fn failed_tasks_are_reported_once_and_removed() {
+        let lua = scheduler();
+        let id = start(&lua, "wait_frames() error('boom')", Some("enemy"));
+        assert!(run_frame(&lua, 0.1).is_empty());
+
+        let failures = run_frame(&lua, 0.1);
+        assert_eq!(failures.len(), 1);
+        let (failed, module, e) = &failures[0];
+        assert_eq!(*failed, id);
+        assert_eq!(module.as_deref(), Some("enemy"));
+        assert!(e.to_string().contains("boom"), "{}", e);
+        assert_eq!(task_count(&lua).unwrap(), 0);
+        assert!(run_frame(&lua, 0.1).is_empty());
+    }
+
+    #[test]
+    fn waiting_outside_a_task_is_an_error() {
+        let lua = scheduler();
+        for call in [
+            "wait(1)",
+            "wait_frames(1)",
+            "wait_until(function() return true end)",
+        ] {
+            let err = lua.load(call).exec().unwrap_err();
+            let name = call.split('(').next().unwrap();
+            let expected = format!("{}() can only be used inside spawn_task", name);
+            assert!(err.to_string().contains(&expected), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Gameplay systems run in a Bevy app without a window
+#![cfg(feature = "engine")]
+
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use revgame::game::ScriptedGameplayPlugin;
+use revgame::scripting::{
+    lua_dispatch_events, setup_lua_bindings, LuaEmittedEvent, LuaEntityMap, LuaEventAppExt,
+    LuaEventSet, LuaGameState, LuaRuntime, ScriptErrors, ScriptEvent,
+};
+use revgame::{GameConfig, GameState};
+
+/// Frame time of the test apps
+const DT: f32 = 1.0 / 60.0;
+
+/// A windowless app running `ScriptedGameplayPlugin` on the scripts in
+/// `scripts_dir`, updated until it is in `GameState::InGame`
+fn scripted_app(scripts_dir: &Path) -> App {
+    let mut config = GameConfig::default();
+    config.scripting.scripts_dir = scripts_dir.to_path_buf();
+    config.scripting.hot_reload = false;
+
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            DT,
+        )))
+        .insert_resource(config)
+        .init_resource::<ButtonInput<KeyCode>>()
+        .init_state::<GameState>()
+        .add_plugins(ScriptedGameplayPlugin);
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::InGame);
+    for _ in 0..10 {
+        app.update();
+        if *app.world().resource::<State<GameState>>().get() == GameState::InGame {
+            return app;
+        }
+    }
+    panic!("the app never reached InGame");
+}
+
+/// Runs a chunk of Lua in the app's runtime
+fn exec(app: &App, code: &str) {
+    let runtime = app.world().resource::<LuaRuntime>();
+    runtime.lua().load(code).exec().unwrap();
+}
+
+fn eval<T: mlua::FromLuaMulti>(app: &App, expression: &str) -> T {
+    let runtime = app.world().resource::<LuaRuntime>();
+    runtime.lua().load(expression).eval().unwrap()
+}
+
+/// Translation of the entity a Lua id is mapped to
+fn translation(app: &App, lua_id: u32) -> Vec3 {
+    let entity = app.world().resource::<LuaEntityMap>().get(lua_id).unwrap();
+    app.world().get::<Transform>(entity).unwrap().translation
+}
+
+/// A runtime with the bindings set up and `content` loaded as module `name`
+fn runtime_with(name: &str, content: &str) -> LuaRuntime {
+    let mut runtime = LuaRuntime::new().unwrap();
+    setup_lua_bindings(&runtime.lua(), LuaGameState::new()).unwrap();
+    runtime.load_script_content(name, content).unwrap();
+    runtime
+}
+
+#[derive(Event, Serialize)]
+struct Ping {
+    n: u32,
+}
+
+#[derive(Event, Deserialize)]
+struct Pong {
+    n: u32,
+}
+
+#[derive(Resource, Default)]
+struct Pongs(Vec<u32>);
+
+fn send_first_ping(mut pings: EventWriter<Ping>, mut sent: Local<bool>) {
+    if !*sent {
+        pings.send(Ping { n: 1 });
+        *sent = true;
+    }
+}
+
+fn collect_pongs(mut pongs: EventReader<Pong>, mut seen: ResMut<Pongs>) {
+    seen.0.extend(pongs.read().map(|pong| pong.n));
+}
+
+#[test]
+fn events_round_trip_through_lua() {
+    let runtime = runtime_with(
+        "bus",
+        "last_ping = nil
+         on('ping', function(ping)
+             last_ping = ping.n
+             emit('pong', { n = ping.n + 1 })
+         end)",
+    );
+
+    let mut app = App::new();
+    app.insert_resource(runtime)
+        .init_resource::<ScriptErrors>()
+        .init_resource::<Pongs>()
+        .add_event::<ScriptEvent>()
+        .add_event::<LuaEmittedEvent>()
+        .add_event_to_lua::<Ping>("ping")
+        .add_event_from_lua::<Pong>("pong")
+        .add_systems(
+            Update,
+            (
+                lua_dispatch_events,
+                send_first_ping.before(LuaEventSet::ToLua),
+                collect_pongs.after(LuaEventSet::FromLua),
+            ),
+        );
+
+    // The ping reaches Lua the frame it is sent
+    app.update();
+    let runtime = app.world().resource::<LuaRuntime>();
+    let last_ping: Option<u32> = runtime.lua().load("last_ping").eval().unwrap();
+    assert_eq!(last_ping, Some(1));
+    assert!(app.world().resource::<Pongs>().0.is_empty());
+
+    // What the handler emitted comes back as a typed event next frame
+    app.update();
+    assert_eq!(app.world().resource::<Pongs>().0, vec![2]);
+    assert!(app.world().resource::<ScriptErrors>().is_empty());
+}
+
+#[test]
+fn ids_of_entities_despawned_by_rust_are_forgotten() {
+    let scripts = tempfile::tempdir().unwrap();
+    let mut app = scripted_app(scripts.path());
+    exec(
+        &app,
+        "failures = {}
+         on('command_failed', function(e) failures[#failures + 1] = e.message end)
+         id = spawn{ sprite = { size = 10 } }",
+    );
+    app.update();
+    let lua_id: u32 = eval(&app, "id");
+    let entity = app.world().resource::<LuaEntityMap>().get(lua_id).unwrap();
+
+    app.world_mut().despawn(entity);
+    app.update();
+    assert_eq!(app.world().resource::<LuaEntityMap>().get(lua_id), None);
+    assert!(!eval::<bool>(&app, "is_alive(id)"));
+
+    // Commands for the stale id fail rather than reach another entity
+    exec(&app, "set_position(id, 1, 2)");
+    app.update();
+    app.update();
+    let failures: Vec<String> = eval(&app, "failures");
+    assert_eq!(failures, [format!("entity {} does not exist", lua_id)]);
+}
+
+#[test]
+fn set_velocity_moves_entities_every_frame() {
+    let scripts = tempfile::tempdir().unwrap();
+    let mut app = scripted_app(scripts.path());
+    exec(
+        &app,
+        "id = spawn{ sprite = { size = 10 }, transform = { x = 10 } }",
+    );
+    app.update();
+    let lua_id: u32 = eval(&app, "id");
+
+    // Applied the frame the command is, then integrated every frame
+    exec(&app, "set_velocity(id, 60, -30)");
+    for _ in 0..30 {
+        app.update();
+    }
+    let moved = translation(&app, lua_id);
+    assert!(
+        (moved.x - (10.0 + 60.0 * DT * 30.0)).abs() < 1e-3,
+        "{}",
+        moved
+    );
+    assert!((moved.y - (-30.0 * DT * 30.0)).abs() < 1e-3, "{}", moved);
+
+    // Scripts see where it stopped
+    exec(&app, "set_velocity(id, 0, 0)");
+    app.update();
+    app.update();
+    assert_eq!(translation(&app, lua_id), moved);
+    let (x, y): (f32, f32) = eval(&app, "get_position(id)");
+    assert_eq!((x, y), (moved.x, moved.y));
+}
+
+#[test]
+fn only_changed_entities_are_synced_to_lua() {
+    let scripts = tempfile::tempdir().unwrap();
+    let mut app = scripted_app(scripts.path());
+    exec(
+        &app,
+        "still = spawn{ sprite = { size = 10 }, transform = { x = 5, y = 5 }, health = 100 }
+         moving = spawn{ sprite = { size = 10 }, velocity = { x = 60, y = 0 } }",
+    );
+    app.update();
+    app.update();
+    let (still, moving): (u32, u32) = eval(&app, "still, moving");
+    assert_eq!(eval::<(f32, f32)>(&app, "get_position(still)"), (5.0, 5.0));
+
+    // Overwrite what scripts see: only a change in Bevy replaces it again
+    let runtime = app.world().resource::<LuaRuntime>();
+    runtime.with_game_state(|state| {
+        state.update_entity_position(still, -1.0, -1.0);
+        state.update_entity_health(still, 1.0, 1.0);
+        state.update_entity_position(moving, -1.0, -1.0);
+        state.publish_frame();
+    });
+    let moving_before = translation(&app, moving);
+    app.update();
+    assert_eq!(
+        eval::<(f32, f32)>(&app, "get_position(still)"),
+        (-1.0, -1.0)
+    );
+    assert_eq!(eval::<(f32, f32)>(&app, "get_health(still)"), (1.0, 1.0));
+    assert_eq!(
+        eval::<(f32, f32)>(&app, "get_position(moving)"),
+        (moving_before.x, moving_before.y)
+    );
+
+    exec(&app, "set_position(still, 7, 8) set_health(still, 50)");
+    app.update();
+    app.update();
+    assert_eq!(eval::<(f32, f32)>(&app, "get_position(still)"), (7.0, 8.0));
+    assert_eq!(eval::<(f32, f32)>(&app, "get_health(still)"), (50.0, 100.0));
+}
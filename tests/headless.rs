@@ -0,0 +1,50 @@
+//! Both gameplay implementations run for a few seconds through `revgame-headless`
+#![cfg(feature = "engine")]
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value as Json;
+
+/// Runs `revgame-headless` from the repository root holding D for every tick and
+/// returns the world it dumped
+fn run_headless(mode: &str, ticks: u32) -> Json {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = dir.path().join("inputs.json");
+    let output = dir.path().join("world.json");
+    std::fs::write(&inputs, r#"[{ "tick": 0, "press": ["KeyD"] }]"#).unwrap();
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let status = Command::new(env!("CARGO_BIN_EXE_revgame-headless"))
+        .current_dir(root)
+        .args(["--config", "config.json", "--mode", mode])
+        .args(["--ticks", &ticks.to_string()])
+        .arg("--inputs")
+        .arg(&inputs)
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "revgame-headless --mode {} failed", mode);
+
+    serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap()
+}
+
+fn assert_game_ran(mode: &str) {
+    let world = run_headless(mode, 120);
+    assert_eq!(world["state"], "InGame", "{}", world);
+    assert_eq!(world["players"].as_array().unwrap().len(), 1, "{}", world);
+    let x = world["players"][0]["x"].as_f64().unwrap();
+    assert!(x > 0.0, "the player did not move right: x = {}", x);
+    assert!(world["world_elements"].as_u64().unwrap() > 0, "{}", world);
+}
+
+#[test]
+fn native_gameplay_runs_headless() {
+    assert_game_ran("native");
+}
+
+#[test]
+fn scripted_gameplay_runs_headless() {
+    assert_game_ran("lua");
+}
@@ -0,0 +1,867 @@
+//! Gameplay scripts run headlessly through `ScriptHarness`
+#![cfg(feature = "scripting")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use mlua::{Function, Lua, MultiValue, Value as LuaValue};
+use serde_json::{json, Value as Json};
+
+use revgame::scripting::{
+    api_mismatches, lua_api_markdown, lua_stubs, reload_changed_scripts, run_lua_tests, LuaRuntime,
+    ScriptErrors, ScriptHarness, ScriptLimits, ScriptProfile, ScriptStage, ScriptWatcher, SpawnTag,
+    DEFAULT_TEST_DT, LOAD_CALLBACK, LUA_BINDINGS, LUA_STUBS_FILE,
+};
+
+fn scripts_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scripts")
+}
+
+fn harness_with_player() -> (ScriptHarness, u32) {
+    let mut harness = ScriptHarness::new(scripts_dir()).expect("scripts load");
+    let player: u32 = harness.eval("spawn_player()").expect("spawn_player");
+    harness.step(DEFAULT_TEST_DT);
+    (harness, player)
+}
+
+#[test]
+fn player_moves_right_while_d_is_held() {
+    let (mut harness, player) = harness_with_player();
+    harness.press("KeyD");
+    harness.step_frames(60, DEFAULT_TEST_DT);
+
+    let entity = harness.entity(player).unwrap();
+    assert!((entity.x - 200.0).abs() < 0.01, "x = {}", entity.x);
+    assert_eq!(entity.y, 0.0);
+    assert!(harness
+        .output()
+        .position_updates
+        .iter()
+        .any(|&(id, x, _)| id == player && x > 0.0));
+    assert!(harness.errors().is_empty());
+}
+
+#[test]
+fn spawn_player_queues_a_tagged_sprite() {
+    let (harness, player) = harness_with_player();
+    let spawn = &harness.output().spawns[0];
+    assert_eq!(spawn.lua_id, player);
+    assert_eq!(spawn.tags, vec![SpawnTag::Player, SpawnTag::CameraTarget]);
+    assert_eq!(spawn.health, Some([100.0, 100.0]));
+    assert_eq!(harness.player(), Some(player));
+}
+
+#[test]
+fn healthbar_follows_the_player() {
+    let (mut harness, player) = harness_with_player();
+    harness.step(DEFAULT_TEST_DT);
+    let background: u32 = harness.eval("HealthbarState.bg_id").unwrap();
+
+    harness.press("KeyW");
+    harness.step_frames(30, DEFAULT_TEST_DT);
+    harness.release("KeyW");
+    harness.step(DEFAULT_TEST_DT);
+
+    let player = harness.entity(player).unwrap();
+    let bar = harness.entity(background).unwrap();
+    assert!((bar.x - player.x).abs() < 0.01);
+    assert!((bar.y - (player.y + 35.0)).abs() < 0.01);
+}
+
+#[test]
+fn set_health_resizes_the_healthbar() {
+    let (mut harness, player) = harness_with_player();
+    harness.step(DEFAULT_TEST_DT);
+    let foreground: u32 = harness.eval("HealthbarState.fg_id").unwrap();
+
+    harness
+        .exec(&format!("set_health({}, 25)", player))
+        .unwrap();
+    harness.step_frames(2, DEFAULT_TEST_DT);
+
+    assert_eq!(harness.output().health_updates, vec![(player, 25.0)]);
+    assert_eq!(harness.entity(player).unwrap().health, Some((25.0, 100.0)));
+    let (width, _) = harness.entity(foreground).unwrap().size.unwrap();
+    assert!((width - 12.5).abs() < 0.01, "width = {}", width);
+}
+
+#[test]
+fn spawn_rejects_unknown_fields() {
+    let harness = ScriptHarness::new(scripts_dir()).unwrap();
+    let err = harness
+        .exec("spawn{ sprite = { size = 10 }, colour = '#fff' }")
+        .unwrap_err();
+    assert!(err.to_string().contains("colour"), "{}", err);
+    assert!(harness.output().spawns.is_empty());
+}
+
+#[test]
+fn lua_test_files_pass() {
+    let results = run_lua_tests(&scripts_dir()).unwrap();
+    assert!(!results.is_empty());
+    let failures: Vec<String> = results
+        .iter()
+        .filter_map(|result| {
+            let failure = result.failure.as_ref()?;
+            Some(format!(
+                "{} > {}: {}",
+                result.file.display(),
+                result.name,
+                failure
+            ))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn lua_api_reference_is_up_to_date() {
+    let harness = ScriptHarness::empty().unwrap();
+    assert_eq!(
+        api_mismatches(&harness.runtime().lua()).unwrap(),
+        Vec::<String>::new()
+    );
+
+    let stubs = std::fs::read_to_string(scripts_dir().join(LUA_STUBS_FILE)).unwrap();
+    assert!(
+        stubs == lua_stubs(),
+        "run `revgame lua-api` to update the stubs"
+    );
+    let docs_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("docs/lua-api.md");
+    let docs = std::fs::read_to_string(docs_path).unwrap();
+    assert!(
+        docs == lua_api_markdown(),
+        "run `revgame lua-api` to update the docs"
+    );
+}
+
+/// A value of a documented parameter type that gets past argument conversion
+fn placeholder(lua: &Lua, ty: &str) -> LuaValue {
+    let ty = ty.trim_end_matches('?');
+    match ty {
+        "integer" | "number" | "any" => LuaValue::Integer(1),
+        "boolean" => LuaValue::Boolean(true),
+        "string" => LuaValue::String(lua.create_string("a").unwrap()),
+        "table" | "SpawnSpec" => LuaValue::Table(lua.create_table().unwrap()),
+        _ if ty == "function" || ty.starts_with("fun(") => {
+            LuaValue::Function(lua.create_function(|_, ()| Ok(())).unwrap())
+        }
+        // A union of string literals, such as a system stage
+        _ if ty.starts_with('"') => {
+            let first = ty.split('"').nth(1).unwrap();
+            LuaValue::String(lua.create_string(first).unwrap())
+        }
+        _ => panic!("no placeholder for the Lua type {}", ty),
+    }
+}
+
+fn is_bad_argument(err: &mlua::Error) -> bool {
+    let message = err.to_string();
+    message.contains("bad argument") || message.contains("error converting Lua")
+}
+
+#[test]
+fn bindings_take_the_documented_parameters() {
+    let harness = ScriptHarness::empty().unwrap();
+    let lua = harness.runtime().lua();
+    for binding in LUA_BINDINGS {
+        let func: Function = lua.globals().get(binding.name).unwrap();
+        // The task waits are written in Lua and check their own arguments
+        if func.info().what != "C" {
+            continue;
+        }
+        let args: Vec<LuaValue> = binding
+            .params
+            .iter()
+            .map(|param| placeholder(&lua, param.ty))
+            .collect();
+        if let Err(e) = func.call::<MultiValue>(MultiValue::from_vec(args.clone())) {
+            assert!(!is_bad_argument(&e), "{}: {}", binding.name, e);
+        }
+
+        let required = binding
+            .params
+            .iter()
+            .filter(|param| !param.ty.ends_with('?'))
+            .count();
+        if required > 0 {
+            let missing = MultiValue::from_vec(args[..required - 1].to_vec());
+            let err = func.call::<MultiValue>(missing).unwrap_err();
+            assert!(
+                is_bad_argument(&err),
+                "{} without `{}`: {}",
+                binding.name,
+                binding.params[required - 1].name,
+                err
+            );
+        }
+    }
+}
+
+#[test]
+fn escape_requests_the_main_menu() {
+    let (mut harness, _) = harness_with_player();
+    harness.with_game_state(|state| state.set_game_state_read("InGame"));
+    harness.press("Escape");
+    harness.step(DEFAULT_TEST_DT);
+    assert_eq!(
+        harness.output().requested_game_state.as_deref(),
+        Some("MainMenu")
+    );
+}
+
+#[test]
+fn restarting_scripts_resets_persisted_state() {
+    let (mut harness, _) = harness_with_player();
+    harness.step(DEFAULT_TEST_DT);
+    assert!(harness.eval::<bool>("HealthbarState.spawned").unwrap());
+
+    harness
+        .runtime_mut()
+        .restart_scripts(|script, e| panic!("{} failed to restart: {}", script, e))
+        .unwrap();
+    assert!(!harness.eval::<bool>("HealthbarState.spawned").unwrap());
+    assert!(harness.runtime().has_function("spawn_player"));
+}
+
+#[test]
+fn commands_apply_in_call_order() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "local id = spawn{ sprite = { size = 10 }, health = 50 }
+             set_health(id, 20)
+             set_position(id, 5, 6)",
+        )
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+
+    let entity = harness.entity(1).unwrap();
+    assert_eq!(entity.health, Some((20.0, 50.0)));
+    assert_eq!((entity.x, entity.y), (5.0, 6.0));
+    assert!(harness.output().command_failures.is_empty());
+}
+
+#[test]
+fn sprite_size_applies_to_any_sprite() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "image = spawn{ sprite = { image = 'player.png' } }
+             marker = spawn{ transform = { x = 1 } }
+             set_sprite_size(image, 40, 20)
+             set_sprite_size(marker, 5, 5)",
+        )
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+
+    let image: u32 = harness.eval("image").unwrap();
+    let marker: u32 = harness.eval("marker").unwrap();
+    assert_eq!(harness.entity(image).unwrap().size, Some((40.0, 20.0)));
+    let failures = &harness.output().command_failures;
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].id, Some(marker));
+    assert_eq!(
+        failures[0].message,
+        format!("entity {} has no sprite", marker)
+    );
+}
+
+#[test]
+fn failed_commands_are_sent_to_scripts() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "failures = {}
+             on('command_failed', function(e)
+                 failures[#failures + 1] = e.command .. ': ' .. e.message
+             end)
+             local id = spawn_sprite(10, 10, 1, 1, 1, 0, 0, 0)
+             set_health(id, 20)",
+        )
+        .unwrap();
+    harness.step_frames(2, DEFAULT_TEST_DT);
+
+    assert_eq!(harness.output().command_failures.len(), 1);
+    let failure: String = harness.eval("failures[1]").unwrap();
+    assert_eq!(failure, "set_health: entity 1 has no health");
+}
+
+#[test]
+fn event_handlers_run_in_subscription_order() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "log = {}
+             on('ping', function(p) log[#log + 1] = 'first ' .. p.n end)
+             on('ping', function() error('boom') end)
+             on('ping', function(p) log[#log + 1] = 'third ' .. p.n end)
+             on('other', function() log[#log + 1] = 'other' end)
+             emit('ping', { n = 1 })",
+        )
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+
+    // The failing handler does not stop the ones after it
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(log, "first 1,third 1");
+    let errors: Vec<_> = harness.errors().recent().collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].callback, "on(ping)");
+    assert_eq!(
+        harness.output().emitted_events,
+        vec![("ping".to_string(), json!({ "n": 1 }))]
+    );
+
+    // Events emitted by handlers are delivered the next frame
+    harness
+        .exec("on('other', function() emit('ping', { n = 2 }) end) emit('other')")
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(log, "first 1,third 1,other");
+    harness.step(DEFAULT_TEST_DT);
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(log, "first 1,third 1,other,first 2,third 2");
+}
+
+#[test]
+fn systems_run_by_stage_then_order_then_registration() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "log = {}
+             local function logger(name)
+                 return function() log[#log + 1] = name end
+             end
+             register_system('follow', 'camera', logger('follow'))
+             register_system('ui', 'late', logger('ui'))
+             register_system('move', 'simulation', logger('move'))
+             register_system('collide', 'simulation', logger('collide'))
+             register_system('read_keys', 'input', logger('read_keys'))
+             register_system('spawn_wave', 'simulation', logger('spawn_wave'), -1)
+             -- Replacing a system keeps its place
+             register_system('move', 'simulation', logger('move again'))",
+        )
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(log, "read_keys,spawn_wave,move again,collide,ui,follow");
+    let simulation: Vec<_> = harness
+        .runtime()
+        .systems(ScriptStage::Simulation)
+        .unwrap()
+        .into_iter()
+        .map(|system| system.name)
+        .collect();
+    assert_eq!(simulation, ["spawn_wave", "move", "collide"]);
+    assert!(harness.errors().is_empty());
+}
+
+#[test]
+fn systems_need_a_known_stage() {
+    let harness = ScriptHarness::empty().unwrap();
+    let err = harness
+        .exec("register_system('physics', 'fixed', function() end)")
+        .unwrap_err();
+    assert!(
+        err.to_string().contains(
+            "unknown stage 'fixed' for system 'physics' \
+             (expected one of: input, simulation, late, camera)"
+        ),
+        "{}",
+        err
+    );
+    for stage in ScriptStage::ALL {
+        assert!(harness.runtime().systems(stage).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn despawned_entities_stay_dead() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .exec(
+            "failures = {}
+             on('command_failed', function(e)
+                 failures[#failures + 1] = e.command .. ': ' .. e.message
+             end)
+             id = spawn{ sprite = { size = 10 }, health = 10 }",
+        )
+        .unwrap();
+    harness.step(DEFAULT_TEST_DT);
+    let id: u32 = harness.eval("id").unwrap();
+    assert!(harness.eval::<bool>("is_alive(id)").unwrap());
+
+    harness
+        .exec(
+            "first = despawn(id)
+             second = despawn(id)
+             set_position(id, 1, 2)
+             set_health(id, 5)",
+        )
+        .unwrap();
+    assert!(harness.eval::<bool>("first").unwrap());
+    assert!(!harness.eval::<bool>("second").unwrap());
+    assert!(!harness.eval::<bool>("is_alive(id)").unwrap());
+
+    harness.step_frames(2, DEFAULT_TEST_DT);
+    assert!(harness.entity(id).is_none());
+    assert_eq!(harness.output().despawns, vec![id]);
+    let failures: Vec<String> = harness.eval("failures").unwrap();
+    assert_eq!(
+        failures,
+        [
+            format!("set_position: entity {} does not exist", id),
+            format!("set_health: entity {} does not exist", id),
+        ]
+    );
+}
+
+#[test]
+fn entity_scripts_update_as_one_batch() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    harness
+        .runtime_mut()
+        .load_script_content(
+            "ticker",
+            "return { on_update = function(self, dt) self.frames = (self.frames or 0) + 1 end }",
+        )
+        .unwrap();
+    harness
+        .exec("for _ = 1, 200 do spawn{ sprite = { size = 4 }, script = 'ticker' } end")
+        .unwrap();
+    harness.step_frames(3, DEFAULT_TEST_DT);
+
+    let counted: u32 = harness
+        .eval(
+            "local n = 0
+             for id = 1, 200 do
+                 if get_instance(id).frames == 2 then n = n + 1 end
+             end
+             return n",
+        )
+        .unwrap();
+    assert_eq!(counted, 200);
+    assert!(harness.errors().is_empty());
+}
+
+#[test]
+fn cached_functions_are_dropped_on_reload() {
+    let mut harness = ScriptHarness::empty().unwrap();
+    let runtime = harness.runtime_mut();
+    runtime
+        .load_script_content("counter", "function bump() count = 1 end")
+        .unwrap();
+    runtime.call_function("bump").unwrap();
+    runtime
+        .load_script_content("counter", "function bump() count = 2 end")
+        .unwrap();
+    runtime.call_function("bump").unwrap();
+    assert_eq!(harness.eval::<i32>("count").unwrap(), 2);
+}
+
+#[test]
+fn reload_skips_modules_that_were_never_loaded() {
+    let mut harness = ScriptHarness::new(scripts_dir()).unwrap();
+    let test_file = scripts_dir().join("tests").join("player_test.lua");
+    let runtime = harness.runtime_mut();
+    let name = runtime.module_name(&test_file).unwrap();
+    assert!(!runtime.reload_script(&name, &test_file).unwrap());
+    assert!(harness.errors().is_empty());
+}
+
+/// Writes `content` as module `name` in `dir` and hot-reloads it
+fn reload(
+    harness: &mut ScriptHarness,
+    dir: &Path,
+    name: &str,
+    content: &str,
+) -> mlua::Result<bool> {
+    let path = dir.join(name).with_extension("lua");
+    std::fs::write(&path, content).unwrap();
+    harness.runtime_mut().reload_script(name, &path)
+}
+
+#[test]
+fn persisted_state_survives_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("counter.lua"),
+        "State = persist('counter', { count = 0, step = 1 })
+         function bump() State.count = State.count + State.step end",
+    )
+    .unwrap();
+    let mut harness = ScriptHarness::new(dir.path()).unwrap();
+    harness.exec("bump() bump()").unwrap();
+
+    let reloaded = reload(
+        &mut harness,
+        dir.path(),
+        "counter",
+        "State = persist('counter', { count = 0, step = 1, bonus = 10 })
+         function bump() State.count = State.count + State.bonus end",
+    );
+    assert!(reloaded.unwrap());
+
+    // Existing fields keep their values and new defaults are filled in
+    assert_eq!(harness.eval::<i32>("State.count").unwrap(), 2);
+    assert_eq!(harness.eval::<i32>("State.bonus").unwrap(), 10);
+    harness.exec("bump()").unwrap();
+    assert_eq!(harness.eval::<i32>("State.count").unwrap(), 12);
+}
+
+#[test]
+fn reload_hooks_run_around_the_new_version() {
+    let version = |n: u32| {
+        format!(
+            "log = log or {{}}
+             on_before_reload(function(module) log[#log + 1] = 'before v{n} ' .. module end)
+             on_after_reload(function(module) log[#log + 1] = 'after v{n} ' .. module end)
+             log[#log + 1] = 'load v{n}'"
+        )
+    };
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("bars.lua"), version(1)).unwrap();
+    let mut harness = ScriptHarness::new(dir.path()).unwrap();
+
+    assert!(reload(&mut harness, dir.path(), "bars", &version(2)).unwrap());
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(log, "load v1,before v1 bars,load v2,after v2 bars");
+
+    // Only the hooks of the current version run
+    assert!(reload(&mut harness, dir.path(), "bars", &version(3)).unwrap());
+    let log: String = harness.eval("table.concat(log, ',')").unwrap();
+    assert_eq!(
+        log,
+        "load v1,before v1 bars,load v2,after v2 bars,before v2 bars,load v3,after v3 bars"
+    );
+}
+
+#[test]
+fn failed_reloads_keep_the_old_version_attached() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("ticker.lua"),
+        "calls = calls or {}
+         on('ping', function() calls[#calls + 1] = 'v1 handler' end)
+         register_system('tick', 'simulation', function() calls[#calls + 1] = 'v1 system' end)
+         on_before_reload(function() if not ready then error('not ready') end end)
+         function version() return 1 end",
+    )
+    .unwrap();
+    let mut harness = ScriptHarness::new(dir.path()).unwrap();
+    let calls = |harness: &ScriptHarness| -> String {
+        let calls = harness.eval("table.concat(calls, ',')").unwrap();
+        harness.exec("calls = {}").unwrap();
+        calls
+    };
+    let ping_and_step = |harness: &mut ScriptHarness| {
+        harness.exec("emit('ping')").unwrap();
+        harness.step(DEFAULT_TEST_DT);
+    };
+    let fixed = "calls = calls or {}
+                 on('ping', function() calls[#calls + 1] = 'v2 handler' end)
+                 register_system('tick', 'simulation', function() calls[#calls + 1] = 'v2 system' end)
+                 function version() return 2 end";
+
+    // A failing before hook cancels the reload
+    let err = reload(&mut harness, dir.path(), "ticker", fixed).unwrap_err();
+    assert!(err.to_string().contains("not ready"), "{}", err);
+    assert_eq!(harness.eval::<i32>("version()").unwrap(), 1);
+    ping_and_step(&mut harness);
+    assert_eq!(calls(&harness), "v1 handler,v1 system");
+
+    // A version that fails to load is detached again, with everything it registered
+    harness.exec("ready = true").unwrap();
+    let broken = "on('ping', function() calls[#calls + 1] = 'broken handler' end)
+                  register_system('tick', 'simulation', function() end)
+                  register_system('extra', 'simulation', function() end)
+                  error('broken')";
+    let err = reload(&mut harness, dir.path(), "ticker", broken).unwrap_err();
+    assert!(err.to_string().contains("broken"), "{}", err);
+    assert_eq!(harness.eval::<i32>("version()").unwrap(), 1);
+    let systems: Vec<_> = harness
+        .runtime()
+        .systems(ScriptStage::Simulation)
+        .unwrap()
+        .into_iter()
+        .map(|system| system.name)
+        .collect();
+    assert_eq!(systems, ["tick"]);
+    ping_and_step(&mut harness);
+    assert_eq!(calls(&harness), "v1 handler,v1 system");
+
+    // The old version's before hook still guards the next reload
+    assert!(reload(&mut harness, dir.path(), "ticker", fixed).unwrap());
+    assert_eq!(harness.eval::<i32>("version()").unwrap(), 2);
+    ping_and_step(&mut harness);
+    assert_eq!(calls(&harness), "v2 handler,v2 system");
+    assert!(harness.errors().is_empty());
+}
+
+#[test]
+fn scripts_that_fail_to_reload_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mover.lua");
+    std::fs::write(&path, "function mover_speed() return 1 end").unwrap();
+    let mut runtime = LuaRuntime::new().unwrap();
+    runtime.set_scripts_dir(dir.path().to_path_buf());
+    runtime.require("mover").unwrap();
+    let watcher = ScriptWatcher::new(dir.path().to_path_buf()).unwrap();
+
+    std::fs::write(&path, "function mover_speed() return end end").unwrap();
+    let mut errors = ScriptErrors::new(None);
+    for _ in 0..100 {
+        let reloaded = reload_changed_scripts(&watcher, &mut runtime, |script, e| {
+            errors.report_load(script, e)
+        });
+        assert!(reloaded.is_empty());
+        if !errors.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let error = errors.latest().expect("the failed reload is reported");
+    assert_eq!(error.script, "mover");
+    assert_eq!(error.callback, LOAD_CALLBACK);
+    assert_eq!(error.line, Some(1));
+    // The previous version keeps running
+    let speed: i32 = runtime.lua().load("mover_speed()").eval().unwrap();
+    assert_eq!(speed, 1);
+}
+
+#[test]
+fn profiling_times_systems_for_a_chrome_trace() {
+    let (mut harness, _) = harness_with_player();
+    harness.runtime().set_profiling(true);
+    harness.step_frames(10, DEFAULT_TEST_DT);
+
+    let mut profile = ScriptProfile::new();
+    harness.runtime().collect_profile(&mut profile);
+    let movement = profile.stats("player_movement").unwrap();
+    assert_eq!(movement.category, "system");
+    assert_eq!(movement.calls, 10);
+    assert!(movement.max_time >= movement.average_time());
+
+    let trace = profile.chrome_trace();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert!(events
+        .iter()
+        .any(|event| event["name"] == "player_movement" && event["ph"] == "X"));
+}
+
+#[test]
+fn sampling_counts_the_running_function() {
+    let harness = ScriptHarness::empty().unwrap();
+    harness.runtime().set_sampling(true);
+    harness
+        .exec(
+            "function busy()
+                 local total = 0
+                 for i = 1, 100000 do total = total + i end
+                 return total
+             end
+             busy()",
+        )
+        .unwrap();
+
+    let mut profile = ScriptProfile::new();
+    harness.runtime().collect_profile(&mut profile);
+    let samples = profile.samples();
+    assert!(
+        samples.iter().any(|(function, _)| function.starts_with("busy ")),
+        "{:?}",
+        samples
+    );
+}
+
+/// Minimal DAP client for driving the script debugger
+struct DapClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u64,
+    /// Events read while waiting for a response
+    events: Vec<Json>,
+}
+
+impl DapClient {
+    fn connect(port: u16) -> Self {
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Self {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            seq: 0,
+            events: Vec::new(),
+        }
+    }
+
+    fn read(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a request and returns the body of its successful response
+    fn request(&mut self, command: &str, arguments: Json) -> Json {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        loop {
+            let message = self.read();
+            if message["type"] == "event" {
+                self.events.push(message);
+            } else if message["request_seq"] == self.seq {
+                assert_eq!(message["success"], true, "{}", message);
+                return message["body"].clone();
+            }
+        }
+    }
+
+    fn wait_for_event(&mut self, event: &str) -> Json {
+        if let Some(index) = self.events.iter().position(|e| e["event"] == event) {
+            return self.events.remove(index);
+        }
+        loop {
+            let message = self.read();
+            if message["event"] == event {
+                return message;
+            }
+        }
+    }
+}
+
+#[test]
+fn debugger_needs_a_runtime_created_for_it() {
+    let (harness, _) = harness_with_player();
+    assert!(harness.runtime().start_debugger(0).is_err());
+    assert!(harness.eval::<bool>("debug == nil").unwrap());
+}
+
+/// A script for the debugger to stop in, and the line marked `-- breakpoint`
+fn debugger_fixture(dir: &Path) -> (PathBuf, usize) {
+    let source = "register_system('count', 'simulation', function(dt)
+    local step = 7
+    ticks = (ticks or 0) + step -- breakpoint
+end)
+";
+    let line = source
+        .lines()
+        .position(|line| line.ends_with("-- breakpoint"))
+        .unwrap()
+        + 1;
+    let path = dir.join("counter.lua");
+    std::fs::write(&path, source).unwrap();
+    (path, line)
+}
+
+#[test]
+fn debugger_stops_at_a_breakpoint_and_inspects_locals() {
+    let dir = tempfile::tempdir().unwrap();
+    let (script, line) = debugger_fixture(dir.path());
+    let limits = ScriptLimits {
+        debugger: true,
+        ..ScriptLimits::default()
+    };
+    let mut harness = ScriptHarness::with_limits(dir.path(), limits).expect("scripts load");
+    // Scripts still cannot reach the library the debugger uses
+    assert!(harness.eval::<bool>("debug == nil").unwrap());
+    let port = harness.runtime().start_debugger(0).unwrap();
+
+    // The client runs on its own thread: hitting the breakpoint blocks this one
+    let client = thread::spawn(move || {
+        let mut client = DapClient::connect(port);
+        client.request("initialize", json!({ "adapterID": "revgame" }));
+        client.wait_for_event("initialized");
+        let breakpoints = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": script }, "breakpoints": [{ "line": line }] }),
+        );
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+        client.request("configurationDone", json!({}));
+
+        let stopped = client.wait_for_event("stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+        let top = &trace["stackFrames"][0];
+        assert_eq!(top["line"], line);
+        assert!(top["source"]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("counter.lua"));
+
+        let scopes = client.request("scopes", json!({ "frameId": top["id"] }));
+        let locals = client.request(
+            "variables",
+            json!({ "variablesReference": scopes["scopes"][0]["variablesReference"] }),
+        );
+        let names: Vec<_> = locals["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| variable["name"].as_str().unwrap().to_string())
+            .collect();
+        assert!(names.contains(&"dt".to_string()), "{:?}", names);
+        assert!(names.contains(&"step".to_string()), "{:?}", names);
+
+        let evaluated = client.request(
+            "evaluate",
+            json!({ "expression": "step * 10", "frameId": top["id"] }),
+        );
+        let result = evaluated["result"].as_str().unwrap().to_string();
+
+        client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": script }, "breakpoints": [] }),
+        );
+        client.request("continue", json!({ "threadId": 1 }));
+        client.request("disconnect", json!({}));
+        result
+    });
+
+    for _ in 0..500 {
+        if client.is_finished() {
+            break;
+        }
+        harness.runtime().poll_debugger();
+        harness.step(DEFAULT_TEST_DT);
+        thread::sleep(Duration::from_millis(10));
+    }
+    let result = client.join().unwrap();
+    assert_eq!(result.parse::<f64>().unwrap(), 70.0);
+    // The stopped frame finished once the client continued
+    assert!(harness.eval::<i32>("ticks").unwrap() >= 7);
+    assert!(harness.errors().is_empty());
+}